@@ -6,6 +6,20 @@ mod m20250214_132032_create_table_exhibitors_children;
 mod m20250227_173624_create_table_forms;
 mod m20250227_191549_create_table_form_responses;
 mod m20250310_133355_create_table_revoked_refresh_tokens;
+mod m20250318_091524_create_table_refresh_token_families;
+mod m20250324_143010_create_table_password_reset_tokens;
+mod m20250327_102241_create_table_activation_codes;
+mod m20250401_093817_create_table_mail_outbox;
+mod m20250404_160522_create_table_auth_sessions;
+mod m20250408_101530_create_table_user_totp;
+mod m20250408_101612_create_table_totp_recovery_codes;
+mod m20250412_140207_alter_table_users_add_lockout;
+mod m20250412_140316_create_table_login_attempts;
+mod m20250416_110945_alter_table_refresh_token_families_add_client;
+mod m20250419_093102_create_index_revoked_refresh_tokens_exp;
+mod m20250423_101204_convert_form_choices_to_objects;
+mod m20250426_143518_create_table_stored_files;
+mod m20250428_101823_alter_table_mail_outbox_add_lease;
 
 pub struct Migrator;
 
@@ -19,6 +33,20 @@ impl MigratorTrait for Migrator {
             Box::new(m20250227_173624_create_table_forms::Migration),
             Box::new(m20250227_191549_create_table_form_responses::Migration),
             Box::new(m20250310_133355_create_table_revoked_refresh_tokens::Migration),
+            Box::new(m20250318_091524_create_table_refresh_token_families::Migration),
+            Box::new(m20250324_143010_create_table_password_reset_tokens::Migration),
+            Box::new(m20250327_102241_create_table_activation_codes::Migration),
+            Box::new(m20250401_093817_create_table_mail_outbox::Migration),
+            Box::new(m20250404_160522_create_table_auth_sessions::Migration),
+            Box::new(m20250408_101530_create_table_user_totp::Migration),
+            Box::new(m20250408_101612_create_table_totp_recovery_codes::Migration),
+            Box::new(m20250412_140207_alter_table_users_add_lockout::Migration),
+            Box::new(m20250412_140316_create_table_login_attempts::Migration),
+            Box::new(m20250416_110945_alter_table_refresh_token_families_add_client::Migration),
+            Box::new(m20250419_093102_create_index_revoked_refresh_tokens_exp::Migration),
+            Box::new(m20250423_101204_convert_form_choices_to_objects::Migration),
+            Box::new(m20250426_143518_create_table_stored_files::Migration),
+            Box::new(m20250428_101823_alter_table_mail_outbox_add_lease::Migration),
         ]
     }
 }
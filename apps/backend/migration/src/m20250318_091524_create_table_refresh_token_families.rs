@@ -0,0 +1,58 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TABLE refresh_token_families(
+                    family_id uuid PRIMARY KEY,
+                    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    user_id uuid NOT NULL REFERENCES users,
+                    current_jti uuid NOT NULL,
+                    revoked boolean NOT NULL DEFAULT false
+                );
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TRIGGER refresh_token_families_modtime
+                    BEFORE UPDATE ON refresh_token_families
+                    FOR EACH ROW
+                    EXECUTE PROCEDURE update_timestamp();
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TABLE refresh_token_families;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TABLE password_reset_tokens(
+                    token_hash TEXT PRIMARY KEY,
+                    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    user_id uuid NOT NULL REFERENCES users,
+                    expires_at timestamp with time zone NOT NULL,
+                    used_at timestamp with time zone
+                );
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TABLE password_reset_tokens;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
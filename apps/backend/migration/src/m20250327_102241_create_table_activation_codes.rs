@@ -0,0 +1,43 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TABLE activation_codes(
+                    user_id uuid PRIMARY KEY REFERENCES users DEFERRABLE INITIALLY DEFERRED,
+                    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    code_hash TEXT NOT NULL,
+                    expires_at timestamp with time zone NOT NULL
+                );
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TABLE activation_codes;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TYPE mail_status AS ENUM ('PENDING', 'SENT', 'FAILED');
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TABLE mail_outbox(
+                    id uuid PRIMARY KEY,
+                    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    to_address TEXT NOT NULL,
+                    subject TEXT NOT NULL,
+                    body TEXT NOT NULL,
+                    status mail_status NOT NULL DEFAULT 'PENDING',
+                    attempts integer NOT NULL DEFAULT 0,
+                    next_attempt_at timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                    last_error TEXT,
+                    sent_at timestamp with time zone
+                );
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE INDEX mail_outbox_pending_idx ON mail_outbox (next_attempt_at) WHERE status = 'PENDING';
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TRIGGER mail_outbox_modtime
+                    BEFORE UPDATE ON mail_outbox
+                    FOR EACH ROW
+                    EXECUTE PROCEDURE update_timestamp();
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TABLE mail_outbox;
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TYPE mail_status;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
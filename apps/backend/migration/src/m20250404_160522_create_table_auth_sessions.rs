@@ -0,0 +1,54 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TABLE auth_sessions(
+                    state_hash TEXT PRIMARY KEY,
+                    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    pkce_verifier TEXT NOT NULL,
+                    nonce TEXT NOT NULL,
+                    expires_at timestamp with time zone NOT NULL
+                );
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE INDEX auth_sessions_expires_at_idx ON auth_sessions (expires_at);
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TABLE auth_sessions;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TABLE user_totp(
+                    user_id uuid PRIMARY KEY REFERENCES users ON DELETE CASCADE,
+                    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    updated_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    secret TEXT NOT NULL,
+                    confirmed_at timestamp with time zone,
+                    last_used_step bigint
+                );
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TRIGGER user_totp_modtime
+                    BEFORE UPDATE ON user_totp
+                    FOR EACH ROW
+                    EXECUTE PROCEDURE update_timestamp();
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TABLE user_totp;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
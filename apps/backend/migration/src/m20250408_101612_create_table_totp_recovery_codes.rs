@@ -0,0 +1,54 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TABLE totp_recovery_codes(
+                    id uuid PRIMARY KEY,
+                    created_at timestamp with time zone DEFAULT CURRENT_TIMESTAMP,
+                    user_id uuid NOT NULL REFERENCES users ON DELETE CASCADE,
+                    code_hash TEXT NOT NULL,
+                    used_at timestamp with time zone
+                );
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE INDEX totp_recovery_codes_user_id_idx ON totp_recovery_codes (user_id);
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TABLE totp_recovery_codes;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                ALTER TABLE users
+                    ADD COLUMN failed_login_count integer NOT NULL DEFAULT 0,
+                    ADD COLUMN locked_until timestamp with time zone;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                ALTER TABLE users
+                    DROP COLUMN failed_login_count,
+                    DROP COLUMN locked_until;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TYPE login_outcome AS ENUM ('SUCCESS', 'MFA_REQUIRED', 'INVALID_PASSWORD', 'INVALID_TOTP', 'UNKNOWN_USER', 'NOT_ACTIVATED', 'LOCKED');
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TABLE login_attempts(
+                    id uuid PRIMARY KEY,
+                    created_at timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                    user_id uuid REFERENCES users ON DELETE CASCADE,
+                    m_address TEXT NOT NULL,
+                    ip TEXT NOT NULL,
+                    user_agent TEXT,
+                    outcome login_outcome NOT NULL
+                );
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE INDEX login_attempts_user_id_created_at_idx ON login_attempts (user_id, created_at);
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TABLE login_attempts;
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TYPE login_outcome;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                ALTER TABLE refresh_token_families
+                    ADD COLUMN last_used_at timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                    ADD COLUMN ip TEXT,
+                    ADD COLUMN user_agent TEXT;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                ALTER TABLE refresh_token_families
+                    DROP COLUMN last_used_at,
+                    DROP COLUMN ip,
+                    DROP COLUMN user_agent;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! ラジオボタンとチェックボックスの選択肢を文字列から`{ choice_id, label }`に変換する
+//!
+//! 回答は選択肢のインデックス(`answer_text`の場合は選択肢の文字列)から`choice_id`に変換する。
+use crate::sea_orm::{ConnectionTrait, Statement};
+use sea_orm_migration::prelude::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const RADIO_BUTTON: &str = "question_radio_button";
+const CHECK_BOX: &str = "question_check_box";
+
+/// 項目のIDと、質問の種類(`question_radio_button`か`question_check_box`)と選択肢(`choice_id`, `label`)の対応
+type ItemChoices = HashMap<String, (&'static str, Vec<(Value, Value)>)>;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let mut form_choices = HashMap::new();
+        for (form_id, mut items) in select_json(db, SELECT_FORMS).await? {
+            let mut item_choices = ItemChoices::new();
+            for (item_id, kind, question) in questions_mut(&mut items) {
+                let choices = question["choices"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|label| (json!(Uuid::new_v4()), label))
+                    .collect::<Vec<_>>();
+                question["choices"] = choices
+                    .iter()
+                    .map(|(choice_id, label)| json!({"choice_id": choice_id, "label": label}))
+                    .collect();
+                question["allow_other"] = json!(false);
+                item_choices.insert(item_id, (kind, choices));
+            }
+            update_json(db, UPDATE_FORM, &form_id, &items).await?;
+            form_choices.insert(form_id, item_choices);
+        }
+
+        for (ids, mut answers) in select_json(db, SELECT_RESPONSES).await? {
+            let (response_id, form_id) = ids.split_once(',').unwrap_or_default();
+            let (Some(item_choices), Some(answers_map)) =
+                (form_choices.get(form_id), answers.as_object_mut())
+            else {
+                continue;
+            };
+            for (item_id, answer) in answers_map.iter_mut() {
+                let Some((kind, choices)) = item_choices.get(item_id) else {
+                    continue;
+                };
+                let choice_id = |index: &Value| {
+                    index
+                        .as_u64()
+                        .and_then(|index| choices.get(index as usize))
+                        .map(|(choice_id, _)| choice_id.clone())
+                };
+                if let Some(radio_button) = answer.get_mut("answer_radio_button") {
+                    radio_button["choice"] = choice_id(&radio_button["choice"]).into();
+                } else if let Some(check_box) = answer.get_mut("answer_check_box") {
+                    check_box["choices"] = check_box["choices"]
+                        .as_array()
+                        .map(|indexes| indexes.iter().filter_map(choice_id).collect())
+                        .unwrap_or_default();
+                } else if let Some(text) = answer.get("answer_text") {
+                    // 型付きの回答が追加される前は選択肢の文字列をそのまま保存していた
+                    let Some((choice_id, _)) =
+                        choices.iter().find(|(_, label)| *label == text["value"])
+                    else {
+                        continue;
+                    };
+                    let item_id = answer["item_id"].clone();
+                    *answer = if *kind == RADIO_BUTTON {
+                        json!({"item_id": item_id, "answer_radio_button": {"choice": choice_id}})
+                    } else {
+                        json!({"item_id": item_id, "answer_check_box": {"choices": [choice_id]}})
+                    };
+                }
+            }
+            update_json(db, UPDATE_RESPONSE, response_id, &answers).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let mut form_choices = HashMap::new();
+        for (form_id, mut items) in select_json(db, SELECT_FORMS).await? {
+            let mut item_choices = ItemChoices::new();
+            for (item_id, kind, question) in questions_mut(&mut items) {
+                let choices = question["choices"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|choice| (choice["choice_id"].clone(), choice["label"].clone()))
+                    .collect::<Vec<_>>();
+                *question = json!({
+                    "choices": choices.iter().map(|(_, label)| label).collect::<Vec<_>>()
+                });
+                item_choices.insert(item_id, (kind, choices));
+            }
+            update_json(db, UPDATE_FORM, &form_id, &items).await?;
+            form_choices.insert(form_id, item_choices);
+        }
+
+        for (ids, mut answers) in select_json(db, SELECT_RESPONSES).await? {
+            let (response_id, form_id) = ids.split_once(',').unwrap_or_default();
+            let (Some(item_choices), Some(answers_map)) =
+                (form_choices.get(form_id), answers.as_object_mut())
+            else {
+                continue;
+            };
+            for (item_id, answer) in answers_map.iter_mut() {
+                let Some((_, choices)) = item_choices.get(item_id) else {
+                    continue;
+                };
+                let index = |choice_id: &Value| {
+                    choices
+                        .iter()
+                        .position(|(id, _)| id == choice_id)
+                        .map(|index| json!(index))
+                };
+                // 「その他」の回答は変換前の形式で表せないため失われる
+                if let Some(radio_button) = answer.get_mut("answer_radio_button") {
+                    *radio_button = json!({"choice": index(&radio_button["choice"])});
+                } else if let Some(check_box) = answer.get_mut("answer_check_box") {
+                    let indexes = check_box["choices"]
+                        .as_array()
+                        .map(|choice_ids| choice_ids.iter().filter_map(index).collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    *check_box = json!({"choices": indexes});
+                }
+            }
+            update_json(db, UPDATE_RESPONSE, response_id, &answers).await?;
+        }
+
+        Ok(())
+    }
+}
+
+const SELECT_FORMS: &str = "SELECT form_id::text, items::text FROM forms";
+const UPDATE_FORM: &str = "UPDATE forms SET items = $1::json WHERE form_id = $2::uuid";
+const SELECT_RESPONSES: &str =
+    "SELECT response_id::text || ',' || form_id::text, answers::text FROM form_responses";
+const UPDATE_RESPONSE: &str =
+    "UPDATE form_responses SET answers = $1::json WHERE response_id = $2::uuid";
+
+/// ラジオボタンとチェックボックスの質問を、項目のIDと質問の種類と共に列挙する
+fn questions_mut(items: &mut Value) -> Vec<(String, &'static str, &mut Value)> {
+    let Some(items) = items.as_array_mut() else {
+        return vec![];
+    };
+    items
+        .iter_mut()
+        .filter_map(|item| {
+            let item_id = item["item_id"].as_str()?.to_string();
+            let question = item.get_mut("item_question")?.get_mut("question")?;
+            let kind = [RADIO_BUTTON, CHECK_BOX]
+                .into_iter()
+                .find(|kind| question.get(*kind).is_some())?;
+            Some((item_id, kind, question.get_mut(kind)?))
+        })
+        .collect()
+}
+
+/// 1列目を文字列、2列目をJSONとして読み込む
+async fn select_json<C: ConnectionTrait>(db: &C, sql: &str) -> Result<Vec<(String, Value)>, DbErr> {
+    let rows = db
+        .query_all(Statement::from_string(db.get_database_backend(), sql))
+        .await?;
+    let mut values = vec![];
+    for row in rows {
+        let id: String = row.try_get_by_index(0)?;
+        let json: String = row.try_get_by_index(1)?;
+        let json = serde_json::from_str(&json).map_err(|err| DbErr::Custom(err.to_string()))?;
+        values.push((id, json));
+    }
+    Ok(values)
+}
+
+async fn update_json<C: ConnectionTrait>(
+    db: &C,
+    sql: &str,
+    id: &str,
+    json: &Value,
+) -> Result<(), DbErr> {
+    db.execute(Statement::from_sql_and_values(
+        db.get_database_backend(),
+        sql,
+        [json.to_string().into(), id.to_string().into()],
+    ))
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE TABLE stored_files(
+                    file_id uuid PRIMARY KEY,
+                    created_at timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                    exhibition_id TEXT NOT NULL REFERENCES exhibitors_root,
+                    uploader_id uuid NOT NULL REFERENCES users,
+                    file_name TEXT NOT NULL,
+                    content_type TEXT NOT NULL,
+                    size bigint NOT NULL
+                );
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                CREATE INDEX stored_files_exhibition_id_idx ON stored_files (exhibition_id);
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                DROP TABLE stored_files;
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use crate::sea_orm::Statement;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                ALTER TYPE mail_status ADD VALUE 'SENDING' AFTER 'PENDING';
+                "#
+                .trim(),
+            ))
+            .await?;
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                ALTER TABLE mail_outbox
+                    ADD COLUMN locked_until timestamp with time zone;
+                "#
+                .trim(),
+            ))
+            .await?;
+        // 送信済みのメールの本文にはアクティベーション等のリンクが含まれるため消去する
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                UPDATE mail_outbox SET body = '' WHERE status IN ('SENT', 'FAILED');
+                "#
+                .trim(),
+            ))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                manager.get_database_backend(),
+                r#"
+                ALTER TABLE mail_outbox
+                    DROP COLUMN locked_until;
+                "#
+                .trim(),
+            ))
+            .await?;
+        // enumの値は削除できないため型を作り直す
+        for sql in [
+            "ALTER TYPE mail_status RENAME TO mail_status_old",
+            "CREATE TYPE mail_status AS ENUM ('PENDING', 'SENT', 'FAILED')",
+            "DROP INDEX mail_outbox_pending_idx",
+            "ALTER TABLE mail_outbox ALTER COLUMN status DROP DEFAULT",
+            r#"
+            ALTER TABLE mail_outbox ALTER COLUMN status TYPE mail_status
+                USING (CASE status::text WHEN 'SENDING' THEN 'PENDING' ELSE status::text END)::mail_status
+            "#,
+            "ALTER TABLE mail_outbox ALTER COLUMN status SET DEFAULT 'PENDING'",
+            "CREATE INDEX mail_outbox_pending_idx ON mail_outbox (next_attempt_at) WHERE status = 'PENDING'",
+            "DROP TYPE mail_status_old",
+        ] {
+            manager
+                .get_connection()
+                .execute(Statement::from_string(
+                    manager.get_database_backend(),
+                    sql.trim(),
+                ))
+                .await?;
+        }
+
+        Ok(())
+    }
+}
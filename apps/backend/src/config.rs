@@ -1,8 +1,10 @@
+use crate::permissions::{self, Permission};
+use anyhow::anyhow;
 use confy::ConfyError;
-use jsonwebtoken::{DecodingKey, EncodingKey};
 use rand::distr::{Alphanumeric, SampleString};
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::collections::HashMap;
+use tracing::warn;
 use tracing_core::LevelFilter;
 
 pub fn init_config() -> Result<Config, ConfyError> {
@@ -14,6 +16,83 @@ pub struct Config {
     pub logging: Logging,
     pub web: Web,
     pub db: Db,
+    pub mail: Mail,
+    #[serde(default)]
+    pub scheduler: Scheduler,
+    #[serde(default)]
+    pub storage: Storage,
+}
+
+/// 定期実行する処理の設定
+/// * `purge_interval`: 期限切れのトークン等を削除する間隔(秒)。1秒未満の場合は1秒になる
+/// * `login_attempt_retention`: ログイン試行の記録を保持する期間(秒)
+/// * `mail_retention`: 送信済み・送信に失敗したメールの記録を保持する期間(秒)
+/// * `unreferenced_file_retention`: どの回答からも参照されていないアップロードされたファイルを保持する期間(秒)。
+///   アップロードしてから回答を送信するまでの時間より長くする
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Scheduler {
+    pub purge_interval: u64,
+    pub login_attempt_retention: i64,
+    pub mail_retention: i64,
+    pub unreferenced_file_retention: i64,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self {
+            purge_interval: 60 * 60,
+            login_attempt_retention: 60 * 60 * 24 * 90,
+            mail_retention: 60 * 60 * 24 * 30,
+            unreferenced_file_retention: 60 * 60 * 24,
+        }
+    }
+}
+
+/// アップロードされたファイルの保存の設定
+/// * `backend`: 保存先
+/// * `max_upload_size`: 一度にアップロードできるファイルの最大サイズ(バイト)。質問毎の上限はフォームで設定する
+/// * `exhibitor_quota`: 参加団体毎にアップロードできるファイルの合計サイズ(バイト)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Storage {
+    pub backend: StorageBackend,
+    pub max_upload_size: usize,
+    pub exhibitor_quota: i64,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Local {
+                path: "./storage".into(),
+            },
+            max_upload_size: 20 * 1024 * 1024,
+            exhibitor_quota: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// * `Local`: `path`のディレクトリに保存する
+/// * `S3`: S3互換のオブジェクトストレージに保存する
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum StorageBackend {
+    Local { path: String },
+    S3(S3),
+}
+
+/// S3互換のオブジェクトストレージの設定
+/// * `endpoint`: エンドポイントのURL(例: `https://s3.ap-northeast-1.amazonaws.com`)。バケットはパスで指定する
+/// * `bucket`: バケット名
+/// * `region`: 署名に使うリージョン
+/// * `access_key_id`, `secret_access_key`: アクセスキー
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct S3 {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -83,10 +162,32 @@ impl Default for Server {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Auth {
     pub password_salt: String,
-    pub activation_salt: String,
+    #[serde(default)]
+    pub activation: Activation,
     pub stretch_cost: u8,
-    pub jwt_secret_key_path: String,
-    pub jwt_public_key_path: String,
+    #[serde(default)]
+    pub password_hash: PasswordHash,
+    #[serde(default)]
+    pub password_reset: PasswordReset,
+    #[serde(default)]
+    pub admin_session: AdminSession,
+    #[serde(default)]
+    pub cookie: Cookie,
+    #[serde(default)]
+    pub lockout: Lockout,
+    #[serde(default)]
+    pub jwt: Option<Jwt>,
+    /// 非推奨。`jwt`が設定されていない場合は`kid`が`default`のRS256の鍵として扱う
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt_secret_key_path: Option<String>,
+    /// 非推奨。`jwt_secret_key_path`を参照
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt_public_key_path: Option<String>,
+    #[serde(default)]
+    pub token: Token,
+    /// 二要素認証のシークレットをDBに保存する際の暗号化に使う鍵。設定されていない場合は平文で保存する
+    #[serde(default)]
+    pub totp_encryption_key: Option<String>,
     pub keycloak: KeyCloak,
 }
 
@@ -95,30 +196,332 @@ impl Default for Auth {
         let mut rng = rand::rng();
         Self {
             password_salt: Alphanumeric.sample_string(&mut rng, 16),
-            activation_salt: Alphanumeric.sample_string(&mut rng, 16),
+            activation: Activation::default(),
             stretch_cost: 13,
-            jwt_secret_key_path: "./secret_key".parse().unwrap(),
-            jwt_public_key_path: "./public_key".parse().unwrap(),
+            password_hash: PasswordHash::default(),
+            password_reset: PasswordReset::default(),
+            admin_session: AdminSession::default(),
+            cookie: Cookie::default(),
+            lockout: Lockout::default(),
+            jwt: Some(Jwt::default()),
+            jwt_secret_key_path: None,
+            jwt_public_key_path: None,
+            token: Token::default(),
+            totp_encryption_key: Some(Alphanumeric.sample_string(&mut rng, 43)),
             keycloak: KeyCloak::default(),
         }
     }
 }
 
+/// 参加団体責任者のトークンの設定
+/// * `access_token_expire_time`: アクセストークンの有効期限(秒)
+/// * `refresh_token_expire_time`: リフレッシュトークンの有効期限(秒)
+/// * `refresh_token_expiry`: リフレッシュトークンの有効期限の起点
+/// * `issuer`: `iss`クレーム。省略した場合は`server.base_url`
+/// * `audience`: `aud`クレーム。省略した場合は`issuer`と同じ
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Token {
+    pub access_token_expire_time: i64,
+    pub refresh_token_expire_time: i64,
+    pub refresh_token_expiry: RefreshTokenExpiry,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+}
+
+impl Default for Token {
+    fn default() -> Self {
+        Self {
+            access_token_expire_time: 60 * 10,
+            refresh_token_expire_time: 60 * 60 * 24 * 30 * 6,
+            refresh_token_expiry: RefreshTokenExpiry::Sliding,
+            issuer: None,
+            audience: None,
+        }
+    }
+}
+
+/// * `Sliding`: リフレッシュする度に期限を延長する。使われ続ける限りログインが続く
+/// * `Absolute`: ログインした日時から数える。期限が来ると再ログインが必要になる
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RefreshTokenExpiry {
+    Sliding,
+    Absolute,
+}
+
 impl Auth {
-    pub(crate) fn get_jwt_encoding_key(&self) -> jsonwebtoken::errors::Result<EncodingKey> {
-        EncodingKey::from_rsa_pem(fs::read(&self.jwt_secret_key_path).unwrap().as_slice())
+    /// JWTの署名鍵の設定
+    ///
+    /// 非推奨の`jwt_secret_key_path`と`jwt_public_key_path`は`kid`が`default`の鍵に読み替える。
+    /// `jwt`と同時に設定されている場合はどちらを使うか判断できないためエラーにする。
+    pub fn jwt(&self) -> anyhow::Result<Jwt> {
+        let legacy = self.jwt_secret_key_path.is_some() || self.jwt_public_key_path.is_some();
+        match (&self.jwt, legacy) {
+            (Some(_), true) => Err(anyhow!(
+                "web.auth.jwt_secret_key_path and web.auth.jwt_public_key_path are deprecated \
+                 and cannot be used together with web.auth.jwt; move them to web.auth.jwt.keys"
+            )),
+            (Some(jwt), false) => Ok(jwt.clone()),
+            (None, true) => {
+                warn!(
+                    "web.auth.jwt_secret_key_path and web.auth.jwt_public_key_path are deprecated; \
+                     use web.auth.jwt.keys instead"
+                );
+                let default = Jwt::default();
+                Ok(Jwt {
+                    keys: vec![JwtKey {
+                        kid: default.signing_kid.clone(),
+                        algorithm: JwtAlgorithm::RS256,
+                        secret_key_path: Some(
+                            self.jwt_secret_key_path
+                                .clone()
+                                .unwrap_or_else(|| "./secret_key".to_string()),
+                        ),
+                        public_key_path: self
+                            .jwt_public_key_path
+                            .clone()
+                            .unwrap_or_else(|| "./public_key".to_string()),
+                    }],
+                    ..default
+                })
+            }
+            (None, false) => Ok(Jwt::default()),
+        }
+    }
+}
+
+/// JWTの署名鍵の設定
+/// * `signing_kid`: 署名に使う鍵の`kid`
+/// * `keys`: 検証に使う鍵。鍵を入れ替える際は、古い鍵で署名されたトークンが期限切れになるまで残しておく
+/// * `generate_missing_keys`: 署名に使う鍵のファイルが両方とも存在しない場合に起動時に鍵ペアを生成する。
+///   パスの誤りで鍵が入れ替わらないよう、初回の起動時など必要な場合のみ有効にする
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Jwt {
+    pub signing_kid: String,
+    pub keys: Vec<JwtKey>,
+    #[serde(default)]
+    pub generate_missing_keys: bool,
+}
+
+impl Default for Jwt {
+    fn default() -> Self {
+        Self {
+            signing_kid: "default".to_string(),
+            keys: vec![JwtKey {
+                kid: "default".to_string(),
+                algorithm: JwtAlgorithm::default(),
+                secret_key_path: Some("./secret_key".to_string()),
+                public_key_path: "./public_key".to_string(),
+            }],
+            generate_missing_keys: false,
+        }
     }
+}
 
-    pub(crate) fn get_jwt_decoding_key(&self) -> jsonwebtoken::errors::Result<DecodingKey> {
-        DecodingKey::from_rsa_pem(fs::read(&self.jwt_public_key_path).unwrap().as_slice())
+/// * `kid`: JWTのヘッダーとJWKSで鍵を識別するID
+/// * `algorithm`: 署名アルゴリズム
+/// * `secret_key_path`: 秘密鍵(PKCS#8のPEM)のパス。署名に使わない鍵では省略できる
+/// * `public_key_path`: 公開鍵(SPKIのPEM)のパス
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JwtKey {
+    pub kid: String,
+    #[serde(default)]
+    pub algorithm: JwtAlgorithm,
+    #[serde(default)]
+    pub secret_key_path: Option<String>,
+    pub public_key_path: String,
+}
+
+/// * `RS256`: RSA 2048bit + SHA-256
+/// * `ES256`: ECDSA P-256 + SHA-256
+/// * `EdDSA`: Ed25519
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum JwtAlgorithm {
+    #[default]
+    RS256,
+    ES256,
+    EdDSA,
+}
+
+/// パスワードハッシュの設定
+/// * `memory_cost`: KiB単位のメモリ使用量
+/// * `time_cost`: 反復回数
+/// * `parallelism`: 並列度
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordHash {
+    pub algorithm: PasswordHashAlgorithm,
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHash {
+    fn default() -> Self {
+        Self {
+            algorithm: PasswordHashAlgorithm::Argon2id,
+            memory_cost: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum PasswordHashAlgorithm {
+    Argon2id,
+}
+
+/// アカウント有効化の設定
+/// * `code_expire_time`: アクティベーションコードの有効期限(秒)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Activation {
+    pub code_expire_time: i64,
+}
+
+impl Default for Activation {
+    fn default() -> Self {
+        Self {
+            code_expire_time: 60 * 60 * 24 * 14,
+        }
+    }
+}
+
+/// パスワード再設定の設定
+/// * `token_expire_time`: 再設定用トークンの有効期限(秒)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordReset {
+    pub token_expire_time: i64,
+}
+
+impl Default for PasswordReset {
+    fn default() -> Self {
+        Self {
+            token_expire_time: 60 * 30,
+        }
+    }
+}
+
+/// ログインの失敗によるアカウントロックの設定
+/// * `threshold`: 連続して何回失敗したらロックするか
+/// * `base_lock_time`: 最初のロック時間(秒)。ロック後も失敗が続く場合は失敗する度に倍になる
+/// * `max_lock_time`: ロック時間の上限(秒)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Lockout {
+    pub threshold: i32,
+    pub base_lock_time: i64,
+    pub max_lock_time: i64,
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            base_lock_time: 60,
+            max_lock_time: 60 * 60,
+        }
+    }
+}
+
+/// 管理者のログイン(OIDC)中のセッションの設定
+/// * `store`: PKCEのverifierとnonceの保存先
+/// * `expire_time`: ログインを開始してから完了するまでの期限(秒)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdminSession {
+    pub store: AuthSessionStore,
+    pub expire_time: i64,
+}
+
+impl Default for AdminSession {
+    fn default() -> Self {
+        Self {
+            store: AuthSessionStore::Postgres,
+            expire_time: 60 * 10,
+        }
+    }
+}
+
+/// * `Postgres`: DBに保存する。再起動後も有効で、複数のインスタンスで共有される
+/// * `Memory`: メモリに保存する(開発用)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AuthSessionStore {
+    Postgres,
+    Memory,
+}
+
+/// 参加団体責任者のログインでトークンをCookieに保存するモードの設定
+/// * `enabled`: trueの場合、トークンをレスポンスボディではなくHttpOnlyのCookieで返す
+/// * `secure`: `Secure`属性を付けるか(httpで動かす開発環境以外ではtrueにする)
+/// * `same_site`: `SameSite`属性
+/// * `domain`: `Domain`属性。指定しない場合はリクエストしたホストのみに送られる
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cookie {
+    pub enabled: bool,
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub domain: Option<String>,
+}
+
+impl Default for Cookie {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            secure: true,
+            same_site: SameSite::Strict,
+            domain: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// * `access_token`: 管理者のアクセストークンの検証の設定
+/// * `roles`: ロール名(レルムロール、`id`のクライアントロール、グループ名)と付与する権限の対応
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KeyCloak {
     pub id: String,
     pub secret: String,
     pub issuer: String,
+    #[serde(default)]
+    pub access_token: AccessTokenValidation,
+    #[serde(default = "permissions::default_mapping")]
+    pub roles: HashMap<String, Vec<Permission>>,
+}
+
+impl Default for KeyCloak {
+    fn default() -> Self {
+        Self {
+            id: Default::default(),
+            secret: Default::default(),
+            issuer: Default::default(),
+            access_token: Default::default(),
+            roles: permissions::default_mapping(),
+        }
+    }
+}
+
+/// 管理者のアクセストークンはプロバイダのJWKSで署名を検証し、`iss`、`aud`(または`azp`)、`exp`を確認する
+/// * `audience`: `aud`に含まれている必要がある値。指定しない場合は`azp`が`id`(クライアントID)と一致することを確認する
+/// * `jwks_refresh_interval`: JWKSを再取得する間隔(秒)
+/// * `userinfo_fallback`: JWKSで検証できないトークン(JWTでない、署名鍵が見つからない等)をuserinfoエンドポイントへの問い合わせで検証する
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccessTokenValidation {
+    pub audience: Option<String>,
+    pub jwks_refresh_interval: u64,
+    pub userinfo_fallback: bool,
+}
+
+impl Default for AccessTokenValidation {
+    fn default() -> Self {
+        Self {
+            audience: None,
+            jwks_refresh_interval: 60 * 60,
+            userinfo_fallback: false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -140,3 +543,144 @@ impl Default for StaticFiles {
         }
     }
 }
+
+/// メール送信の設定
+/// * `from`: 送信元のメールアドレス
+/// * `locale`: メールの言語
+/// * `transport`: 送信手段。省略できない
+/// * `outbox`: 送信キューの設定
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mail {
+    pub from: String,
+    #[serde(default)]
+    pub locale: Locale,
+    pub transport: MailTransport,
+    #[serde(default)]
+    pub outbox: Outbox,
+}
+
+impl Default for Mail {
+    fn default() -> Self {
+        Self {
+            from: "noreply@koudaisai.jp".into(),
+            locale: Locale::default(),
+            transport: MailTransport::Maildir {
+                path: "./mails".into(),
+            },
+            outbox: Outbox::default(),
+        }
+    }
+}
+
+/// * `Ja`: 日本語
+/// * `En`: 英語
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    Ja,
+    En,
+}
+
+/// * `Smtp`: SMTPサーバーを通して送信する
+/// * `Maildir`: `path`のMaildirにRFC 5322形式で書き出す(開発用)
+/// * `File`: `path`のディレクトリにJSONとして書き出す(開発・テスト用)
+/// * `Stdout`: 宛先と件名のみをログに出力し、本文は出力しない(開発用)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum MailTransport {
+    Smtp(Smtp),
+    Maildir { path: String },
+    File { path: String },
+    Stdout,
+}
+
+/// 送信キュー(`mail_outbox`)の設定
+/// * `poll_interval`: 未送信のメールを確認する間隔(秒)
+/// * `batch_size`: 一度に送信するメールの最大件数
+/// * `max_attempts`: 送信を試みる最大回数。超えた場合は`FAILED`になる
+/// * `retry_interval`: 再送までの待ち時間(秒)。失敗する度に2倍になる
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Outbox {
+    pub poll_interval: u64,
+    pub batch_size: u64,
+    pub max_attempts: i32,
+    pub retry_interval: i64,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self {
+            poll_interval: 5,
+            batch_size: 20,
+            max_attempts: 8,
+            retry_interval: 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Smtp {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub starttls: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth(
+        jwt: Option<Jwt>,
+        jwt_secret_key_path: Option<&str>,
+        jwt_public_key_path: Option<&str>,
+    ) -> Auth {
+        Auth {
+            jwt,
+            jwt_secret_key_path: jwt_secret_key_path.map(str::to_string),
+            jwt_public_key_path: jwt_public_key_path.map(str::to_string),
+            ..Auth::default()
+        }
+    }
+
+    #[test]
+    fn maps_legacy_key_paths_to_default_kid() {
+        let jwt = auth(None, Some("/etc/portal/secret"), Some("/etc/portal/public"))
+            .jwt()
+            .unwrap();
+        assert_eq!(jwt.signing_kid, "default");
+        assert_eq!(jwt.keys.len(), 1);
+        assert_eq!(jwt.keys[0].kid, "default");
+        assert_eq!(jwt.keys[0].algorithm, JwtAlgorithm::RS256);
+        assert_eq!(
+            jwt.keys[0].secret_key_path.as_deref(),
+            Some("/etc/portal/secret")
+        );
+        assert_eq!(jwt.keys[0].public_key_path, "/etc/portal/public");
+        assert!(!jwt.generate_missing_keys);
+    }
+
+    #[test]
+    fn rejects_legacy_key_paths_with_jwt() {
+        assert!(auth(Some(Jwt::default()), Some("/etc/portal/secret"), None)
+            .jwt()
+            .is_err());
+    }
+
+    #[test]
+    fn uses_configured_jwt() {
+        let configured = Jwt {
+            signing_kid: "2025-04".to_string(),
+            ..Jwt::default()
+        };
+        let jwt = auth(Some(configured), None, None).jwt().unwrap();
+        assert_eq!(jwt.signing_kid, "2025-04");
+    }
+
+    #[test]
+    fn uses_default_jwt_without_any_setting() {
+        let jwt = auth(None, None, None).jwt().unwrap();
+        assert_eq!(jwt.signing_kid, "default");
+        assert_eq!(jwt.keys[0].public_key_path, "./public_key");
+    }
+}
@@ -34,6 +34,8 @@ pub enum Relation {
     ExhibitorsCategoryLabo,
     #[sea_orm(has_one = "super::exhibitors_category_stage::Entity")]
     ExhibitorsCategoryStage,
+    #[sea_orm(has_many = "super::stored_files::Entity")]
+    StoredFiles,
     #[sea_orm(
         belongs_to = "super::users::Entity",
         from = "Column::Representative1",
@@ -84,4 +86,10 @@ impl Related<super::exhibitors_category_stage::Entity> for Entity {
     }
 }
 
+impl Related<super::stored_files::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::StoredFiles.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
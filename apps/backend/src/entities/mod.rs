@@ -2,6 +2,8 @@
 
 pub mod prelude;
 
+pub mod activation_codes;
+pub mod auth_sessions;
 pub mod exhibitors_category_booth;
 pub mod exhibitors_category_general;
 pub mod exhibitors_category_labo;
@@ -9,6 +11,13 @@ pub mod exhibitors_category_stage;
 pub mod exhibitors_root;
 pub mod form_responses;
 pub mod forms;
+pub mod login_attempts;
+pub mod mail_outbox;
+pub mod password_reset_tokens;
+pub mod refresh_token_families;
 pub mod revoked_refresh_tokens;
 pub mod sea_orm_active_enums;
+pub mod stored_files;
+pub mod totp_recovery_codes;
+pub mod user_totp;
 pub mod users;
@@ -15,6 +15,36 @@ pub enum ExhibitionType {
     Labo,
 }
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "login_outcome")]
+pub enum LoginOutcome {
+    #[sea_orm(string_value = "SUCCESS")]
+    Success,
+    #[sea_orm(string_value = "MFA_REQUIRED")]
+    MfaRequired,
+    #[sea_orm(string_value = "INVALID_PASSWORD")]
+    InvalidPassword,
+    #[sea_orm(string_value = "INVALID_TOTP")]
+    InvalidTotp,
+    #[sea_orm(string_value = "UNKNOWN_USER")]
+    UnknownUser,
+    #[sea_orm(string_value = "NOT_ACTIVATED")]
+    NotActivated,
+    #[sea_orm(string_value = "LOCKED")]
+    Locked,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "mail_status")]
+pub enum MailStatus {
+    #[sea_orm(string_value = "PENDING")]
+    Pending,
+    #[sea_orm(string_value = "SENDING")]
+    Sending,
+    #[sea_orm(string_value = "SENT")]
+    Sent,
+    #[sea_orm(string_value = "FAILED")]
+    Failed,
+}
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(rs_type = "String", db_type = "Enum", enum_name = "stage_type")]
 pub enum StageType {
     #[sea_orm(string_value = "OUTDOOR")]
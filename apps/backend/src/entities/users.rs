@@ -20,10 +20,14 @@ pub struct Model {
     pub password_salt: String,
     #[sea_orm(column_type = "Text")]
     pub exhibition_id: String,
+    pub failed_login_count: i32,
+    pub locked_until: Option<DateTimeWithTimeZone>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_one = "super::activation_codes::Entity")]
+    ActivationCodes,
     #[sea_orm(
         belongs_to = "super::exhibitors_root::Entity",
         from = "Column::ExhibitionId",
@@ -34,6 +38,24 @@ pub enum Relation {
     ExhibitorsRoot,
     #[sea_orm(has_many = "super::form_responses::Entity")]
     FormResponses,
+    #[sea_orm(has_many = "super::login_attempts::Entity")]
+    LoginAttempts,
+    #[sea_orm(has_many = "super::password_reset_tokens::Entity")]
+    PasswordResetTokens,
+    #[sea_orm(has_many = "super::refresh_token_families::Entity")]
+    RefreshTokenFamilies,
+    #[sea_orm(has_many = "super::stored_files::Entity")]
+    StoredFiles,
+    #[sea_orm(has_many = "super::totp_recovery_codes::Entity")]
+    TotpRecoveryCodes,
+    #[sea_orm(has_one = "super::user_totp::Entity")]
+    UserTotp,
+}
+
+impl Related<super::activation_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ActivationCodes.def()
+    }
 }
 
 impl Related<super::exhibitors_root::Entity> for Entity {
@@ -48,4 +70,40 @@ impl Related<super::form_responses::Entity> for Entity {
     }
 }
 
+impl Related<super::login_attempts::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::LoginAttempts.def()
+    }
+}
+
+impl Related<super::password_reset_tokens::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PasswordResetTokens.def()
+    }
+}
+
+impl Related<super::refresh_token_families::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RefreshTokenFamilies.def()
+    }
+}
+
+impl Related<super::stored_files::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::StoredFiles.def()
+    }
+}
+
+impl Related<super::totp_recovery_codes::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TotpRecoveryCodes.def()
+    }
+}
+
+impl Related<super::user_totp::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UserTotp.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,370 @@
+use crate::entities::{exhibitors_root, users};
+use crate::middlewares::{Admin, CurrentUser};
+use crate::routes::AppState;
+use crate::util::jwt;
+use crate::util::AppError;
+use anyhow::anyhow;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use sea_orm::{DatabaseConnection, EntityTrait};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+use tracing::debug;
+
+/// ルート毎に要求する管理者の権限
+pub mod policy {
+    use crate::permissions::Permission;
+
+    pub trait Policy: Send + Sync + std::fmt::Debug {
+        const PERMISSION: Permission;
+    }
+
+    macro_rules! permission_policies {
+        ($($permission:ident),* $(,)?) => {
+            $(
+                #[doc = concat!("`Permission::", stringify!($permission), "`を要求する")]
+                #[derive(Debug)]
+                pub struct $permission;
+
+                impl Policy for $permission {
+                    const PERMISSION: Permission = Permission::$permission;
+                }
+            )*
+        };
+    }
+
+    permission_policies!(
+        FormsRead,
+        FormsWrite,
+        ResponsesRead,
+        ExhibitorsRead,
+        ExhibitorsWrite,
+        UsersRead,
+        UsersWrite,
+    );
+}
+
+/// 認証ミドルウェアが挿入した`CurrentUser`を取り出す
+fn current_user(parts: &Parts) -> Result<CurrentUser, AppError> {
+    parts
+        .extensions
+        .get::<CurrentUser>()
+        .cloned()
+        .ok_or_else(|| anyhow!("CurrentUser is not set. is the auth middleware applied?").into())
+}
+
+/// `P`の権限を持つ管理者であることを要求する。それ以外の場合は403
+///
+/// ```ignore
+/// async fn post_forms(_admin: RequireAdmin<policy::FormsWrite>, ...) -> AppResponse
+/// ```
+#[derive(Debug)]
+pub struct RequireAdmin<P: policy::Policy> {
+    pub admin: Admin,
+    _policy: PhantomData<P>,
+}
+
+impl<P: policy::Policy> Deref for RequireAdmin<P> {
+    type Target = Admin;
+
+    fn deref(&self) -> &Self::Target {
+        &self.admin
+    }
+}
+
+impl<P, S> FromRequestParts<S> for RequireAdmin<P>
+where
+    P: policy::Policy,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match current_user(parts)? {
+            CurrentUser::Admin(admin) if admin.has_permission(P::PERMISSION) => Ok(Self {
+                admin,
+                _policy: PhantomData,
+            }),
+            _ => Err(AppError::Forbidden),
+        }
+    }
+}
+
+/// 参加団体責任者であることを要求する。それ以外の場合は403
+///
+/// ユーザーとその参加団体をDBから読み込む。トークンが有効でもユーザーが削除されている場合は401、
+/// 参加団体が削除されている場合は403を返す。
+#[derive(Debug)]
+pub struct RequireExhibitorMember {
+    pub claims: jwt::Claims,
+    pub user: users::Model,
+    pub exhibitor: exhibitors_root::Model,
+}
+
+impl RequireExhibitorMember {
+    pub async fn load(db: &DatabaseConnection, claims: jwt::Claims) -> Result<Self, AppError> {
+        let Some(user) = users::Entity::find_by_id(claims.sub).one(db).await? else {
+            debug!("user {} doesn't exist", claims.sub);
+            return Err(AppError::Unauthorized("user no longer exists."));
+        };
+        let Some(exhibitor) = exhibitors_root::Entity::find_by_id(user.exhibition_id.clone())
+            .one(db)
+            .await?
+        else {
+            debug!("exhibitor {} doesn't exist", user.exhibition_id);
+            return Err(AppError::Forbidden);
+        };
+        Ok(Self {
+            claims,
+            user,
+            exhibitor,
+        })
+    }
+
+    /// 参加団体`exhibition_id`に属する場合true
+    pub fn belongs_to(&self, exhibition_id: &str) -> bool {
+        self.exhibitor.id == exhibition_id
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for RequireExhibitorMember {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        match current_user(parts)? {
+            CurrentUser::User(claims) => Self::load(&state.db_conn, claims).await,
+            _ => Err(AppError::Forbidden),
+        }
+    }
+}
+
+/// 管理者と参加団体責任者の両方がアクセスできるルートで使う
+///
+/// 参加団体責任者の場合は`RequireExhibitorMember`と同様にユーザーと参加団体を読み込む。
+#[derive(Debug)]
+pub enum Requester {
+    Admin(Box<Admin>),
+    ExhibitorMember(Box<RequireExhibitorMember>),
+    Anonymous,
+}
+
+impl Requester {
+    /// 管理者の場合は`P`の権限を要求する。参加団体責任者と未ログインの場合は何もしない
+    pub fn require_admin_policy<P: policy::Policy>(&self) -> Result<(), AppError> {
+        match self {
+            Requester::Admin(admin) if !admin.has_permission(P::PERMISSION) => {
+                Err(AppError::Forbidden)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for Requester {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(match current_user(parts)? {
+            CurrentUser::Admin(admin) => Requester::Admin(Box::new(admin)),
+            CurrentUser::User(claims) => Requester::ExhibitorMember(Box::new(
+                RequireExhibitorMember::load(&state.db_conn, claims).await?,
+            )),
+            CurrentUser::None => Requester::Anonymous,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::sea_orm_active_enums::ExhibitionType;
+    use crate::permissions::Permission;
+    use crate::util::oidc::AdminClaims;
+    use http::{Request, StatusCode};
+    use sea_orm::{
+        Database, DatabaseBackend, DbErr, EntityTrait, IdenStatic, Iterable, ModelTrait,
+        ProxyDatabaseTrait, ProxyExecResult, ProxyRow, Statement,
+    };
+    use std::collections::{BTreeMap, HashSet, VecDeque};
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    /// クエリ毎に、積んだ順に結果の行を返すDB
+    #[derive(Debug, Default)]
+    struct QueuedRows(Mutex<VecDeque<Vec<ProxyRow>>>);
+
+    #[async_trait::async_trait]
+    impl ProxyDatabaseTrait for QueuedRows {
+        async fn query(&self, _statement: Statement) -> Result<Vec<ProxyRow>, DbErr> {
+            Ok(self.0.lock().unwrap().pop_front().unwrap_or_default())
+        }
+
+        async fn execute(&self, _statement: Statement) -> Result<ProxyExecResult, DbErr> {
+            Ok(ProxyExecResult::default())
+        }
+    }
+
+    fn row<M: ModelTrait>(model: &M) -> ProxyRow {
+        <M::Entity as EntityTrait>::Column::iter()
+            .map(|column| (column.as_str().to_string(), model.get(column)))
+            .collect::<BTreeMap<_, _>>()
+            .into()
+    }
+
+    async fn connect(results: Vec<Vec<ProxyRow>>) -> DatabaseConnection {
+        let rows = QueuedRows(Mutex::new(results.into()));
+        Database::connect_proxy(DatabaseBackend::Postgres, Arc::new(Box::new(rows)))
+            .await
+            .unwrap()
+    }
+
+    fn claims(sub: Uuid) -> jwt::Claims {
+        jwt::Claims {
+            iss: "https://portal.example.com".to_string(),
+            aud: "https://portal.example.com".to_string(),
+            sub,
+            exp: 0,
+            iat: 0,
+            typ: jwt::Type::AccessToken,
+            jti: Uuid::new_v4(),
+            fam: Uuid::new_v4(),
+        }
+    }
+
+    fn user(id: Uuid) -> users::Model {
+        users::Model {
+            id,
+            created_at: None,
+            updated_at: None,
+            first_name: "太郎".to_string(),
+            last_name: "工大".to_string(),
+            m_address: "taro@example.com".to_string(),
+            password_hash: None,
+            password_salt: String::new(),
+            exhibition_id: "booth-1".to_string(),
+            failed_login_count: 0,
+            locked_until: None,
+        }
+    }
+
+    fn exhibitor() -> exhibitors_root::Model {
+        exhibitors_root::Model {
+            id: "booth-1".to_string(),
+            created_at: None,
+            updated_at: None,
+            exhibitor_name: "工大祭実行委員会".to_string(),
+            r#type: ExhibitionType::Booth,
+            exhibition_name: None,
+            icon_id: None,
+            description: None,
+            representative1: None,
+            representative2: None,
+            representative3: None,
+        }
+    }
+
+    fn parts(current_user: Option<CurrentUser>) -> Parts {
+        let (mut parts, _) = Request::new(()).into_parts();
+        if let Some(current_user) = current_user {
+            parts.extensions.insert(current_user);
+        }
+        parts
+    }
+
+    fn admin(permissions: &[Permission]) -> Admin {
+        Admin {
+            claims: AdminClaims {
+                iss: "https://keycloak.example.com/realms/portal".to_string(),
+                sub: "admin".to_string(),
+                preferred_username: None,
+                email: None,
+                name: None,
+                roles: Default::default(),
+            },
+            permissions: permissions.iter().copied().collect::<HashSet<_>>(),
+        }
+    }
+
+    #[tokio::test]
+    async fn load_returns_user_and_exhibitor() {
+        let id = Uuid::new_v4();
+        let db = connect(vec![vec![row(&user(id))], vec![row(&exhibitor())]]).await;
+        let member = RequireExhibitorMember::load(&db, claims(id)).await.unwrap();
+        assert_eq!(member.user.id, id);
+        assert!(member.belongs_to("booth-1"));
+        assert!(!member.belongs_to("booth-2"));
+    }
+
+    #[tokio::test]
+    async fn load_rejects_deleted_user_with_401() {
+        let db = connect(vec![vec![]]).await;
+        let err = RequireExhibitorMember::load(&db, claims(Uuid::new_v4()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn load_rejects_deleted_exhibitor_with_403() {
+        let id = Uuid::new_v4();
+        let db = connect(vec![vec![row(&user(id))], vec![]]).await;
+        let err = RequireExhibitorMember::load(&db, claims(id))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn require_admin_accepts_admin_with_permission() {
+        let mut parts = parts(Some(CurrentUser::Admin(admin(&[Permission::FormsWrite]))));
+        assert!(
+            RequireAdmin::<policy::FormsWrite>::from_request_parts(&mut parts, &())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn require_admin_rejects_others_with_403() {
+        for current_user in [
+            CurrentUser::Admin(admin(&[Permission::FormsRead])),
+            CurrentUser::User(claims(Uuid::new_v4())),
+            CurrentUser::None,
+        ] {
+            let mut parts = parts(Some(current_user));
+            let err = RequireAdmin::<policy::FormsWrite>::from_request_parts(&mut parts, &())
+                .await
+                .unwrap_err();
+            assert_eq!(err.status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_auth_middleware_is_internal_error() {
+        let mut parts = parts(None);
+        let err = RequireAdmin::<policy::FormsWrite>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn requester_requires_policy_only_for_admin() {
+        assert!(Requester::Admin(Box::new(admin(&[Permission::FormsRead])))
+            .require_admin_policy::<policy::FormsRead>()
+            .is_ok());
+        assert!(Requester::Admin(Box::new(admin(&[])))
+            .require_admin_policy::<policy::FormsRead>()
+            .is_err());
+        assert!(Requester::Anonymous
+            .require_admin_policy::<policy::FormsRead>()
+            .is_ok());
+    }
+}
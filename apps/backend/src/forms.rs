@@ -1,5 +1,6 @@
 pub mod question;
 pub mod responses;
+pub mod validation;
 
 use chrono::{DateTime, Utc};
 use question::Question;
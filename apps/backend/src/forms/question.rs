@@ -1,7 +1,9 @@
+use chrono::{DateTime, FixedOffset, NaiveDate};
 use serde::de::{MapAccess, Visitor};
 use serde::ser::SerializeMap;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::Formatter;
+use uuid::Uuid;
 
 /// フォームの質問
 /// * `required` - 回答必須かどうか
@@ -18,27 +20,114 @@ pub enum Questions {
     Text(QuestionText),
     RadioButton(QuestionRadioButton),
     CheckBox(QuestionCheckBox),
+    Number(QuestionNumber),
+    Date(QuestionDate),
+    DateTime(QuestionDateTime),
+    Dropdown(QuestionDropdown),
+    File(QuestionFile),
 }
 
 /// テキスト
 /// * `paragraph` - trueの場合複数行にわたるテキスト。falseの場合一行の回答。
+/// * `format` - 回答の形式の制約
 #[derive(Serialize, Deserialize, Debug)]
 pub struct QuestionText {
     pub paragraph: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<TextFormat>,
+}
+
+/// テキストの回答の形式
+#[derive(Serialize, Deserialize, Debug)]
+pub enum TextFormat {
+    Email,
+    Url,
+    /// 電話番号。数字と`+`、`-`、括弧、空白のみ
+    Phone,
+    /// 回答全体が`pattern`に一致する
+    Regex {
+        pattern: String,
+    },
 }
 
 /// ラジオボタン
 /// * `choices` - 選択肢
+/// * `allow_other` - trueの場合「その他」として自由記述で回答できる
 #[derive(Serialize, Deserialize, Debug)]
 pub struct QuestionRadioButton {
-    pub choices: Vec<String>,
+    pub choices: Vec<Choice>,
+    #[serde(default)]
+    pub allow_other: bool,
 }
 
 /// チェックボックス
 /// * `choices` - 選択肢
+/// * `allow_other` - trueの場合「その他」として自由記述で回答できる
 #[derive(Serialize, Deserialize, Debug)]
 pub struct QuestionCheckBox {
-    pub choices: Vec<String>,
+    pub choices: Vec<Choice>,
+    #[serde(default)]
+    pub allow_other: bool,
+}
+
+/// 数値
+/// * `min` - 最小値
+/// * `max` - 最大値
+/// * `step` - 回答は`min`(省略した場合は0)から`step`の倍数だけ離れた値のみ
+/// * `unit` - 回答者に表示される単位(例: 人)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QuestionNumber {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub step: Option<f64>,
+    pub unit: Option<String>,
+}
+
+/// 日付
+/// * `min` - 回答できる最初の日付(例: 工大祭の初日)
+/// * `max` - 回答できる最後の日付(例: 工大祭の最終日)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QuestionDate {
+    pub min: Option<NaiveDate>,
+    pub max: Option<NaiveDate>,
+}
+
+/// 日時
+/// * `min` - 回答できる最初の日時
+/// * `max` - 回答できる最後の日時
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QuestionDateTime {
+    pub min: Option<DateTime<FixedOffset>>,
+    pub max: Option<DateTime<FixedOffset>>,
+}
+
+/// ドロップダウン
+/// * `choices` - 選択肢
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QuestionDropdown {
+    pub choices: Vec<Choice>,
+}
+
+/// ファイルのアップロード
+/// * `allowed_mime_types` - アップロードできるファイルの種類(例: `application/pdf`、`image/*`)。空の場合は制限しない
+/// * `max_size` - 1ファイルの最大サイズ(バイト)
+/// * `max_count` - 最大のファイル数
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QuestionFile {
+    pub allowed_mime_types: Vec<String>,
+    pub max_size: i64,
+    pub max_count: usize,
+}
+
+/// ラジオボタン、チェックボックス、ドロップダウンの選択肢
+/// * `choice_id` - 選択肢のID。回答はIDで選択肢を参照するため、`label`を変更しても回答は保たれる。
+///   省略した場合は生成される
+/// * `label` - 表示される選択肢
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Choice {
+    #[serde(default = "Uuid::new_v4")]
+    pub choice_id: Uuid,
+    pub label: String,
 }
 
 impl Serialize for Question {
@@ -58,6 +147,21 @@ impl Serialize for Question {
             Questions::CheckBox(question_check_box) => {
                 map.serialize_entry("question_check_box", &question_check_box)?;
             }
+            Questions::Number(question_number) => {
+                map.serialize_entry("question_number", &question_number)?;
+            }
+            Questions::Date(question_date) => {
+                map.serialize_entry("question_date", &question_date)?;
+            }
+            Questions::DateTime(question_date_time) => {
+                map.serialize_entry("question_date_time", &question_date_time)?;
+            }
+            Questions::Dropdown(question_dropdown) => {
+                map.serialize_entry("question_dropdown", &question_dropdown)?;
+            }
+            Questions::File(question_file) => {
+                map.serialize_entry("question_file", &question_file)?;
+            }
         }
         map.end()
     }
@@ -113,6 +217,36 @@ impl<'de> Visitor<'de> for QuestionVisitor {
                     }
                     question = Some(Questions::CheckBox(map.next_value()?));
                 }
+                "question_number" => {
+                    if question.is_some() {
+                        return Err(de::Error::duplicate_field("question"));
+                    }
+                    question = Some(Questions::Number(map.next_value()?));
+                }
+                "question_date" => {
+                    if question.is_some() {
+                        return Err(de::Error::duplicate_field("question"));
+                    }
+                    question = Some(Questions::Date(map.next_value()?));
+                }
+                "question_date_time" => {
+                    if question.is_some() {
+                        return Err(de::Error::duplicate_field("question"));
+                    }
+                    question = Some(Questions::DateTime(map.next_value()?));
+                }
+                "question_dropdown" => {
+                    if question.is_some() {
+                        return Err(de::Error::duplicate_field("question"));
+                    }
+                    question = Some(Questions::Dropdown(map.next_value()?));
+                }
+                "question_file" => {
+                    if question.is_some() {
+                        return Err(de::Error::duplicate_field("question"));
+                    }
+                    question = Some(Questions::File(map.next_value()?));
+                }
                 unknown => {
                     return Err(de::Error::unknown_field(
                         unknown,
@@ -121,6 +255,11 @@ impl<'de> Visitor<'de> for QuestionVisitor {
                             "question_text",
                             "question_radio_button",
                             "question_check_box",
+                            "question_number",
+                            "question_date",
+                            "question_date_time",
+                            "question_dropdown",
+                            "question_file",
                         ],
                     ))
                 }
@@ -1,4 +1,4 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
 use serde::de::{MapAccess, Visitor};
 use serde::ser::SerializeMap;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
@@ -24,6 +24,13 @@ pub struct FormResponse {
     pub answers: HashMap<Uuid, Answer>,
 }
 
+/// 回答の作成・更新時のリクエスト
+/// * `answers`: 質問に対する回答(item_idをキーとする)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResponseInput {
+    pub answers: HashMap<String, Answer>,
+}
+
 /// 質問に対する回答
 /// * `item_id`: 質問の回答
 /// * `answer`: 回答の種類と詳細な情報
@@ -37,6 +44,13 @@ pub struct Answer {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Answers {
     Text(AnswerText),
+    RadioButton(AnswerRadioButton),
+    CheckBox(AnswerCheckBox),
+    Number(AnswerNumber),
+    Date(AnswerDate),
+    DateTime(AnswerDateTime),
+    Dropdown(AnswerDropdown),
+    File(AnswerFile),
 }
 
 /// 質問に対する回答をテキストで表したもの
@@ -48,6 +62,62 @@ pub struct AnswerText {
     pub value: String,
 }
 
+/// ラジオボタンの回答
+/// * `choice`: 選択した選択肢の`choice_id`。「その他」を回答した場合は`None`
+/// * `other`: 「その他」に入力したテキスト
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswerRadioButton {
+    #[serde(default)]
+    pub choice: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other: Option<String>,
+}
+
+/// チェックボックスの回答
+/// * `choices`: 選択した選択肢の`choice_id`
+/// * `other`: 「その他」に入力したテキスト
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswerCheckBox {
+    pub choices: Vec<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other: Option<String>,
+}
+
+/// 数値の回答
+/// * `value`: 回答の値
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswerNumber {
+    pub value: f64,
+}
+
+/// 日付の回答
+/// * `value`: 回答の日付(`YYYY-MM-DD`)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswerDate {
+    pub value: NaiveDate,
+}
+
+/// 日時の回答
+/// * `value`: 回答の日時(RFC 3339)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswerDateTime {
+    pub value: DateTime<FixedOffset>,
+}
+
+/// ドロップダウンの回答
+/// * `choice`: 選択した選択肢の`choice_id`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswerDropdown {
+    pub choice: Uuid,
+}
+
+/// ファイルのアップロードの回答
+/// * `file_ids`: `/api/v1/files`でアップロードしたファイルのID
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswerFile {
+    pub file_ids: Vec<Uuid>,
+}
+
 impl Serialize for Answer {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -59,6 +129,27 @@ impl Serialize for Answer {
             Answers::Text(answer_text) => {
                 map.serialize_entry("answer_text", &answer_text)?;
             }
+            Answers::RadioButton(answer_radio_button) => {
+                map.serialize_entry("answer_radio_button", &answer_radio_button)?;
+            }
+            Answers::CheckBox(answer_check_box) => {
+                map.serialize_entry("answer_check_box", &answer_check_box)?;
+            }
+            Answers::Number(answer_number) => {
+                map.serialize_entry("answer_number", &answer_number)?;
+            }
+            Answers::Date(answer_date) => {
+                map.serialize_entry("answer_date", &answer_date)?;
+            }
+            Answers::DateTime(answer_date_time) => {
+                map.serialize_entry("answer_date_time", &answer_date_time)?;
+            }
+            Answers::Dropdown(answer_dropdown) => {
+                map.serialize_entry("answer_dropdown", &answer_dropdown)?;
+            }
+            Answers::File(answer_file) => {
+                map.serialize_entry("answer_file", &answer_file)?;
+            }
         }
         map.end()
     }
@@ -102,10 +193,62 @@ impl<'de> Visitor<'de> for AnswerVisitor {
                     }
                     answer = Some(Answers::Text(map.next_value()?));
                 }
+                "answer_radio_button" => {
+                    if answer.is_some() {
+                        return Err(de::Error::duplicate_field("answer"));
+                    }
+                    answer = Some(Answers::RadioButton(map.next_value()?));
+                }
+                "answer_check_box" => {
+                    if answer.is_some() {
+                        return Err(de::Error::duplicate_field("answer"));
+                    }
+                    answer = Some(Answers::CheckBox(map.next_value()?));
+                }
+                "answer_number" => {
+                    if answer.is_some() {
+                        return Err(de::Error::duplicate_field("answer"));
+                    }
+                    answer = Some(Answers::Number(map.next_value()?));
+                }
+                "answer_date" => {
+                    if answer.is_some() {
+                        return Err(de::Error::duplicate_field("answer"));
+                    }
+                    answer = Some(Answers::Date(map.next_value()?));
+                }
+                "answer_date_time" => {
+                    if answer.is_some() {
+                        return Err(de::Error::duplicate_field("answer"));
+                    }
+                    answer = Some(Answers::DateTime(map.next_value()?));
+                }
+                "answer_dropdown" => {
+                    if answer.is_some() {
+                        return Err(de::Error::duplicate_field("answer"));
+                    }
+                    answer = Some(Answers::Dropdown(map.next_value()?));
+                }
+                "answer_file" => {
+                    if answer.is_some() {
+                        return Err(de::Error::duplicate_field("answer"));
+                    }
+                    answer = Some(Answers::File(map.next_value()?));
+                }
                 unknown => {
                     return Err(de::Error::unknown_field(
                         unknown,
-                        &["item_id", "answer_text"],
+                        &[
+                            "item_id",
+                            "answer_text",
+                            "answer_radio_button",
+                            "answer_check_box",
+                            "answer_number",
+                            "answer_date",
+                            "answer_date_time",
+                            "answer_dropdown",
+                            "answer_file",
+                        ],
                     ))
                 }
             }
@@ -116,6 +259,20 @@ impl<'de> Visitor<'de> for AnswerVisitor {
     }
 }
 
+impl ResponseInput {
+    /// 回答で参照しているファイルのID
+    pub fn file_ids(&self) -> Vec<Uuid> {
+        self.answers
+            .values()
+            .filter_map(|answer| match &answer.answer {
+                Answers::File(answer_file) => Some(answer_file.file_ids.iter().copied()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+}
+
 impl FormResponse {
     pub fn from_model(model: &crate::entities::form_responses::Model) -> anyhow::Result<Self> {
         let response_id = model.response_id;
@@ -0,0 +1,875 @@
+use super::question::{Choice, Questions, TextFormat};
+use super::responses::{Answers, ResponseInput};
+use super::{Form, Item, Items};
+use crate::entities::stored_files;
+use lettre::Address;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use url::Url;
+use uuid::Uuid;
+
+/// `step`の倍数かどうかを判定する際の誤差の許容範囲
+const STEP_TOLERANCE: f64 = 1e-9;
+
+/// 電話番号の桁数
+const PHONE_DIGITS: RangeInclusive<usize> = 10..=15;
+
+/// 回答の検証エラー
+/// * `item_id`: エラーのある項目のID(`answers`のキー)
+/// * `message`: エラーの内容
+#[derive(Debug, PartialEq)]
+pub struct AnswerError {
+    pub item_id: String,
+    pub message: &'static str,
+}
+
+impl AnswerError {
+    fn new(item_id: impl Into<String>, message: &'static str) -> Self {
+        Self {
+            item_id: item_id.into(),
+            message,
+        }
+    }
+}
+
+/// 項目の定義の検証エラー
+/// * `item_id`: エラーのある項目のID
+/// * `message`: エラーの内容
+#[derive(Debug, PartialEq)]
+pub struct ItemError {
+    pub item_id: Uuid,
+    pub message: &'static str,
+}
+
+/// フォームの項目の定義を検証する
+///
+/// 回答時に初めて失敗しないよう、テキストの`format`の正規表現がコンパイルできることを確認する。
+pub fn validate_items(items: &[Item]) -> Result<(), Vec<ItemError>> {
+    let errors = items
+        .iter()
+        .filter_map(|item| match &item.item {
+            Items::Question(item_question) => match &item_question.question.question {
+                Questions::Text(question) => match &question.format {
+                    Some(TextFormat::Regex { pattern }) if compile_pattern(pattern).is_err() => {
+                        Some(ItemError {
+                            item_id: item.item_id,
+                            message: "format pattern is invalid.",
+                        })
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// 回答全体が`pattern`に一致するか判定する正規表現
+fn compile_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+/// 回答をフォームの定義に対して検証する
+///
+/// 以下を確認し、問題のある項目毎にエラーを返す。
+/// * 回答した項目がフォームに存在する質問であること
+/// * `required`の質問に回答していること
+/// * 回答の種類が質問の種類と対応していること
+/// * 選択肢の回答が`choices`に含まれていること
+/// * 数値と日付・日時が範囲内であること、テキストが`format`に一致すること
+/// * ファイルが存在し、種類・サイズ・数が質問の制限を満たすこと
+///
+/// `files`は回答者が参照できるファイル(回答者の参加団体がアップロードしたファイル)。
+pub fn validate_response(
+    form: &Form,
+    input: &ResponseInput,
+    files: &HashMap<Uuid, stored_files::Model>,
+) -> Result<(), Vec<AnswerError>> {
+    let mut errors = vec![];
+    let mut answered = HashSet::new();
+
+    let mut keys = input.answers.keys().collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        let answer = &input.answers[key];
+        let Ok(item_id) = Uuid::from_str(key) else {
+            errors.push(AnswerError::new(key, "item does not exist."));
+            continue;
+        };
+        if answer.item_id != item_id {
+            errors.push(AnswerError::new(key, "item_id does not match the key."));
+            continue;
+        }
+        let Some(item) = form.items.iter().find(|item| item.item_id == item_id) else {
+            errors.push(AnswerError::new(key, "item does not exist."));
+            continue;
+        };
+        let Items::Question(item_question) = &item.item else {
+            errors.push(AnswerError::new(key, "item is not a question."));
+            continue;
+        };
+        if let Err(message) =
+            validate_answer(&item_question.question.question, &answer.answer, files)
+        {
+            errors.push(AnswerError::new(key, message));
+            continue;
+        }
+        if !is_empty(&answer.answer) {
+            answered.insert(item_id);
+        }
+    }
+
+    for item in &form.items {
+        if let Items::Question(item_question) = &item.item {
+            if item_question.question.required && !answered.contains(&item.item_id) {
+                errors.push(AnswerError::new(
+                    item.item_id.to_string(),
+                    "answer is required.",
+                ));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// 回答の種類と値が質問に対応しているか確認する
+fn validate_answer(
+    question: &Questions,
+    answer: &Answers,
+    files: &HashMap<Uuid, stored_files::Model>,
+) -> Result<(), &'static str> {
+    match (question, answer) {
+        (Questions::Text(question), Answers::Text(answer)) => {
+            if !question.paragraph && answer.value.contains('\n') {
+                return Err("answer must be a single line.");
+            }
+            match &question.format {
+                Some(format) if !answer.value.is_empty() => {
+                    validate_text_format(format, &answer.value)
+                }
+                _ => Ok(()),
+            }
+        }
+        (Questions::RadioButton(question), Answers::RadioButton(answer)) => {
+            if answer.choice.is_some() && answer.other.is_some() {
+                return Err("either choice or other must be answered.");
+            }
+            if answer.other.is_some() && !question.allow_other {
+                return Err("other is not allowed.");
+            }
+            if let Some(choice) = answer.choice {
+                if !has_choice(&question.choices, choice) {
+                    return Err("answer is not in choices.");
+                }
+            }
+            Ok(())
+        }
+        (Questions::CheckBox(question), Answers::CheckBox(answer)) => {
+            if answer.other.is_some() && !question.allow_other {
+                return Err("other is not allowed.");
+            }
+            if !answer
+                .choices
+                .iter()
+                .all(|choice| has_choice(&question.choices, *choice))
+            {
+                return Err("answer is not in choices.");
+            }
+            if answer.choices.iter().collect::<HashSet<_>>().len() != answer.choices.len() {
+                return Err("choices must not be duplicated.");
+            }
+            Ok(())
+        }
+        (Questions::Number(question), Answers::Number(answer)) => {
+            let value = answer.value;
+            if !value.is_finite() {
+                return Err("answer must be a finite number.");
+            }
+            if question.min.is_some_and(|min| value < min)
+                || question.max.is_some_and(|max| value > max)
+            {
+                return Err("answer is out of range.");
+            }
+            if let Some(step) = question.step.filter(|step| *step > 0.0) {
+                let steps = (value - question.min.unwrap_or(0.0)) / step;
+                if (steps - steps.round()).abs() > STEP_TOLERANCE {
+                    return Err("answer does not match the step.");
+                }
+            }
+            Ok(())
+        }
+        (Questions::Date(question), Answers::Date(answer)) => {
+            if question.min.is_some_and(|min| answer.value < min)
+                || question.max.is_some_and(|max| answer.value > max)
+            {
+                return Err("answer is out of range.");
+            }
+            Ok(())
+        }
+        (Questions::DateTime(question), Answers::DateTime(answer)) => {
+            if question.min.is_some_and(|min| answer.value < min)
+                || question.max.is_some_and(|max| answer.value > max)
+            {
+                return Err("answer is out of range.");
+            }
+            Ok(())
+        }
+        (Questions::Dropdown(question), Answers::Dropdown(answer)) => {
+            if !has_choice(&question.choices, answer.choice) {
+                return Err("answer is not in choices.");
+            }
+            Ok(())
+        }
+        (Questions::File(question), Answers::File(answer)) => {
+            if answer.file_ids.len() > question.max_count {
+                return Err("too many files.");
+            }
+            if answer.file_ids.iter().collect::<HashSet<_>>().len() != answer.file_ids.len() {
+                return Err("files must not be duplicated.");
+            }
+            for file_id in &answer.file_ids {
+                let Some(file) = files.get(file_id) else {
+                    return Err("file does not exist.");
+                };
+                if file.size > question.max_size {
+                    return Err("file is too large.");
+                }
+                if !question.allowed_mime_types.is_empty()
+                    && !question
+                        .allowed_mime_types
+                        .iter()
+                        .any(|allowed| matches_mime_type(allowed, &file.content_type))
+                {
+                    return Err("file type is not allowed.");
+                }
+            }
+            Ok(())
+        }
+        _ => Err("answer type does not match the question."),
+    }
+}
+
+fn validate_text_format(format: &TextFormat, value: &str) -> Result<(), &'static str> {
+    match format {
+        TextFormat::Email => Address::from_str(value)
+            .map(|_| ())
+            .map_err(|_| "answer must be an email address."),
+        TextFormat::Url => match Url::parse(value) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+            _ => Err("answer must be a url."),
+        },
+        TextFormat::Phone => {
+            let digits = value.chars().filter(char::is_ascii_digit).count();
+            if PHONE_DIGITS.contains(&digits)
+                && value
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || "+-() ".contains(c))
+            {
+                Ok(())
+            } else {
+                Err("answer must be a phone number.")
+            }
+        }
+        TextFormat::Regex { pattern } => {
+            let regex = compile_pattern(pattern).map_err(|_| "format pattern is invalid.")?;
+            if regex.is_match(value) {
+                Ok(())
+            } else {
+                Err("answer does not match the pattern.")
+            }
+        }
+    }
+}
+
+/// `allowed`は`type/subtype`か`type/*`
+fn matches_mime_type(allowed: &str, content_type: &str) -> bool {
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let allowed = allowed.trim().to_ascii_lowercase();
+    match allowed.strip_suffix("/*") {
+        Some(prefix) => content_type
+            .split_once('/')
+            .is_some_and(|(r#type, _)| r#type == prefix),
+        None => content_type == allowed,
+    }
+}
+
+fn has_choice(choices: &[Choice], choice_id: Uuid) -> bool {
+    choices.iter().any(|choice| choice.choice_id == choice_id)
+}
+
+/// 空の回答は未回答として扱う
+fn is_empty(answer: &Answers) -> bool {
+    let is_blank =
+        |other: &Option<String>| other.as_ref().is_none_or(|other| other.trim().is_empty());
+    match answer {
+        Answers::Text(answer) => answer.value.trim().is_empty(),
+        Answers::RadioButton(answer) => answer.choice.is_none() && is_blank(&answer.other),
+        Answers::CheckBox(answer) => answer.choices.is_empty() && is_blank(&answer.other),
+        Answers::File(answer) => answer.file_ids.is_empty(),
+        Answers::Number(_) | Answers::Date(_) | Answers::DateTime(_) | Answers::Dropdown(_) => {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forms::question::Question;
+    use crate::forms::responses::Answer;
+    use serde_json::{json, Value};
+
+    const TEXT: Uuid = Uuid::from_u128(1);
+    const RADIO: Uuid = Uuid::from_u128(2);
+    const PAGE_BREAK: Uuid = Uuid::from_u128(3);
+    const CHOICE_A: Uuid = Uuid::from_u128(0xa);
+    const CHOICE_B: Uuid = Uuid::from_u128(0xb);
+    const PDF: Uuid = Uuid::from_u128(0xf1);
+    const PNG: Uuid = Uuid::from_u128(0xf2);
+
+    fn item(item_id: Uuid, item: Value) -> Value {
+        let mut value = json!({ "item_id": item_id, "title": "", "description": "" });
+        value
+            .as_object_mut()
+            .unwrap()
+            .extend(item.as_object().unwrap().clone());
+        value
+    }
+
+    /// 必須のテキスト、任意のラジオボタン、改ページからなるフォーム
+    fn form() -> Form {
+        serde_json::from_value(json!({
+            "form_id": Uuid::nil(),
+            "created_at": "2025-01-01T00:00:00Z",
+            "updated_at": "2025-01-01T00:00:00Z",
+            "info": { "title": "", "document_title": "", "description": "" },
+            "items": [
+                item(TEXT, json!({ "item_question": { "question": {
+                    "required": true,
+                    "question_text": { "paragraph": false }
+                }}})),
+                item(RADIO, json!({ "item_question": { "question": {
+                    "required": false,
+                    "question_radio_button": {
+                        "choices": [{ "choice_id": CHOICE_A, "label": "A" }]
+                    }
+                }}})),
+                item(PAGE_BREAK, json!({ "item_page_break": {} })),
+            ],
+            "access_control": { "roles": [] },
+        }))
+        .unwrap()
+    }
+
+    fn validate(answers: Value) -> Vec<AnswerError> {
+        let input: ResponseInput = serde_json::from_value(json!({ "answers": answers })).unwrap();
+        validate_response(&form(), &input, &HashMap::new())
+            .err()
+            .unwrap_or_default()
+    }
+
+    fn question(question: Value) -> Questions {
+        let mut value = json!({ "required": false });
+        value
+            .as_object_mut()
+            .unwrap()
+            .extend(question.as_object().unwrap().clone());
+        serde_json::from_value::<Question>(value).unwrap().question
+    }
+
+    fn answer(answer: Value) -> Answers {
+        let mut value = json!({ "item_id": Uuid::nil() });
+        value
+            .as_object_mut()
+            .unwrap()
+            .extend(answer.as_object().unwrap().clone());
+        serde_json::from_value::<Answer>(value).unwrap().answer
+    }
+
+    fn file(file_id: Uuid, content_type: &str, size: i64) -> (Uuid, stored_files::Model) {
+        (
+            file_id,
+            stored_files::Model {
+                file_id,
+                created_at: Default::default(),
+                exhibition_id: "booth-1".to_string(),
+                uploader_id: Uuid::nil(),
+                file_name: "file".to_string(),
+                content_type: content_type.to_string(),
+                size,
+            },
+        )
+    }
+
+    fn check(question_json: Value, answer_json: Value) -> Result<(), &'static str> {
+        let files = HashMap::from([
+            file(PDF, "application/pdf", 100),
+            file(PNG, "image/png", 10),
+        ]);
+        validate_answer(&question(question_json), &answer(answer_json), &files)
+    }
+
+    fn text_answer(item_id: Uuid, value: &str) -> Value {
+        json!({ "item_id": item_id, "answer_text": { "value": value } })
+    }
+
+    #[test]
+    fn accepts_valid_response() {
+        assert_eq!(
+            validate(json!({
+                TEXT.to_string(): text_answer(TEXT, "hello"),
+                RADIO.to_string(): {
+                    "item_id": RADIO,
+                    "answer_radio_button": { "choice": CHOICE_A }
+                },
+            })),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_item_ids() {
+        let unknown = Uuid::from_u128(99);
+        assert_eq!(
+            validate(json!({
+                TEXT.to_string(): text_answer(TEXT, "hello"),
+                "not-a-uuid": text_answer(TEXT, "hello"),
+                unknown.to_string(): text_answer(unknown, "hello"),
+            })),
+            vec![
+                AnswerError::new(unknown.to_string(), "item does not exist."),
+                AnswerError::new("not-a-uuid", "item does not exist."),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_item_id_not_matching_key() {
+        assert_eq!(
+            validate(json!({
+                TEXT.to_string(): text_answer(TEXT, "hello"),
+                RADIO.to_string(): text_answer(TEXT, "hello"),
+            })),
+            vec![AnswerError::new(
+                RADIO.to_string(),
+                "item_id does not match the key."
+            )]
+        );
+    }
+
+    #[test]
+    fn rejects_answers_to_non_question_items() {
+        assert_eq!(
+            validate(json!({
+                TEXT.to_string(): text_answer(TEXT, "hello"),
+                PAGE_BREAK.to_string(): text_answer(PAGE_BREAK, "hello"),
+            })),
+            vec![AnswerError::new(
+                PAGE_BREAK.to_string(),
+                "item is not a question."
+            )]
+        );
+    }
+
+    #[test]
+    fn requires_answers_to_required_questions() {
+        let required = vec![AnswerError::new(TEXT.to_string(), "answer is required.")];
+        assert_eq!(validate(json!({})), required);
+        // 空白のみの回答は未回答として扱う
+        assert_eq!(
+            validate(json!({ TEXT.to_string(): text_answer(TEXT, "  ") })),
+            required
+        );
+    }
+
+    #[test]
+    fn accepts_blank_answers_to_optional_questions() {
+        assert_eq!(
+            validate(json!({
+                TEXT.to_string(): text_answer(TEXT, "hello"),
+                RADIO.to_string(): { "item_id": RADIO, "answer_radio_button": {} },
+            })),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn rejects_answer_type_not_matching_question() {
+        assert_eq!(
+            validate(json!({
+                TEXT.to_string(): { "item_id": TEXT, "answer_number": { "value": 1.0 } },
+            })),
+            vec![
+                AnswerError::new(TEXT.to_string(), "answer type does not match the question."),
+                AnswerError::new(TEXT.to_string(), "answer is required."),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_multiline_answer_to_single_line_text() {
+        let single_line = json!({ "question_text": { "paragraph": false } });
+        let paragraph = json!({ "question_text": { "paragraph": true } });
+        let multiline = json!({ "answer_text": { "value": "a\nb" } });
+        assert_eq!(
+            check(single_line, multiline.clone()),
+            Err("answer must be a single line.")
+        );
+        assert_eq!(check(paragraph, multiline), Ok(()));
+    }
+
+    #[test]
+    fn validates_radio_button_choices() {
+        let radio = |allow_other: bool| {
+            json!({ "question_radio_button": {
+                "choices": [{ "choice_id": CHOICE_A, "label": "A" }],
+                "allow_other": allow_other,
+            }})
+        };
+        let answer = |choice: Option<Uuid>, other: Option<&str>| json!({ "answer_radio_button": { "choice": choice, "other": other } });
+        assert_eq!(check(radio(false), answer(Some(CHOICE_A), None)), Ok(()));
+        assert_eq!(check(radio(true), answer(None, Some("other"))), Ok(()));
+        assert_eq!(
+            check(radio(true), answer(Some(CHOICE_A), Some("other"))),
+            Err("either choice or other must be answered.")
+        );
+        assert_eq!(
+            check(radio(false), answer(None, Some("other"))),
+            Err("other is not allowed.")
+        );
+        assert_eq!(
+            check(radio(false), answer(Some(CHOICE_B), None)),
+            Err("answer is not in choices.")
+        );
+    }
+
+    #[test]
+    fn validates_check_box_choices() {
+        let check_box = json!({ "question_check_box": {
+            "choices": [
+                { "choice_id": CHOICE_A, "label": "A" },
+                { "choice_id": CHOICE_B, "label": "B" },
+            ],
+        }});
+        let answer = |choices: &[Uuid], other: Option<&str>| json!({ "answer_check_box": { "choices": choices, "other": other } });
+        assert_eq!(
+            check(check_box.clone(), answer(&[CHOICE_A, CHOICE_B], None)),
+            Ok(())
+        );
+        assert_eq!(
+            check(check_box.clone(), answer(&[], Some("other"))),
+            Err("other is not allowed.")
+        );
+        assert_eq!(
+            check(check_box.clone(), answer(&[Uuid::from_u128(0xc)], None)),
+            Err("answer is not in choices.")
+        );
+        assert_eq!(
+            check(check_box, answer(&[CHOICE_A, CHOICE_A], None)),
+            Err("choices must not be duplicated.")
+        );
+    }
+
+    #[test]
+    fn validates_dropdown_choice() {
+        let dropdown = json!({ "question_dropdown": {
+            "choices": [{ "choice_id": CHOICE_A, "label": "A" }],
+        }});
+        assert_eq!(
+            check(
+                dropdown.clone(),
+                json!({ "answer_dropdown": { "choice": CHOICE_A } })
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check(
+                dropdown,
+                json!({ "answer_dropdown": { "choice": CHOICE_B } })
+            ),
+            Err("answer is not in choices.")
+        );
+    }
+
+    #[test]
+    fn validates_files() {
+        let question = |allowed_mime_types: &[&str]| {
+            json!({ "question_file": {
+                "allowed_mime_types": allowed_mime_types,
+                "max_size": 100,
+                "max_count": 2,
+            }})
+        };
+        let answer = |file_ids: &[Uuid]| json!({ "answer_file": { "file_ids": file_ids } });
+        assert_eq!(check(question(&[]), answer(&[PDF, PNG])), Ok(()));
+        assert_eq!(
+            check(
+                question(&["application/pdf", "image/*"]),
+                answer(&[PDF, PNG])
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check(question(&[]), answer(&[PDF, PNG, PNG])),
+            Err("too many files.")
+        );
+        assert_eq!(
+            check(question(&[]), answer(&[PNG, PNG])),
+            Err("files must not be duplicated.")
+        );
+        assert_eq!(
+            check(question(&[]), answer(&[Uuid::from_u128(0xf3)])),
+            Err("file does not exist.")
+        );
+        assert_eq!(
+            check(question(&["image/*"]), answer(&[PDF])),
+            Err("file type is not allowed.")
+        );
+    }
+
+    #[test]
+    fn rejects_files_over_max_size() {
+        let question = json!({ "question_file": {
+            "allowed_mime_types": [],
+            "max_size": 99,
+            "max_count": 1,
+        }});
+        assert_eq!(
+            check(question, json!({ "answer_file": { "file_ids": [PDF] } })),
+            Err("file is too large.")
+        );
+    }
+
+    #[test]
+    fn validates_email_format() {
+        let email = TextFormat::Email;
+        assert_eq!(validate_text_format(&email, "taro@example.com"), Ok(()));
+        for value in ["taro", "taro@", "@example.com", "taro@example.com\n"] {
+            assert_eq!(
+                validate_text_format(&email, value),
+                Err("answer must be an email address.")
+            );
+        }
+    }
+
+    #[test]
+    fn validates_url_format() {
+        let url = TextFormat::Url;
+        assert_eq!(
+            validate_text_format(&url, "https://example.com/a?b=c"),
+            Ok(())
+        );
+        assert_eq!(validate_text_format(&url, "http://example.com"), Ok(()));
+        for value in ["example.com", "ftp://example.com", "javascript:alert(1)"] {
+            assert_eq!(
+                validate_text_format(&url, value),
+                Err("answer must be a url.")
+            );
+        }
+    }
+
+    #[test]
+    fn validates_phone_format() {
+        let phone = TextFormat::Phone;
+        for value in [
+            "0312345678",
+            "03-1234-5678",
+            "+81 (3) 1234-5678",
+            "090-1234-5678",
+        ] {
+            assert_eq!(validate_text_format(&phone, value), Ok(()));
+        }
+        // 桁数が足りない、多すぎる、数字と記号以外を含む
+        for value in [
+            "123-4567",
+            "1234567890123456",
+            "03-1234-567x",
+            "03.1234.5678",
+        ] {
+            assert_eq!(
+                validate_text_format(&phone, value),
+                Err("answer must be a phone number.")
+            );
+        }
+    }
+
+    #[test]
+    fn regex_format_matches_whole_answer() {
+        let regex = TextFormat::Regex {
+            pattern: "[A-Z]{2}|[0-9]+".to_string(),
+        };
+        assert_eq!(validate_text_format(&regex, "AB"), Ok(()));
+        assert_eq!(validate_text_format(&regex, "123"), Ok(()));
+        // 部分一致では受け付けない
+        for value in ["ABC", "xAB", "12a"] {
+            assert_eq!(
+                validate_text_format(&regex, value),
+                Err("answer does not match the pattern.")
+            );
+        }
+        let invalid = TextFormat::Regex {
+            pattern: "(".to_string(),
+        };
+        assert_eq!(
+            validate_text_format(&invalid, "("),
+            Err("format pattern is invalid.")
+        );
+    }
+
+    #[test]
+    fn skips_format_for_blank_text() {
+        let question = json!({ "question_text": { "paragraph": false, "format": "Email" } });
+        assert_eq!(
+            check(question.clone(), json!({ "answer_text": { "value": "" } })),
+            Ok(())
+        );
+        assert_eq!(
+            check(question, json!({ "answer_text": { "value": "taro" } })),
+            Err("answer must be an email address.")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_regex_in_items() {
+        let text = |item_id: Uuid, pattern: &str| {
+            item(
+                item_id,
+                json!({ "item_question": { "question": {
+                    "required": false,
+                    "question_text": {
+                        "paragraph": false,
+                        "format": { "Regex": { "pattern": pattern } }
+                    }
+                }}}),
+            )
+        };
+        let items: Vec<Item> = serde_json::from_value(json!([
+            text(TEXT, "[0-9]+"),
+            text(RADIO, "(unclosed"),
+            item(PAGE_BREAK, json!({ "item_page_break": {} })),
+        ]))
+        .unwrap();
+        assert_eq!(
+            validate_items(&items),
+            Err(vec![ItemError {
+                item_id: RADIO,
+                message: "format pattern is invalid."
+            }])
+        );
+        assert_eq!(validate_items(&form().items), Ok(()));
+    }
+
+    #[test]
+    fn validates_number_range_and_step() {
+        let number = json!({ "question_number": { "min": 1.0, "max": 2.0, "step": 0.1 } });
+        let answer = |value: f64| json!({ "answer_number": { "value": value } });
+        // 0.1刻みの値は浮動小数点の誤差があっても受け付ける
+        for value in [1.0, 1.1, 1.3, 1.7, 0.1 + 0.2 + 0.8, 2.0] {
+            assert_eq!(check(number.clone(), answer(value)), Ok(()), "{}", value);
+        }
+        assert_eq!(
+            check(number.clone(), answer(1.05)),
+            Err("answer does not match the step.")
+        );
+        assert_eq!(
+            check(number.clone(), answer(1.0 + STEP_TOLERANCE * 1e3)),
+            Err("answer does not match the step.")
+        );
+        for value in [0.9, 2.1] {
+            assert_eq!(
+                check(number.clone(), answer(value)),
+                Err("answer is out of range.")
+            );
+        }
+    }
+
+    #[test]
+    fn step_is_counted_from_zero_without_min() {
+        let number = json!({ "question_number": { "step": 5.0 } });
+        let answer = |value: f64| json!({ "answer_number": { "value": value } });
+        assert_eq!(check(number.clone(), answer(-10.0)), Ok(()));
+        assert_eq!(check(number.clone(), answer(15.0)), Ok(()));
+        assert_eq!(
+            check(number, answer(12.0)),
+            Err("answer does not match the step.")
+        );
+    }
+
+    #[test]
+    fn validates_date_bounds_inclusively() {
+        let date = json!({ "question_date": { "min": "2025-11-22", "max": "2025-11-24" } });
+        let answer = |value: &str| json!({ "answer_date": { "value": value } });
+        for value in ["2025-11-22", "2025-11-23", "2025-11-24"] {
+            assert_eq!(check(date.clone(), answer(value)), Ok(()));
+        }
+        for value in ["2025-11-21", "2025-11-25"] {
+            assert_eq!(
+                check(date.clone(), answer(value)),
+                Err("answer is out of range.")
+            );
+        }
+    }
+
+    #[test]
+    fn validates_date_time_bounds_across_offsets() {
+        let date_time = json!({ "question_date_time": {
+            "min": "2025-11-22T09:00:00+09:00",
+            "max": "2025-11-22T17:00:00+09:00",
+        }});
+        let answer = |value: &str| json!({ "answer_date_time": { "value": value } });
+        // 同じ時刻はオフセットが異なっても範囲内
+        for value in [
+            "2025-11-22T09:00:00+09:00",
+            "2025-11-22T00:00:00Z",
+            "2025-11-22T08:00:00Z",
+        ] {
+            assert_eq!(check(date_time.clone(), answer(value)), Ok(()));
+        }
+        for value in ["2025-11-22T08:59:59+09:00", "2025-11-22T08:00:01Z"] {
+            assert_eq!(
+                check(date_time.clone(), answer(value)),
+                Err("answer is out of range.")
+            );
+        }
+    }
+
+    #[test]
+    fn matches_exact_mime_type() {
+        assert!(matches_mime_type("application/pdf", "application/pdf"));
+        assert!(!matches_mime_type("application/pdf", "application/json"));
+        assert!(!matches_mime_type("application/pdf", "application/pdfx"));
+    }
+
+    #[test]
+    fn matches_mime_type_ignoring_case_and_parameters() {
+        assert!(matches_mime_type("text/plain", "Text/Plain; charset=UTF-8"));
+        assert!(matches_mime_type(" Application/PDF ", "application/pdf"));
+    }
+
+    #[test]
+    fn matches_wildcard_subtype() {
+        assert!(matches_mime_type("image/*", "image/png"));
+        assert!(matches_mime_type("image/*", "IMAGE/JPEG; q=1"));
+        assert!(!matches_mime_type("image/*", "imagex/png"));
+        assert!(!matches_mime_type("image/*", "application/image"));
+        assert!(!matches_mime_type("image/*", "image"));
+    }
+}
@@ -0,0 +1,86 @@
+pub mod outbox;
+pub mod template;
+pub mod transport;
+
+use crate::config;
+use anyhow::Result;
+use async_trait::async_trait;
+use sea_orm::ConnectionTrait;
+use serde::{Deserialize, Serialize};
+use template::Template;
+use tracing::warn;
+use transport::{FileTransport, MaildirTransport, SmtpTransport, StdoutTransport};
+use uuid::Uuid;
+
+/// 送信するメール
+/// * `to`: 宛先のメールアドレス
+/// * `subject`: 件名
+/// * `body`: 本文(プレーンテキスト)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// メールの送信手段
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, from: &str, mail: &Mail) -> Result<()>;
+}
+
+pub struct Mailer {
+    from: String,
+    locale: config::Locale,
+    transport: Box<dyn MailTransport>,
+    outbox: config::Outbox,
+}
+
+impl Mailer {
+    pub fn new(
+        from: impl ToString,
+        locale: config::Locale,
+        transport: Box<dyn MailTransport>,
+        outbox: config::Outbox,
+    ) -> Self {
+        Self {
+            from: from.to_string(),
+            locale,
+            transport,
+            outbox,
+        }
+    }
+
+    pub fn from_config(mail: &config::Mail) -> Result<Self> {
+        let transport: Box<dyn MailTransport> = match &mail.transport {
+            config::MailTransport::Smtp(smtp) => Box::new(SmtpTransport::new(smtp)?),
+            config::MailTransport::Maildir { path } => Box::new(MaildirTransport::new(path)),
+            config::MailTransport::File { path } => Box::new(FileTransport::new(path)),
+            config::MailTransport::Stdout => {
+                warn!("mail.transport is Stdout; mails are not delivered");
+                Box::new(StdoutTransport)
+            }
+        };
+        Ok(Self::new(
+            &mail.from,
+            mail.locale,
+            transport,
+            mail.outbox.clone(),
+        ))
+    }
+
+    /// 送信キューを通さずに直接送信する
+    pub async fn send(&self, mail: &Mail) -> Result<()> {
+        self.transport.send(&self.from, mail).await
+    }
+
+    /// テンプレートから設定された言語でメールを生成し、送信キューに追加する
+    pub async fn enqueue<C: ConnectionTrait>(
+        &self,
+        db: &C,
+        to: impl ToString,
+        template: Template,
+    ) -> Result<Uuid> {
+        outbox::enqueue(db, &template.render(to, self.locale)).await
+    }
+}
@@ -0,0 +1,182 @@
+use crate::entities::mail_outbox;
+use crate::entities::sea_orm_active_enums::MailStatus;
+use crate::mailer::{Mail, Mailer};
+use crate::scheduler::Job;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use sea_orm::sea_query::{Expr, LockBehavior, LockType};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait,
+    IntoActiveModel, NotSet, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// メールを送信キューに追加する
+///
+/// 送信は`run_worker`が行う。メールの送信契機となる変更と同じトランザクションで呼ぶと、
+/// コミットされた場合にのみ送信される。
+pub async fn enqueue<C: ConnectionTrait>(db: &C, mail: &Mail) -> Result<Uuid> {
+    let id = Uuid::new_v4();
+    mail_outbox::ActiveModel {
+        id: Set(id),
+        created_at: NotSet,
+        updated_at: NotSet,
+        to_address: Set(mail.to.clone()),
+        subject: Set(mail.subject.clone()),
+        body: Set(mail.body.clone()),
+        status: NotSet,
+        attempts: NotSet,
+        next_attempt_at: NotSet,
+        last_error: NotSet,
+        sent_at: NotSet,
+        locked_until: NotSet,
+    }
+    .insert(db)
+    .await?;
+    debug!("mail {} queued", id);
+    Ok(id)
+}
+
+/// 送信中のメールを他のワーカーが再取得しない期間(秒)。この間に結果を記録できなかった場合は再送される
+const LEASE_TIME: i64 = 60 * 10;
+
+/// 送信時刻になった未送信のメールを送信し、送信したメールの件数を返す
+///
+/// 短いトランザクションでメールを`SENDING`にして確保し、送信はトランザクションの外で行う。
+/// 結果は1通ずつ記録するため、記録に失敗しても送信済みの他のメールが再送されることはない。
+/// 送信後(`SENT`または`FAILED`)は本文にリンク等が残らないよう消去する。
+pub async fn deliver_pending(db: &DatabaseConnection, mailer: &Mailer) -> Result<usize> {
+    let outbox = &mailer.outbox;
+    let mut sent = 0;
+    for model in claim(db, outbox.batch_size).await? {
+        let mail = Mail {
+            to: model.to_address.clone(),
+            subject: model.subject.clone(),
+            body: model.body.clone(),
+        };
+        let id = model.id;
+        let attempts = model.attempts;
+        let mut active_model = model.into_active_model();
+        active_model.locked_until = Set(None);
+        match mailer.send(&mail).await {
+            Ok(()) => {
+                active_model.status = Set(MailStatus::Sent);
+                active_model.body = Set(String::new());
+                active_model.sent_at = Set(Some(Utc::now().into()));
+                active_model.last_error = Set(None);
+                sent += 1;
+            }
+            Err(err) => {
+                warn!("failed to send mail {} (attempt {}): {}", id, attempts, err);
+                active_model.last_error = Set(Some(err.to_string()));
+                if attempts >= outbox.max_attempts {
+                    active_model.status = Set(MailStatus::Failed);
+                    active_model.body = Set(String::new());
+                } else {
+                    let backoff = outbox.retry_interval * 2_i64.pow((attempts - 1).min(10) as u32);
+                    active_model.status = Set(MailStatus::Pending);
+                    active_model.next_attempt_at =
+                        Set((Utc::now() + chrono::Duration::seconds(backoff)).into());
+                }
+            }
+        }
+        if let Err(err) = active_model.update(db).await {
+            warn!("failed to record the result of mail {}: {}", id, err);
+        }
+    }
+    Ok(sent)
+}
+
+/// 送信するメールを最大`limit`件確保し、試行回数を増やして返す
+///
+/// 複数のインスタンスから同時に呼ばれても同じメールを二重に確保しないよう、行ロックを取得し
+/// ロック済みの行は読み飛ばす。期限が切れた`SENDING`のメールは送信中に停止したものとして再度確保する。
+async fn claim(db: &DatabaseConnection, limit: u64) -> Result<Vec<mail_outbox::Model>> {
+    let now = Utc::now();
+    let txn = db.begin().await?;
+    let claimed = mail_outbox::Entity::find()
+        .filter(
+            Condition::any()
+                .add(
+                    Condition::all()
+                        .add(mail_outbox::Column::Status.eq(MailStatus::Pending))
+                        .add(mail_outbox::Column::NextAttemptAt.lte(now)),
+                )
+                .add(
+                    Condition::all()
+                        .add(mail_outbox::Column::Status.eq(MailStatus::Sending))
+                        .add(mail_outbox::Column::LockedUntil.lt(now)),
+                ),
+        )
+        .order_by_asc(mail_outbox::Column::NextAttemptAt)
+        .limit(limit)
+        .lock_with_behavior(LockType::Update, LockBehavior::SkipLocked)
+        .all(&txn)
+        .await?;
+    if claimed.is_empty() {
+        txn.rollback().await?;
+        return Ok(claimed);
+    }
+
+    let locked_until = now + chrono::Duration::seconds(LEASE_TIME);
+    mail_outbox::Entity::update_many()
+        .set(mail_outbox::ActiveModel {
+            status: Set(MailStatus::Sending),
+            locked_until: Set(Some(locked_until.into())),
+            ..Default::default()
+        })
+        .col_expr(
+            mail_outbox::Column::Attempts,
+            Expr::col(mail_outbox::Column::Attempts).add(1),
+        )
+        .filter(mail_outbox::Column::Id.is_in(claimed.iter().map(|model| model.id)))
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+
+    Ok(claimed
+        .into_iter()
+        .map(|model| mail_outbox::Model {
+            status: MailStatus::Sending,
+            attempts: model.attempts + 1,
+            locked_until: Some(locked_until.into()),
+            ..model
+        })
+        .collect())
+}
+
+/// 送信キューを処理する`Job`。`Scheduler`に`poll_interval`毎に実行させる
+pub struct DeliverPending {
+    db_conn: DatabaseConnection,
+    mailer: Arc<Mailer>,
+}
+
+impl DeliverPending {
+    pub fn new(db_conn: DatabaseConnection, mailer: Arc<Mailer>) -> Self {
+        Self { db_conn, mailer }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.mailer.outbox.poll_interval)
+    }
+}
+
+#[async_trait]
+impl Job for DeliverPending {
+    fn name(&self) -> &'static str {
+        "deliver_pending_mails"
+    }
+
+    async fn run(&self) -> Result<()> {
+        let sent = deliver_pending(&self.db_conn, &self.mailer).await?;
+        if sent > 0 {
+            debug!("{} mails sent", sent);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use crate::config::Locale;
+use crate::mailer::Mail;
+use chrono::{DateTime, FixedOffset};
+
+/// メールのテンプレート
+pub enum Template {
+    /// 出展者の代表者にアクティベーション用のリンクを送る
+    /// * `url`: アクティベーション用のリンク
+    /// * `expires_at`: リンクの有効期限
+    Activation {
+        first_name: String,
+        last_name: String,
+        exhibitor_name: String,
+        url: String,
+        expires_at: DateTime<FixedOffset>,
+    },
+    /// パスワード再設定用のリンクを送る
+    /// * `url`: パスワード再設定用のリンク
+    /// * `expire_minutes`: リンクの有効期間(分)
+    PasswordReset { url: String, expire_minutes: i64 },
+}
+
+impl Template {
+    /// `locale`の言語で件名と本文を生成する
+    pub fn render(&self, to: impl ToString, locale: Locale) -> Mail {
+        let (subject, body) = match (self, locale) {
+            (
+                Template::Activation {
+                    first_name,
+                    last_name,
+                    exhibitor_name,
+                    url,
+                    expires_at,
+                },
+                Locale::Ja,
+            ) => (
+                "【工大祭ポータル】アカウントの有効化".to_string(),
+                format!(
+                    "{} {} 様\n\n\
+                     「{}」の代表者として工大祭ポータルのアカウントが作成されました。\n\
+                     以下のリンクからパスワードを設定し、アカウントを有効化してください。\n\
+                     リンクの有効期限は{}です。\n\n\
+                     {}\n\n\
+                     このメールに心当たりがない場合は破棄してください。\n",
+                    last_name,
+                    first_name,
+                    exhibitor_name,
+                    expires_at.with_timezone(&jst()).format("%Y年%m月%d日 %H:%M"),
+                    url
+                ),
+            ),
+            (
+                Template::Activation {
+                    first_name,
+                    last_name,
+                    exhibitor_name,
+                    url,
+                    expires_at,
+                },
+                Locale::En,
+            ) => (
+                "[Koudaisai Portal] Activate your account".to_string(),
+                format!(
+                    "Dear {} {},\n\n\
+                     A Koudaisai Portal account has been created for you as a representative of \"{}\".\n\
+                     Please set your password and activate your account from the link below.\n\
+                     The link expires at {}.\n\n\
+                     {}\n\n\
+                     If you did not expect this email, please ignore it.\n",
+                    first_name,
+                    last_name,
+                    exhibitor_name,
+                    expires_at.with_timezone(&jst()).format("%Y-%m-%d %H:%M JST"),
+                    url
+                ),
+            ),
+            (
+                Template::PasswordReset {
+                    url,
+                    expire_minutes,
+                },
+                Locale::Ja,
+            ) => (
+                "【工大祭ポータル】パスワードの再設定".to_string(),
+                format!(
+                    "以下のリンクからパスワードを再設定してください。\n\
+                     リンクの有効期限は{}分です。\n\n\
+                     {}\n\n\
+                     このメールに心当たりがない場合は破棄してください。\n",
+                    expire_minutes, url
+                ),
+            ),
+            (
+                Template::PasswordReset {
+                    url,
+                    expire_minutes,
+                },
+                Locale::En,
+            ) => (
+                "[Koudaisai Portal] Reset your password".to_string(),
+                format!(
+                    "Please reset your password from the link below.\n\
+                     The link expires in {} minutes.\n\n\
+                     {}\n\n\
+                     If you did not request a password reset, please ignore this email.\n",
+                    expire_minutes, url
+                ),
+            ),
+        };
+        Mail {
+            to: to.to_string(),
+            subject,
+            body,
+        }
+    }
+}
+
+/// 日時は日本標準時で表示する
+fn jst() -> FixedOffset {
+    FixedOffset::east_opt(9 * 3600).unwrap()
+}
@@ -0,0 +1,131 @@
+use crate::config;
+use crate::mailer::{Mail, MailTransport};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde_json::json;
+use std::path::PathBuf;
+use tracing::info;
+use uuid::Uuid;
+
+/// SMTPサーバーを通して送信する
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(smtp: &config::Smtp) -> Result<Self> {
+        let builder = if smtp.starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)?
+        };
+        let transport = builder
+            .port(smtp.port)
+            .credentials(Credentials::new(
+                smtp.username.clone(),
+                smtp.password.clone(),
+            ))
+            .build();
+        Ok(Self { transport })
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpTransport {
+    async fn send(&self, from: &str, mail: &Mail) -> Result<()> {
+        self.transport.send(build_message(from, mail)?).await?;
+        Ok(())
+    }
+}
+
+/// Maildirの`new`に書き出す(開発用)
+///
+/// メールクライアントでそのまま開けるよう、`tmp`に書き込んでから`new`に移動する。
+pub struct MaildirTransport {
+    path: PathBuf,
+}
+
+impl MaildirTransport {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl MailTransport for MaildirTransport {
+    async fn send(&self, from: &str, mail: &Mail) -> Result<()> {
+        let message = build_message(from, mail)?;
+        for dir in ["tmp", "new", "cur"] {
+            tokio::fs::create_dir_all(self.path.join(dir)).await?;
+        }
+        let name = format!(
+            "{}.{}.koudaisai-portal",
+            Utc::now().timestamp(),
+            Uuid::new_v4().simple()
+        );
+        let tmp = self.path.join("tmp").join(&name);
+        let new = self.path.join("new").join(&name);
+        tokio::fs::write(&tmp, message.formatted()).await?;
+        tokio::fs::rename(&tmp, &new).await?;
+        info!("mail written to {}", new.display());
+        Ok(())
+    }
+}
+
+/// ディレクトリにJSONとして書き出す(開発・テスト用)
+pub struct FileTransport {
+    path: PathBuf,
+}
+
+impl FileTransport {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl MailTransport for FileTransport {
+    async fn send(&self, from: &str, mail: &Mail) -> Result<()> {
+        tokio::fs::create_dir_all(&self.path).await?;
+        let file = self.path.join(format!("{}.json", Uuid::new_v4()));
+        let content = json!({
+            "from": from,
+            "to": mail.to,
+            "subject": mail.subject,
+            "body": mail.body,
+        });
+        tokio::fs::write(&file, serde_json::to_vec_pretty(&content)?).await?;
+        info!("mail written to {}", file.display());
+        Ok(())
+    }
+}
+
+fn build_message(from: &str, mail: &Mail) -> Result<Message> {
+    Ok(Message::builder()
+        .from(from.parse()?)
+        .to(mail.to.parse()?)
+        .subject(&mail.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(mail.body.clone())?)
+}
+
+/// 宛先と件名のみをログに出力する(開発用)
+///
+/// 本文にはパスワードリセットのリンクやアクティベーションコードが含まれるため出力しない。
+/// 本文を確認する場合は`MaildirTransport`か`FileTransport`を使う。
+pub struct StdoutTransport;
+
+#[async_trait]
+impl MailTransport for StdoutTransport {
+    async fn send(&self, from: &str, mail: &Mail) -> Result<()> {
+        info!(
+            "mail from {} to {} (body redacted)\nSubject: {}",
+            from, mail.to, mail.subject
+        );
+        Ok(())
+    }
+}
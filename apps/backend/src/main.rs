@@ -1,56 +1,138 @@
-use crate::config::{init_config, Db, Logging};
+use crate::config::{init_config, AccessTokenValidation, Config, Db, Logging};
+use crate::mailer::{outbox, Mailer};
 use crate::routes::init_routes;
-use crate::util::oidc::OIDCClient;
+use crate::scheduler::{purge, Scheduler};
+use crate::util::jwt_keys;
+use crate::util::oidc::{Audience, JwksCache, OIDCClient, OIDCProviderMetadata};
+use anyhow::anyhow;
 use migration::{Migrator, MigratorTrait};
-use openidconnect::core::{CoreClient, CoreProviderMetadata};
-use openidconnect::{ClientId, ClientSecret, IssuerUrl, RedirectUrl};
+use openidconnect::core::CoreClient;
+use openidconnect::{ClientId, ClientSecret, EndSessionUrl, IssuerUrl, RedirectUrl};
 use pkg_version::{pkg_version_major, pkg_version_minor, pkg_version_patch};
 use sea_orm::{Database, DatabaseConnection, DbErr};
-use tracing::{debug, info, instrument};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, instrument, warn};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
 pub mod config;
 pub mod entities;
+pub mod extractors;
 mod forms;
+pub mod mailer;
 pub mod middlewares;
+pub mod permissions;
 mod routes;
+pub mod scheduler;
+pub mod storage;
 pub mod util;
 
 const MAJOR_VERSION: u32 = pkg_version_major!();
 const MINOR_VERSION: u32 = pkg_version_minor!();
 const PATCH_VERSION: u32 = pkg_version_patch!();
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     //初期化
     let config = init_config().unwrap();
-    init_logging(config.logging);
+    init_logging(config.logging.clone());
     info!(
         "Koudaisai Portal v{}.{}.{} (c) 2025 JIZI All Rights Reserved.",
         MAJOR_VERSION, MINOR_VERSION, PATCH_VERSION
     );
 
+    let result = match std::env::args().nth(1).as_deref() {
+        None => run(config).await,
+        Some("keygen") => keygen(&config),
+        Some(command) => Err(anyhow!("unknown command: {}", command)),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{:#}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// 署名に使う鍵ペアが無い場合に生成して終了する
+fn keygen(config: &Config) -> anyhow::Result<()> {
+    let jwt = config.web.auth.jwt()?;
+    if !jwt_keys::generate_signing_key(&jwt)? {
+        info!("key pair for kid {} already exists", jwt.signing_kid);
+    }
+    Ok(())
+}
+
+/// サーバーを起動する
+async fn run(config: Config) -> anyhow::Result<()> {
     // openid connect init
-    let oidc_client = init_oidc(
+    let (oidc_client, jwks, end_session_url) = init_oidc(
         config.web.auth.keycloak.id.clone(),
         config.web.auth.keycloak.secret.clone(),
         config.web.auth.keycloak.issuer.to_string(),
         format!("{}{}", &config.web.server.base_url, "/login"),
+        &config.web.auth.keycloak.access_token,
     )
     .await;
+    if let Err(err) = jwks.refresh().await {
+        warn!("failed to fetch jwks: {}", err);
+    }
 
     //app init
-    let db = init_db(&config.db).await.unwrap();
-    let app = init_routes(&config.web, db, oidc_client);
+    let db = init_db(&config.db).await?;
+    let mailer = Arc::new(Mailer::from_config(&config.mail)?);
+    let deliver_pending = outbox::DeliverPending::new(db.clone(), mailer.clone());
+    Scheduler::new()
+        .add(deliver_pending.interval(), deliver_pending)
+        .add(
+            Duration::from_secs(config.scheduler.purge_interval),
+            purge::PurgeExpiredTokens::new(db.clone()),
+        )
+        .add(
+            Duration::from_secs(config.scheduler.purge_interval),
+            purge::PurgeLoginAttempts::new(db.clone(), config.scheduler.login_attempt_retention),
+        )
+        .add(
+            Duration::from_secs(config.scheduler.purge_interval),
+            purge::PurgeMails::new(db.clone(), config.scheduler.mail_retention),
+        )
+        .add(
+            Duration::from_secs(config.scheduler.purge_interval),
+            purge::PurgeRefreshTokenFamilies::new(
+                db.clone(),
+                config.web.auth.token.refresh_token_expire_time,
+                config.web.auth.token.refresh_token_expiry,
+            ),
+        )
+        .add(
+            Duration::from_secs(config.scheduler.purge_interval),
+            purge::PurgeUnreferencedFiles::new(
+                db.clone(),
+                storage::from_config(&config.storage)?,
+                config.scheduler.unreferenced_file_retention,
+            ),
+        )
+        .start();
+    let app = init_routes(
+        &config.web,
+        &config.storage,
+        db,
+        oidc_client,
+        jwks,
+        end_session_url,
+        mailer,
+    )?;
 
     let listener = tokio::net::TcpListener::bind(format!(
         "{}:{}",
         &config.web.server.host, &config.web.server.port
     ))
-    .await
-    .unwrap();
-    tracing::debug!("Listening on: {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    .await?;
+    tracing::debug!("Listening on: {}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+    Ok(())
 }
 
 #[instrument(skip(client_secret))]
@@ -59,22 +141,41 @@ async fn init_oidc(
     client_secret: String,
     issuer_url: String,
     redirect_url: String,
-) -> OIDCClient {
+    access_token: &AccessTokenValidation,
+) -> (OIDCClient, JwksCache, Option<EndSessionUrl>) {
     let http_client = reqwest::Client::new();
 
-    let provider_metadata: CoreProviderMetadata =
-        CoreProviderMetadata::discover_async(IssuerUrl::new(issuer_url).unwrap(), &http_client)
+    let provider_metadata: OIDCProviderMetadata =
+        OIDCProviderMetadata::discover_async(IssuerUrl::new(issuer_url).unwrap(), &http_client)
             .await
             .unwrap();
+    let end_session_url = provider_metadata
+        .additional_metadata()
+        .end_session_endpoint
+        .clone();
+    let revocation_url = provider_metadata
+        .additional_metadata()
+        .additional_metadata
+        .revocation_endpoint
+        .clone();
+
+    let jwks = JwksCache::new(
+        provider_metadata.jwks_uri().url(),
+        provider_metadata.issuer(),
+        Audience::new(access_token.audience.clone(), &client_id),
+        Duration::from_secs(access_token.jwks_refresh_interval),
+        http_client,
+    );
 
     let client = CoreClient::from_provider_metadata(
         provider_metadata,
         ClientId::new(client_id),
         Some(ClientSecret::new(client_secret)),
     )
-    .set_redirect_uri(RedirectUrl::new(redirect_url).unwrap());
+    .set_redirect_uri(RedirectUrl::new(redirect_url).unwrap())
+    .set_revocation_url(revocation_url);
 
-    client
+    (client, jwks, end_session_url)
 }
 
 #[instrument(skip(db))]
@@ -1,16 +1,19 @@
+use crate::permissions::{Permission, RoleClaims};
 use crate::routes::AppState;
 use crate::util::jwt;
+use crate::util::oidc::{AdminClaims, VerifyError};
+use crate::util::session_cookie;
+use crate::util::AppError;
 use axum::extract::{Request, State};
 use axum::http;
-use axum::http::StatusCode;
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
-use openidconnect::core::CoreUserInfoClaims;
-use openidconnect::AccessToken;
+use axum_extra::extract::CookieJar;
+use openidconnect::core::CoreGenderClaim;
+use openidconnect::{AccessToken, UserInfoClaims};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::io::AsyncReadExt;
 use tracing::log::{trace, warn};
 use tracing::{debug, instrument};
 
@@ -18,11 +21,38 @@ use tracing::{debug, instrument};
 pub enum CurrentUser {
     None,
     User(jwt::Claims),
-    Admin(CoreUserInfoClaims),
+    Admin(Admin),
+}
+
+impl CurrentUser {
+    /// 管理者で`permission`を持つ場合true
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        match self {
+            CurrentUser::Admin(admin) => admin.has_permission(permission),
+            _ => false,
+        }
+    }
+}
+
+/// 管理者
+/// * `permissions`: `keycloak.roles`の設定によってロールから変換された権限
+#[derive(Clone, Debug)]
+pub struct Admin {
+    pub claims: AdminClaims,
+    pub permissions: HashSet<Permission>,
+}
+
+impl Admin {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
 }
 
 /// 認証ミドルウェア
 /// ヘッダーに`Authorization: Bearer <token>`が含まれている場合、tokenの検証を行う。含まれていない場合はCurrentUser::Noneを`extensions`に挿入
+/// Cookieモードの場合、ヘッダーがなければ`access_token`のCookieを使う。このとき状態を変更するリクエストでは
+/// `X-CSRF-Token`ヘッダーが`csrf_token`のCookieと一致する必要がある。
+/// Cookieは自動で送られるため、無効なトークンのCookieは拒否せずCurrentUser::Noneとして扱う
 #[instrument(name = "auth middleware", skip(state, req, next))]
 pub async fn auth(State(state): State<Arc<AppState>>, mut req: Request, next: Next) -> Response {
     let auth_header = req
@@ -34,64 +64,53 @@ pub async fn auth(State(state): State<Arc<AppState>>, mut req: Request, next: Ne
     let token = match auth_header {
         Some(auth_header) => match auth_header.strip_prefix("Bearer ") {
             Some(token) => token.to_string(),
-            None => return StatusCode::UNAUTHORIZED.into_response(),
+            None => return AppError::Unauthorized("bearer token is required.").into_response(),
         },
         None => {
-            req.extensions_mut().insert(CurrentUser::None);
-            return next.run(req).await;
-        }
-    };
-
-    // トークンのissを抽出
-    let payload_base64 = match token.split(".").collect::<Vec<&str>>().get(1) {
-        Some(payload) => payload.to_string(),
-        None => {
-            warn!("Authorization error: token was not jwt");
-            return StatusCode::UNAUTHORIZED.into_response();
-        }
-    };
-    let mut payload = String::new();
-    if let Err(err) = base64url::decode(&payload_base64)
-        .unwrap()
-        .as_slice()
-        .read_to_string(&mut payload)
-        .await
-    {
-        warn!("Authorization error banana: {:?}", err);
-        return StatusCode::UNAUTHORIZED.into_response();
-    }
-    trace!("{}", payload);
-    let insecure_token_data = match serde_json::from_str::<HashMap<String, Value>>(payload.as_str())
-    {
-        Ok(data) => data,
-        Err(err) => {
-            warn!("Authorization error money: {:?}", err);
-            return StatusCode::UNAUTHORIZED.into_response();
+            let jar = CookieJar::from_headers(req.headers());
+            match jar
+                .get(session_cookie::ACCESS_TOKEN_COOKIE)
+                .filter(|_| state.web.auth.cookie.enabled)
+            {
+                Some(cookie) => {
+                    let claims = match state.jwt_manager.decode(cookie.value()) {
+                        Ok(data) if state.jwt_manager.is_access_token_valid(&data.claims) => {
+                            data.claims
+                        }
+                        _ => {
+                            debug!("access token cookie invalid");
+                            req.extensions_mut().insert(CurrentUser::None);
+                            return next.run(req).await;
+                        }
+                    };
+                    if !session_cookie::is_safe_method(req.method())
+                        && !session_cookie::verify_csrf(&jar, req.headers())
+                    {
+                        debug!("Authorization error: csrf token mismatch");
+                        return AppError::Forbidden.into_response();
+                    }
+                    req.extensions_mut().insert(CurrentUser::User(claims));
+                    return next.run(req).await;
+                }
+                None => {
+                    req.extensions_mut().insert(CurrentUser::None);
+                    return next.run(req).await;
+                }
+            }
         }
     };
-    let iss = match insecure_token_data.get("iss") {
-        Some(iss) => iss.to_string(),
-        None => {
-            warn!("Authorization error: claim iss not found in the jwt");
-            return StatusCode::UNAUTHORIZED.into_response();
-        }
-    }
-    .strip_prefix("\"")
-    .unwrap()
-    .strip_suffix("\"")
-    .unwrap()
-    .to_string();
 
     // 自分自身が発行したトークンの場合：参加団体責任者アカウントとして処理
-    // 他のissuerが発行したトークンの場合：adminアカウントとして処理
-    trace!("{} {}", iss, state.jwt_manager.iss);
-    if iss == state.jwt_manager.iss {
+    // それ以外の場合：adminアカウントとして処理
+    let iss = peek_iss(&token);
+    trace!("{:?} {}", iss, state.jwt_manager.iss);
+    if iss.as_deref() == Some(state.jwt_manager.iss.as_str()) {
         trace!("token type: jizi jwt");
         let token = match state.jwt_manager.decode(&*token) {
             Ok(data) => data,
             Err(err) => {
                 warn!("Authorization error: {:?}", err);
-                return StatusCode::UNAUTHORIZED.into_response();
+                return AppError::Unauthorized(INVALID_TOKEN).into_response();
             }
         };
         if state.jwt_manager.is_access_token_valid(&token.claims) {
@@ -99,29 +118,59 @@ pub async fn auth(State(state): State<Arc<AppState>>, mut req: Request, next: Ne
             next.run(req).await
         } else {
             debug!("Authorization error: access token invalid");
-            StatusCode::UNAUTHORIZED.into_response()
+            AppError::Unauthorized(INVALID_TOKEN).into_response()
         }
     } else {
         trace!("token type: oidc jwt");
-        // user_infoを要求する。
-        let access_token = AccessToken::new(token);
-        let user_info = match state.oidc_client.user_info(access_token, None) {
-            Ok(user_info) => user_info,
+        let claims = match state.jwks.verify(&token).await {
+            Ok(claims) => claims,
+            Err(VerifyError::Unverifiable(err))
+                if state.web.auth.keycloak.access_token.userinfo_fallback =>
+            {
+                debug!("falling back to userinfo: {}", err);
+                match request_user_info(&state, token).await {
+                    Ok(claims) => claims,
+                    Err(err) => return err.into_response(),
+                }
+            }
             Err(err) => {
                 warn!("Authorization error: {:?}", err);
-                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                return AppError::Unauthorized(INVALID_TOKEN).into_response();
             }
         };
-        let user_info: CoreUserInfoClaims = match user_info.request_async(&state.http_client).await
-        {
+        let keycloak = &state.web.auth.keycloak;
+        let permissions = claims.roles.permissions(&keycloak.id, &keycloak.roles);
+        trace!("permissions: {:?}", permissions);
+        req.extensions_mut().insert(CurrentUser::Admin(Admin {
+            claims,
+            permissions,
+        }));
+        trace!("oidc auth ok");
+        next.run(req).await
+    }
+}
+
+const INVALID_TOKEN: &str = "invalid or expired token.";
+
+/// 署名を検証せずにトークンの`iss`を取り出す。JWTでない場合は`None`
+fn peek_iss(token: &str) -> Option<String> {
+    let payload_base64 = token.split('.').nth(1)?.to_string();
+    let payload = base64url::decode(&payload_base64).ok()?;
+    let payload = serde_json::from_slice::<HashMap<String, Value>>(&payload).ok()?;
+    payload.get("iss")?.as_str().map(|iss| iss.to_string())
+}
+
+/// userinfoエンドポイントに問い合わせてトークンを検証する
+async fn request_user_info(state: &AppState, token: String) -> Result<AdminClaims, AppError> {
+    let access_token = AccessToken::new(token);
+    let user_info = state.oidc_client.user_info(access_token, None)?;
+    let user_info: UserInfoClaims<RoleClaims, CoreGenderClaim> =
+        match user_info.request_async(&state.http_client).await {
             Ok(user_info) => user_info,
             Err(err) => {
                 warn!("Authorization error: {:?}", err);
-                return StatusCode::UNAUTHORIZED.into_response();
+                return Err(AppError::Unauthorized(INVALID_TOKEN));
             }
         };
-        req.extensions_mut().insert(CurrentUser::Admin(user_info));
-        trace!("oidc auth ok");
-        next.run(req).await
-    }
+    Ok(user_info.into())
 }
@@ -0,0 +1,108 @@
+use openidconnect::AdditionalClaims;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// 管理者の権限
+/// * `FormsRead`: フォームの閲覧
+/// * `FormsWrite`: フォームの作成・編集・削除
+/// * `ResponsesRead`: フォームの回答の閲覧
+/// * `ExhibitorsRead`: 参加団体の閲覧
+/// * `ExhibitorsWrite`: 参加団体の作成・編集
+/// * `UsersRead`: 参加団体責任者アカウントのロックとログイン試行の閲覧
+/// * `UsersWrite`: 参加団体責任者アカウントの管理
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    FormsRead,
+    FormsWrite,
+    ResponsesRead,
+    ExhibitorsRead,
+    ExhibitorsWrite,
+    UsersRead,
+    UsersWrite,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 7] = [
+        Permission::FormsRead,
+        Permission::FormsWrite,
+        Permission::ResponsesRead,
+        Permission::ExhibitorsRead,
+        Permission::ExhibitorsWrite,
+        Permission::UsersRead,
+        Permission::UsersWrite,
+    ];
+}
+
+/// Keycloakのトークン(またはuserinfo)に含まれるロールとグループ
+/// * `realm_access`: レルムロール
+/// * `resource_access`: クライアントID毎のクライアントロール
+/// * `groups`: グループのパス(`/portal-admin`等)。Group Membershipマッパーで追加する
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RoleClaims {
+    #[serde(default)]
+    pub realm_access: Option<Roles>,
+    #[serde(default)]
+    pub resource_access: HashMap<String, Roles>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+impl AdditionalClaims for RoleClaims {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Roles {
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl RoleClaims {
+    /// レルムロール、`client_id`のクライアントロール、グループ名を`mapping`で権限に変換する
+    ///
+    /// グループはパスの先頭の`/`を除いた名前で対応付ける。
+    pub fn permissions(
+        &self,
+        client_id: &str,
+        mapping: &HashMap<String, Vec<Permission>>,
+    ) -> HashSet<Permission> {
+        let realm_roles = self.realm_access.iter().flat_map(|access| &access.roles);
+        let client_roles = self
+            .resource_access
+            .get(client_id)
+            .into_iter()
+            .flat_map(|access| &access.roles);
+        let groups = self.groups.iter();
+
+        realm_roles
+            .chain(client_roles)
+            .chain(groups)
+            .map(|name| name.trim_start_matches('/'))
+            .filter_map(|name| mapping.get(name))
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
+
+/// 既定のロールと権限の対応
+pub fn default_mapping() -> HashMap<String, Vec<Permission>> {
+    HashMap::from([
+        ("portal-admin".to_string(), Permission::ALL.to_vec()),
+        (
+            "portal-viewer".to_string(),
+            vec![
+                Permission::FormsRead,
+                Permission::ResponsesRead,
+                Permission::ExhibitorsRead,
+            ],
+        ),
+        (
+            "forms-editor".to_string(),
+            vec![
+                Permission::FormsRead,
+                Permission::FormsWrite,
+                Permission::ResponsesRead,
+            ],
+        ),
+    ])
+}
@@ -1,53 +1,62 @@
 mod api;
 mod auth;
+mod well_known;
 
-use crate::config::Web;
+use crate::config::{self, Web};
+use crate::mailer::Mailer;
 use crate::middlewares;
+use crate::storage::{self, ObjectStorage};
+use crate::util::auth_session::{self, AuthSessionStore};
 use crate::util::jwt::JWTManager;
-use crate::util::oidc::OIDCClient;
-use crate::util::sha::SHAManager;
+use crate::util::jwt_keys::KeySet;
+use crate::util::oidc::{JwksCache, OIDCClient};
+use crate::util::password::PasswordManager;
+use crate::util::totp::SecretCipher;
+use anyhow::Result;
 use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
 use axum::middleware::from_fn_with_state;
 use axum::routing::get_service;
 use axum::Router;
-use jsonwebtoken::Algorithm;
-use oauth2::PkceCodeVerifier;
-use openidconnect::Nonce;
+use openidconnect::EndSessionUrl;
 use reqwest::Client;
 use sea_orm::DatabaseConnection;
-use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use tower_http::cors::CorsLayer;
 use tower_http::services::ServeDir;
 use tracing::{debug, instrument};
 
-#[instrument(skip(web))]
+#[instrument(skip(web, storage, jwks, mailer))]
 pub fn init_routes(
     web: &Web,
+    storage: &config::Storage,
     db_conn: DatabaseConnection,
     oidc_client: OIDCClient,
-) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
+    jwks: JwksCache,
+    end_session_url: Option<EndSessionUrl>,
+    mailer: Arc<Mailer>,
+) -> Result<IntoMakeServiceWithConnectInfo<Router, SocketAddr>> {
     debug!("Initializing routes");
     let state = Arc::new(AppState {
         web: web.clone(),
         db_conn: db_conn.clone(),
         oidc_client,
-        auth_sessions: Default::default(),
+        jwks,
+        end_session_url,
+        auth_sessions: auth_session::from_config(&web.auth.admin_session, db_conn.clone()),
         http_client: Client::new(),
-        jwt_manager: JWTManager::new(
-            Algorithm::RS256,
-            600,
-            60 * 60 * 24 * 30 * 6,
-            "https://portal.koudaisai.jp",
-            web.auth.get_jwt_encoding_key().unwrap(),
-            web.auth.get_jwt_decoding_key().unwrap(),
+        jwt_manager: JWTManager::from_config(
+            &web.auth.token,
+            &web.server.base_url,
+            KeySet::from_config(&web.auth.jwt()?)?,
             db_conn,
         ),
-        sha_manager: SHAManager {
-            stretch_cost: 2_i32.pow(web.auth.stretch_cost as u32),
-        },
+        password_manager: PasswordManager::from_config(&web.auth)?,
+        totp_cipher: SecretCipher::from_config(&web.auth),
+        mailer,
+        storage: storage::from_config(storage)?,
+        max_upload_size: storage.max_upload_size,
+        exhibitor_quota: storage.exhibitor_quota,
     });
 
     let serve_dir =
@@ -55,28 +64,31 @@ pub fn init_routes(
     let admin_serve_dir =
         ServeDir::new(&web.static_files.admin_path).append_index_html_on_directories(true);
 
-    Router::new()
+    Ok(Router::new()
         .nest("/auth", auth::init_router())
         .nest("/api", api::init_router())
+        .nest("/.well-known", well_known::init_router())
         .fallback_service(get_service(serve_dir))
         .nest_service("/admin", get_service(admin_serve_dir))
         .route_layer(from_fn_with_state(state.clone(), middlewares::auth))
         .layer(CorsLayer::permissive())
         .with_state(state)
-        .into_make_service_with_connect_info::<SocketAddr>()
+        .into_make_service_with_connect_info::<SocketAddr>())
 }
 
 pub struct AppState {
     pub web: Web,
     pub db_conn: DatabaseConnection,
     pub oidc_client: OIDCClient,
-    pub auth_sessions: Mutex<HashMap<String, AuthSession>>,
+    pub jwks: JwksCache,
+    pub end_session_url: Option<EndSessionUrl>,
+    pub auth_sessions: Box<dyn AuthSessionStore>,
     pub http_client: Client,
     pub jwt_manager: JWTManager,
-    pub sha_manager: SHAManager,
-}
-
-pub struct AuthSession {
-    pkce_verifier: PkceCodeVerifier,
-    nonce: Nonce,
+    pub password_manager: PasswordManager,
+    pub totp_cipher: SecretCipher,
+    pub mailer: Arc<Mailer>,
+    pub storage: Box<dyn ObjectStorage>,
+    pub max_upload_size: usize,
+    pub exhibitor_quota: i64,
 }
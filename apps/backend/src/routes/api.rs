@@ -1,5 +1,8 @@
 mod exhibitors;
+mod files;
 mod forms;
+mod me;
+mod users;
 
 use crate::routes::AppState;
 use axum::Router;
@@ -11,4 +14,7 @@ pub fn init_router() -> Router<Arc<AppState>> {
     Router::new()
         .nest("/v1/forms", forms::init_router())
         .nest("/v1/exhibitors", exhibitors::init_router())
+        .nest("/v1/users", users::init_router())
+        .nest("/v1/me", me::init_router())
+        .nest("/v1/files", files::init_router())
 }
@@ -3,21 +3,22 @@ use crate::entities::{
     exhibitors_category_booth, exhibitors_category_general, exhibitors_category_labo,
     exhibitors_category_stage, exhibitors_root, sea_orm_active_enums, users,
 };
-use crate::middlewares::CurrentUser;
+use crate::extractors::{policy, Requester, RequireAdmin};
 use crate::routes::AppState;
-use crate::util::sha::stretch_with_salt;
+use crate::util::activation;
+use crate::util::json::Json;
 use crate::util::AppError;
 use axum::extract::{ConnectInfo, Path, State};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{post, put};
-use axum::{Extension, Json, Router};
+use axum::routing::{delete, post, put};
+use axum::Router;
 use http::StatusCode;
 use sea_orm::{ActiveModelTrait, ActiveValue, EntityTrait, QueryFilter, TransactionTrait};
 use sea_orm::{ColumnTrait, EntityOrSelect};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tracing::{instrument, warn};
+use tracing::{info, instrument, warn};
 use uuid::Uuid;
 
 #[instrument(name = "init /api/v1/exhibitors")]
@@ -25,6 +26,7 @@ pub fn init_router() -> Router<Arc<AppState>> {
     Router::new()
         .route("/", post(post_exhibitors).get(get_exhibitors))
         .route("/{id}", put(put_exhibitors_id).get(get_exhibitors_id))
+        .route("/{id}/sessions", delete(delete_exhibitors_id_sessions))
 }
 
 #[derive(Deserialize, Debug)]
@@ -89,35 +91,35 @@ fn new_user_model(
         password_hash: ActiveValue::NotSet,
         password_salt: ActiveValue::NotSet,
         exhibition_id: ActiveValue::Set(exhibition_id),
+        failed_login_count: ActiveValue::NotSet,
+        locked_until: ActiveValue::NotSet,
     }
 }
-type PostExhibitorsResponse = (String, String, String);
+#[derive(Serialize, Debug)]
+struct PostExhibitorsResponse {
+    representatives: (Uuid, Uuid, Uuid),
+}
 #[instrument(name = "POST /api/v1/exhibitors", skip(state))]
 #[axum::debug_handler]
 async fn post_exhibitors(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    _admin: RequireAdmin<policy::ExhibitorsWrite>,
     Json(payload): Json<PostExhibitorsPayload>,
 ) -> Result<(StatusCode, Response), AppError> {
-    match current_user {
-        CurrentUser::Admin(_) => {}
-        _ => return Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response())),
-    };
-
     // conflict check
     if let Some(_) = exhibitors_root::Entity::find_by_id(payload.id.clone())
         .one(&state.db_conn)
         .await?
     {
-        return Ok((StatusCode::CONFLICT, "Conflict.".into_response()));
+        return Err(AppError::Conflict("exhibitor already exists."));
     }
     if let Some(_) = users::Entity::find()
         .filter(users::Column::MAddress.eq(payload.representatives.0.m_address.clone()))
         .one(&state.db_conn)
         .await?
     {
-        return Ok((StatusCode::CONFLICT, "Conflict.".into_response()));
+        return Err(AppError::Conflict("m_address already in use."));
     }
     // generate uuids
     let uuids = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
@@ -191,54 +193,54 @@ async fn post_exhibitors(
     }
 
     //users
-    new_user_model(
-        &payload.representatives.0,
-        payload.id.clone(),
-        uuids.0.clone(),
-    )
-    .insert(&txn)
-    .await?;
-    new_user_model(
-        &payload.representatives.1,
-        payload.id.clone(),
-        uuids.1.clone(),
-    )
-    .insert(&txn)
-    .await?;
-    new_user_model(
-        &payload.representatives.2,
-        payload.id.clone(),
-        uuids.2.clone(),
-    )
-    .insert(&txn)
-    .await?;
-
-    //commit
-    txn.commit().await?;
-
-    //generate activation tokens
-    let activation_tokens: PostExhibitorsResponse = (
-        stretch_with_salt(
-            payload.representatives.0.m_address.as_str(),
-            state.web.auth.activation_salt.as_str(),
-            2_i32.pow(state.web.auth.stretch_cost as u32),
+    let representatives = [
+        new_user_model(
+            &payload.representatives.0,
+            payload.id.clone(),
+            uuids.0.clone(),
         )
-        .await,
-        stretch_with_salt(
-            payload.representatives.1.m_address.as_str(),
-            state.web.auth.activation_salt.as_str(),
-            2_i32.pow(state.web.auth.stretch_cost as u32),
+        .insert(&txn)
+        .await?,
+        new_user_model(
+            &payload.representatives.1,
+            payload.id.clone(),
+            uuids.1.clone(),
         )
-        .await,
-        stretch_with_salt(
-            payload.representatives.2.m_address.as_str(),
-            state.web.auth.activation_salt.as_str(),
-            2_i32.pow(state.web.auth.stretch_cost as u32),
+        .insert(&txn)
+        .await?,
+        new_user_model(
+            &payload.representatives.2,
+            payload.id.clone(),
+            uuids.2.clone(),
         )
-        .await,
-    );
+        .insert(&txn)
+        .await?,
+    ];
 
-    Ok((StatusCode::CREATED, Json(activation_tokens).into_response()))
+    //activation codes
+    //コードは代表者にメールで送り、レスポンスには含めない
+    for representative in &representatives {
+        activation::issue_and_mail_code(
+            &txn,
+            &state.mailer,
+            &state.web.server.base_url,
+            representative,
+            &payload.exhibitor_name,
+            state.web.auth.activation.code_expire_time,
+        )
+        .await?;
+    }
+
+    //commit
+    txn.commit().await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(PostExhibitorsResponse {
+            representatives: uuids,
+        })
+        .into_response(),
+    ))
 }
 
 #[derive(Serialize, Debug)]
@@ -278,13 +280,8 @@ impl From<exhibitors_root::Model> for GetExhibitorsResponseElement {
 async fn get_exhibitors(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    _admin: RequireAdmin<policy::ExhibitorsRead>,
 ) -> Result<(StatusCode, Response), AppError> {
-    match current_user {
-        CurrentUser::Admin(_) => {}
-        _ => return Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response())),
-    };
-
     let models = exhibitors_root::Entity.select().all(&state.db_conn).await?;
     let mut exhibitors: Vec<GetExhibitorsResponseElement> = vec![];
     for model in models {
@@ -295,42 +292,35 @@ async fn get_exhibitors(
 }
 
 type GetExhibitorsIdResponse = GetExhibitorsResponseElement;
+/// 管理者は`P`の権限を、参加団体責任者は参加団体`id`に属していることを要求する
+fn authorize_exhibitor<P: policy::Policy>(requester: &Requester, id: &str) -> Result<(), AppError> {
+    requester.require_admin_policy::<P>()?;
+    match requester {
+        Requester::Admin(_) => Ok(()),
+        // FORBIDDEN等にすると参加団体の存在が無駄に露呈してしまう
+        Requester::ExhibitorMember(member) if !member.belongs_to(id) => {
+            Err(AppError::NotFound("exhibitor"))
+        }
+        Requester::ExhibitorMember(_) => Ok(()),
+        Requester::Anonymous => Err(AppError::Forbidden),
+    }
+}
+
 #[instrument(name = "GET /api/v1/exhibitors/{id}", skip(state))]
 async fn get_exhibitors_id(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    requester: Requester,
     Path(id): Path<String>,
 ) -> Result<(StatusCode, Response), AppError> {
-    //permission check
-    match current_user {
-        CurrentUser::Admin(_) => {}
-        CurrentUser::User(claims) => {
-            // 属しているかどうか確認
-            let model = users::Entity::find_by_id(claims.sub)
-                .one(&state.db_conn)
-                .await?;
-            if model == None {
-                return Ok((StatusCode::NOT_FOUND, "Subject not found.".into_response()));
-            }
-            if model.unwrap().exhibition_id != id {
-                // FORBIDDEN等にすると参加団体の存在が無駄に露呈してしまう
-                return Ok((StatusCode::NOT_FOUND, "Not found.".into_response()));
-            }
-        }
-        CurrentUser::None => {
-            return Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response()));
-        }
-    }
+    authorize_exhibitor::<policy::ExhibitorsRead>(&requester, &id)?;
 
     //select
     let model = exhibitors_root::Entity::find_by_id(id)
         .one(&state.db_conn)
-        .await?;
-    if model == None {
-        return Ok((StatusCode::NOT_FOUND, "Not found.".into_response()));
-    }
-    let response: GetExhibitorsIdResponse = model.unwrap().into();
+        .await?
+        .ok_or(AppError::NotFound("exhibitor"))?;
+    let response: GetExhibitorsIdResponse = model.into();
     Ok((StatusCode::OK, Json(response).into_response()))
 }
 
@@ -345,30 +335,11 @@ struct PutExhibitorsIdPayload {
 async fn put_exhibitors_id(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    requester: Requester,
     Path(id): Path<String>,
     Json(payload): Json<PutExhibitorsIdPayload>,
 ) -> Result<(StatusCode, Response), AppError> {
-    //permission che
-    match current_user {
-        CurrentUser::Admin(_) => {}
-        CurrentUser::User(claims) => {
-            // 属しているかどうか確認
-            let model = users::Entity::find_by_id(claims.sub)
-                .one(&state.db_conn)
-                .await?;
-            if model == None {
-                return Ok((StatusCode::NOT_FOUND, "Subject not found.".into_response()));
-            }
-            if model.unwrap().exhibition_id != id {
-                // FORBIDDEN等にすると参加団体の存在が無駄に露呈してしまう
-                return Ok((StatusCode::NOT_FOUND, "Not found.".into_response()));
-            }
-        }
-        CurrentUser::None => {
-            return Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response()));
-        }
-    }
+    authorize_exhibitor::<policy::ExhibitorsWrite>(&requester, &id)?;
 
     // update
     let exhibition_name = match payload.exhibition_name {
@@ -401,3 +372,23 @@ async fn put_exhibitors_id(
 
     Ok((StatusCode::CREATED, "Created.".into_response()))
 }
+
+/// 参加団体に属するすべてのユーザーのセッションを失効させる
+#[instrument(name = "DELETE /api/v1/exhibitors/{id}/sessions", skip(state))]
+async fn delete_exhibitors_id_sessions(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    _admin: RequireAdmin<policy::UsersWrite>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Response), AppError> {
+    if exhibitors_root::Entity::find_by_id(id.clone())
+        .one(&state.db_conn)
+        .await?
+        .is_none()
+    {
+        return Err(AppError::NotFound("exhibitor"));
+    }
+    state.jwt_manager.revoke_exhibitor(&id).await?;
+    info!("all sessions of exhibitor {} revoked", id);
+    Ok((StatusCode::NO_CONTENT, ().into_response()))
+}
@@ -0,0 +1,312 @@
+use crate::entities::stored_files;
+use crate::extractors::{policy, Requester, RequireExhibitorMember};
+use crate::routes::AppState;
+use crate::util::json::Json;
+use crate::util::{AppError, AppResponse};
+use axum::extract::multipart::MultipartError;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Multipart, Path, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::Router;
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::{Alias, Expr, Func};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, NotSet, QueryFilter,
+    QuerySelect,
+};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+/// `Content-Type`が無い場合の種類
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// ダウンロード時にそのまま`Content-Type`として返す種類
+///
+/// それ以外(`text/html`や`image/svg+xml`等)はAPIのオリジンでスクリプトが実行されないよう
+/// `DEFAULT_CONTENT_TYPE`として返す。
+const SERVABLE_CONTENT_TYPES: [&str; 6] = [
+    "application/pdf",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "text/plain",
+];
+
+#[instrument(name = "init /api/v1/files")]
+pub fn init_router() -> Router<Arc<AppState>> {
+    Router::new()
+        // サイズの上限は`upload_file`で`storage.max_upload_size`を確認する
+        .route("/", post(upload_file).layer(DefaultBodyLimit::disable()))
+        .route("/{file_id}", get(download_file))
+}
+
+/// アップロードされたファイルの情報
+/// * `file_id`: ファイルのID。回答の`answer_file`で参照する
+/// * `created_at`: アップロード日時
+/// * `file_name`: アップロード時のファイル名
+/// * `content_type`: ファイルの種類
+/// * `size`: サイズ(バイト)
+#[derive(Serialize, Debug)]
+struct StoredFile {
+    file_id: Uuid,
+    created_at: DateTime<Utc>,
+    file_name: String,
+    content_type: String,
+    size: i64,
+}
+
+impl From<stored_files::Model> for StoredFile {
+    fn from(model: stored_files::Model) -> Self {
+        Self {
+            file_id: model.file_id,
+            created_at: model.created_at.into(),
+            file_name: model.file_name,
+            content_type: model.content_type,
+            size: model.size,
+        }
+    }
+}
+
+fn multipart_error(err: MultipartError, max_upload_size: usize) -> AppError {
+    if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        AppError::PayloadTooLarge(max_upload_size)
+    } else {
+        AppError::BadRequest("invalid multipart body.")
+    }
+}
+
+/// 参加団体がアップロードしたファイルの合計サイズ(バイト)
+async fn used_storage(db: &DatabaseConnection, exhibition_id: &str) -> Result<i64, AppError> {
+    let used: Option<Option<i64>> = stored_files::Entity::find()
+        .select_only()
+        .column_as(
+            Expr::expr(Func::cast_as(
+                Expr::col(stored_files::Column::Size).sum(),
+                Alias::new("bigint"),
+            )),
+            "used",
+        )
+        .filter(stored_files::Column::ExhibitionId.eq(exhibition_id))
+        .into_tuple()
+        .one(db)
+        .await?;
+    Ok(used.flatten().unwrap_or(0))
+}
+
+/// `multipart/form-data`の`file`フィールドのファイルを保存する
+///
+/// 参加団体毎の合計サイズが`storage.exhibitor_quota`を超える場合は413。
+/// 同時にアップロードされた場合は上限をわずかに超えることがある。
+#[instrument(name = "POST /api/v1/files", skip(state, multipart))]
+async fn upload_file(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    member: RequireExhibitorMember,
+    mut multipart: Multipart,
+) -> AppResponse {
+    let max_upload_size = state.max_upload_size;
+    let mut field = loop {
+        match multipart
+            .next_field()
+            .await
+            .map_err(|err| multipart_error(err, max_upload_size))?
+        {
+            Some(field) if field.name() == Some("file") => break field,
+            Some(_) => continue,
+            None => return Err(AppError::BadRequest("file is required.")),
+        }
+    };
+    let file_name = field.file_name().unwrap_or_default().to_string();
+    let content_type = field
+        .content_type()
+        .unwrap_or(DEFAULT_CONTENT_TYPE)
+        .to_string();
+    let mut data = vec![];
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|err| multipart_error(err, max_upload_size))?
+    {
+        if data.len() + chunk.len() > max_upload_size {
+            return Err(AppError::PayloadTooLarge(max_upload_size));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    let size = data.len() as i64;
+    if used_storage(&state.db_conn, &member.exhibitor.id).await? + size > state.exhibitor_quota {
+        return Err(AppError::QuotaExceeded(state.exhibitor_quota));
+    }
+
+    // 保存に失敗してもオブジェクトが取り残されないよう、先に行を追加する。
+    // 行を削除できなかった場合も`PurgeUnreferencedFiles`が削除する
+    let file_id = Uuid::new_v4();
+    let model = stored_files::ActiveModel {
+        file_id: Set(file_id),
+        created_at: NotSet,
+        exhibition_id: Set(member.exhibitor.id.clone()),
+        uploader_id: Set(member.user.id),
+        file_name: Set(file_name),
+        content_type: Set(content_type.clone()),
+        size: Set(size),
+    }
+    .insert(&state.db_conn)
+    .await?;
+    if let Err(err) = state
+        .storage
+        .put(&file_id.to_string(), &content_type, data)
+        .await
+    {
+        if let Err(err) = stored_files::Entity::delete_by_id(file_id)
+            .exec(&state.db_conn)
+            .await
+        {
+            warn!(
+                "failed to delete file {} after upload failed: {}",
+                file_id, err
+            );
+        }
+        return Err(err.into());
+    }
+    info!(
+        "file {} ({} bytes) uploaded by {}",
+        file_id, size, member.user.id
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(StoredFile::from(model)).into_response(),
+    ))
+}
+
+/// ファイルをダウンロードする。管理者とアップロードした参加団体の責任者のみ
+#[instrument(name = "GET /api/v1/files/{file_id}", skip(state))]
+async fn download_file(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    requester: Requester,
+    Path(file_id): Path<Uuid>,
+) -> AppResponse {
+    requester.require_admin_policy::<policy::ResponsesRead>()?;
+    let file = stored_files::Entity::find_by_id(file_id)
+        .one(&state.db_conn)
+        .await?
+        .ok_or(AppError::NotFound("file"))?;
+    match &requester {
+        Requester::Admin(_) => {}
+        // 他の参加団体のファイルは存在を明かさない
+        Requester::ExhibitorMember(member) if member.belongs_to(&file.exhibition_id) => {}
+        Requester::ExhibitorMember(_) => return Err(AppError::NotFound("file")),
+        Requester::Anonymous => return Err(AppError::Forbidden),
+    }
+    let data = state
+        .storage
+        .get(&file_id.to_string())
+        .await?
+        .ok_or(AppError::NotFound("file"))?;
+
+    Ok((
+        StatusCode::OK,
+        (
+            [
+                (CONTENT_TYPE, served_content_type(&file.content_type)),
+                (
+                    CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename*=UTF-8''{}",
+                        percent_encode(&file.file_name)
+                    ),
+                ),
+                (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            ],
+            data,
+        )
+            .into_response(),
+    ))
+}
+
+/// アップロード時の`Content-Type`が`SERVABLE_CONTENT_TYPES`のいずれかであればそのまま、それ以外は`DEFAULT_CONTENT_TYPE`を返す
+fn served_content_type(content_type: &str) -> String {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if SERVABLE_CONTENT_TYPES.contains(&essence.as_str()) {
+        content_type.to_string()
+    } else {
+        DEFAULT_CONTENT_TYPE.to_string()
+    }
+}
+
+/// RFC 5987の`filename*`用にエンコードする
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_unreserved_characters() {
+        assert_eq!(
+            percent_encode("Report-2025_v1.0~.pdf"),
+            "Report-2025_v1.0~.pdf"
+        );
+    }
+
+    #[test]
+    fn encodes_reserved_characters_and_spaces() {
+        assert_eq!(
+            percent_encode("a b\"c;d%e/f.pdf"),
+            "a%20b%22c%3Bd%25e%2Ff.pdf"
+        );
+        // ヘッダーを分割できないよう改行もエンコードする
+        assert_eq!(percent_encode("a\r\nb"), "a%0D%0Ab");
+    }
+
+    #[test]
+    fn encodes_utf8_bytes() {
+        assert_eq!(
+            percent_encode("企画書.pdf"),
+            "%E4%BC%81%E7%94%BB%E6%9B%B8.pdf"
+        );
+    }
+
+    #[test]
+    fn serves_allowed_content_types() {
+        assert_eq!(served_content_type("application/pdf"), "application/pdf");
+        assert_eq!(served_content_type("IMAGE/PNG"), "IMAGE/PNG");
+        assert_eq!(
+            served_content_type("text/plain; charset=utf-8"),
+            "text/plain; charset=utf-8"
+        );
+    }
+
+    #[test]
+    fn serves_other_content_types_as_octet_stream() {
+        assert_eq!(served_content_type("text/html"), DEFAULT_CONTENT_TYPE);
+        assert_eq!(served_content_type("image/svg+xml"), DEFAULT_CONTENT_TYPE);
+        assert_eq!(
+            served_content_type("text/plain-html; charset=utf-8"),
+            DEFAULT_CONTENT_TYPE
+        );
+        assert_eq!(served_content_type(""), DEFAULT_CONTENT_TYPE);
+    }
+}
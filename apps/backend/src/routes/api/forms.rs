@@ -1,15 +1,17 @@
-use crate::entities::prelude::{ExhibitorsRoot, Forms, Users};
-use crate::entities::{exhibitors_root, form_responses, forms, users};
-use crate::forms::responses::{Answer, FormResponse};
+use crate::entities::prelude::Forms;
+use crate::entities::{form_responses, forms, stored_files, users};
+use crate::extractors::{policy, Requester, RequireAdmin, RequireExhibitorMember};
+use crate::forms::responses::{FormResponse, ResponseInput};
+use crate::forms::validation;
 use crate::forms::{AccessControl, Form, Info, Item};
-use crate::middlewares::CurrentUser;
 use crate::routes::AppState;
-use crate::util::AppResponse;
+use crate::util::json::Json;
+use crate::util::{AppError, AppResponse, FieldError};
 use axum::extract::{ConnectInfo, Path, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::routing::{get, post};
-use axum::{Extension, Json, Router};
+use axum::routing::{get, put};
+use axum::Router;
 use sea_orm::ActiveValue::Set;
 use sea_orm::{
     ActiveEnum, ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait,
@@ -20,7 +22,7 @@ use serde_json::json;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tracing::{info, instrument, trace, warn};
+use tracing::{info, instrument, trace};
 use uuid::Uuid;
 
 #[instrument(name = "init /api/v1/forms")]
@@ -31,68 +33,43 @@ pub fn init_router() -> Router<Arc<AppState>> {
             "/{form_id}",
             get(get_form).put(put_form).delete(delete_form),
         )
-        .route("/{form_id}/responses", post(post_response))
+        .route(
+            "/{form_id}/responses",
+            get(get_responses).post(post_response),
+        )
+        .route("/{form_id}/responses/{response_id}", put(put_response))
+}
+
+/// フォームに回答できるロール。管理者は制限されないため`None`
+///
+/// 参加団体責任者は参加団体の種類、未ログインの場合は`"none"`。
+fn form_role(requester: &Requester) -> Option<String> {
+    match requester {
+        Requester::Admin(_) => None,
+        Requester::ExhibitorMember(member) => {
+            Some(member.exhibitor.r#type.clone().into_value().to_string())
+        }
+        Requester::Anonymous => Some("none".to_string()),
+    }
 }
 
 #[instrument(name = "GET /api/v1/forms", skip(state))]
 async fn get_forms(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    requester: Requester,
 ) -> AppResponse {
-    trace!("hello");
-    let form_models = match current_user {
-        CurrentUser::Admin(_) => Forms::find().all(&state.db_conn).await?,
-        CurrentUser::User(claims) => {
-            trace!("finding user");
-            let user = Users::find_by_id(claims.sub).one(&state.db_conn).await?;
-            let user = match user {
-                Some(user) => user,
-                None => {
-                    warn!("internal server error occurred: user doesn't exist");
-                    return Ok((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "User not found".into_response(),
-                    ));
-                }
-            };
-            trace!("finding exhibitor");
-            let exhibition_id = user.exhibition_id;
-            let exhibitor = ExhibitorsRoot::find_by_id(exhibition_id)
-                .one(&state.db_conn)
-                .await?;
-            let exhibitor = match exhibitor {
-                Some(exhibitor) => exhibitor,
-                None => {
-                    warn!("internal server error occurred: exhibitor doesn't exist");
-                    return Ok((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "exhibitor not found".into_response(),
-                    ));
-                }
-            };
-            let exhibitor_type = exhibitor.r#type.into_value().to_string();
-            trace!("finding forms");
-            let models = Forms::find().all(&state.db_conn).await?;
-            let mut filtered: Vec<forms::Model> = vec![];
-            for model in models {
-                if model.access_control_roles.contains(&exhibitor_type) {
-                    filtered.push(model)
-                }
-            }
-            filtered
-        }
-        CurrentUser::None => {
-            let models = Forms::find().all(&state.db_conn).await?;
-            let mut filtered: Vec<forms::Model> = vec![];
-            for model in models {
-                if model.access_control_roles.contains(&"none".to_string()) {
-                    filtered.push(model)
-                }
-            }
-            filtered
-        }
-    };
+    requester.require_admin_policy::<policy::FormsRead>()?;
+    let role = form_role(&requester);
+    trace!("finding forms");
+    let form_models = Forms::find()
+        .all(&state.db_conn)
+        .await?
+        .into_iter()
+        .filter(|model| match &role {
+            Some(role) => model.access_control_roles.contains(role),
+            None => true,
+        });
 
     let mut forms = vec![];
     for form_model in form_models {
@@ -114,101 +91,55 @@ struct NewForm {
 async fn post_forms(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    _admin: RequireAdmin<policy::FormsWrite>,
     Json(new_form): Json<NewForm>,
 ) -> AppResponse {
-    if let CurrentUser::Admin(_) = current_user {
-        let model = forms::ActiveModel {
-            form_id: Set(Uuid::new_v4()),
-            created_at: NotSet,
-            updated_at: NotSet,
-            info: Set(json!(new_form.info)),
-            items: Set(json!(new_form.items)),
-            access_control_roles: Set(new_form.access_control.roles),
-        };
-        let model = model.insert(&state.db_conn).await?;
-        let form = Form::from_model(&model)?;
-        info!("new form  added successfully");
-        Ok((StatusCode::ACCEPTED, Json::from(form).into_response()))
-    } else {
-        Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response()))
-    }
+    validate_items(&new_form.items)?;
+    let model = forms::ActiveModel {
+        form_id: Set(Uuid::new_v4()),
+        created_at: NotSet,
+        updated_at: NotSet,
+        info: Set(json!(new_form.info)),
+        items: Set(json!(new_form.items)),
+        access_control_roles: Set(new_form.access_control.roles),
+    };
+    let model = model.insert(&state.db_conn).await?;
+    let form = Form::from_model(&model)?;
+    info!("new form  added successfully");
+    Ok((StatusCode::ACCEPTED, Json::from(form).into_response()))
+}
+
+/// 項目の定義を検証し、エラーを`items.<item_id>`のフィールドエラーとして返す
+fn validate_items(items: &[Item]) -> Result<(), AppError> {
+    validation::validate_items(items).map_err(|errors| {
+        AppError::Validation(
+            errors
+                .into_iter()
+                .map(|error| FieldError::new(format!("items.{}", error.item_id), error.message))
+                .collect(),
+        )
+    })
 }
 
 #[instrument(name = "GET /api/v1/forms/{form_id}", skip(state))]
 async fn get_form(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    requester: Requester,
     Path(form_id): Path<Uuid>,
 ) -> AppResponse {
-    trace!("hello");
-    let form_model = match current_user {
-        CurrentUser::Admin(_) => Forms::find_by_id(form_id).one(&state.db_conn).await?,
-        CurrentUser::User(claims) => {
-            trace!("finding user");
-            let user = Users::find_by_id(claims.sub).one(&state.db_conn).await?;
-            let user = match user {
-                Some(user) => user,
-                None => {
-                    warn!("internal server error occurred: user doesn't exist");
-                    return Ok((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "User not found".into_response(),
-                    ));
-                }
-            };
-            trace!("finding exhibitor");
-            let exhibition_id = user.exhibition_id;
-            let exhibitor = ExhibitorsRoot::find_by_id(exhibition_id)
-                .one(&state.db_conn)
-                .await?;
-            let exhibitor = match exhibitor {
-                Some(exhibitor) => exhibitor,
-                None => {
-                    warn!("internal server error occurred: exhibitor doesn't exist");
-                    return Ok((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "exhibitor not found".into_response(),
-                    ));
-                }
-            };
-            let exhibitor_type = exhibitor.r#type.into_value().to_string();
-            trace!("finding forms");
-            let model = Forms::find_by_id(form_id).one(&state.db_conn).await?;
-            if model == None
-                || !model
-                    .clone()
-                    .unwrap()
-                    .access_control_roles
-                    .contains(&exhibitor_type)
-            {
-                None
-            } else {
-                model
-            }
-        }
-        CurrentUser::None => {
-            let model = Forms::find_by_id(form_id).one(&state.db_conn).await?;
-            if model == None
-                || !model
-                    .clone()
-                    .unwrap()
-                    .access_control_roles
-                    .contains(&"none".to_string())
-            {
-                None
-            } else {
-                model
-            }
-        }
-    };
-
-    if form_model == None {
-        return Ok((StatusCode::NOT_FOUND, "form not found.".into_response()));
-    }
+    requester.require_admin_policy::<policy::FormsRead>()?;
+    // 回答できないフォームは存在を明かさない
+    let form_model = Forms::find_by_id(form_id)
+        .one(&state.db_conn)
+        .await?
+        .filter(|model| match form_role(&requester) {
+            Some(role) => model.access_control_roles.contains(&role),
+            None => true,
+        })
+        .ok_or(AppError::NotFound("form"))?;
 
-    let form = Form::from_model(&form_model.unwrap())?;
+    let form = Form::from_model(&form_model)?;
 
     Ok((StatusCode::OK, Json(form).into_response()))
 }
@@ -224,163 +155,163 @@ struct EditForm {
 async fn put_form(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    _admin: RequireAdmin<policy::FormsWrite>,
     Path(form_id): Path<Uuid>,
     Json(new_form): Json<EditForm>,
 ) -> AppResponse {
-    if let CurrentUser::Admin(_) = current_user {
-        let info = match new_form.info {
-            Some(info) => Set(json!(info)),
-            None => NotSet,
-        };
-        let items = match new_form.items {
-            Some(items) => Set(json!(items)),
-            None => NotSet,
-        };
-        let access_control_roles = match new_form.access_control {
-            Some(access_control) => Set(access_control.roles),
-            None => NotSet,
-        };
-        let model = forms::ActiveModel {
-            form_id: Set(form_id),
-            created_at: NotSet,
-            updated_at: NotSet,
-            info,
-            items,
-            access_control_roles,
-        };
+    let info = match new_form.info {
+        Some(info) => Set(json!(info)),
+        None => NotSet,
+    };
+    let items = match new_form.items {
+        Some(items) => {
+            validate_items(&items)?;
+            Set(json!(items))
+        }
+        None => NotSet,
+    };
+    let access_control_roles = match new_form.access_control {
+        Some(access_control) => Set(access_control.roles),
+        None => NotSet,
+    };
+    let model = forms::ActiveModel {
+        form_id: Set(form_id),
+        created_at: NotSet,
+        updated_at: NotSet,
+        info,
+        items,
+        access_control_roles,
+    };
 
-        let model = model.update(&state.db_conn).await?;
-        let form = Form::from_model(&model)?;
-        info!("form edited successfully");
-        Ok((StatusCode::ACCEPTED, Json::from(form).into_response()))
-    } else {
-        Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response()))
-    }
+    let model = model.update(&state.db_conn).await?;
+    let form = Form::from_model(&model)?;
+    info!("form edited successfully");
+    Ok((StatusCode::ACCEPTED, Json::from(form).into_response()))
 }
 
 #[instrument(name = "POST /api/v1/forms/delete", skip(state))]
 async fn delete_form(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    _admin: RequireAdmin<policy::FormsWrite>,
     Path(form_id): Path<Uuid>,
 ) -> AppResponse {
-    if let CurrentUser::Admin(_) = current_user {
-        match forms::Entity::find_by_id(form_id)
-            .one(&state.db_conn)
-            .await?
-        {
-            Some(_) => {}
-            None => return Ok((StatusCode::NOT_FOUND, "form not found.".into_response())),
-        };
-        let res = forms::Entity::delete_by_id(form_id)
-            .exec(&state.db_conn)
-            .await?;
-        if res.rows_affected == 0 {
-            Ok((StatusCode::NOT_FOUND, "form not found.".into_response()))
-        } else {
-            Ok((StatusCode::ACCEPTED, "Accepted.".into_response()))
-        }
+    let res = forms::Entity::delete_by_id(form_id)
+        .exec(&state.db_conn)
+        .await?;
+    if res.rows_affected == 0 {
+        Err(AppError::NotFound("form"))
     } else {
-        Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response()))
+        Ok((StatusCode::ACCEPTED, "Accepted.".into_response()))
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ResponseInput {
-    answers: HashMap<String, Answer>,
+/// 参加団体責任者が回答できるフォームを取得する
+async fn find_answerable_form(
+    state: &AppState,
+    member: &RequireExhibitorMember,
+    form_id: Uuid,
+) -> Result<Form, AppError> {
+    let form = forms::Entity::find_by_id(form_id)
+        .one(&state.db_conn)
+        .await?
+        .ok_or(AppError::NotFound("form"))?;
+    if !form
+        .access_control_roles
+        .contains(&member.exhibitor.r#type.clone().into_value().to_string())
+    {
+        return Err(AppError::Forbidden);
+    }
+    Ok(Form::from_model(&form)?)
+}
+
+/// 回答をフォームの定義に対して検証し、エラーを`answers.<item_id>`のフィールドエラーとして返す
+///
+/// 回答で参照できるファイルは回答者の参加団体がアップロードしたもののみ。
+async fn validate_response(
+    state: &AppState,
+    member: &RequireExhibitorMember,
+    form: &Form,
+    input: &ResponseInput,
+) -> Result<(), AppError> {
+    let file_ids = input.file_ids();
+    let files = if file_ids.is_empty() {
+        HashMap::new()
+    } else {
+        stored_files::Entity::find()
+            .filter(stored_files::Column::FileId.is_in(file_ids))
+            .filter(stored_files::Column::ExhibitionId.eq(member.exhibitor.id.clone()))
+            .all(&state.db_conn)
+            .await?
+            .into_iter()
+            .map(|file| (file.file_id, file))
+            .collect()
+    };
+    validation::validate_response(form, input, &files).map_err(|errors| {
+        AppError::Validation(
+            errors
+                .into_iter()
+                .map(|error| FieldError::new(format!("answers.{}", error.item_id), error.message))
+                .collect(),
+        )
+    })
 }
 
 #[instrument(name = "POST /api/v1/forms/{form_id}/responses", skip(state))]
 async fn post_response(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    member: RequireExhibitorMember,
     Path(form_id): Path<Uuid>,
     Json(new_response): Json<ResponseInput>,
 ) -> AppResponse {
-    if let CurrentUser::User(claims) = current_user {
-        // 回答権があるかどうか確認
-        let user = match users::Entity::find_by_id(claims.sub)
-            .one(&state.db_conn)
-            .await?
-        {
-            Some(model) => model,
-            None => {
-                warn!("Subject not found.");
-                return Ok((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "user not found.".into_response(),
-                ));
-            }
-        };
-        let exhibitor = match exhibitors_root::Entity::find_by_id(user.exhibition_id)
-            .one(&state.db_conn)
-            .await?
-        {
-            Some(model) => model,
-            None => {
-                warn!("Exhibitor not found.");
-                return Ok((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "exhibitor not found.".into_response(),
-                ));
-            }
-        };
-        let form = match forms::Entity::find_by_id(form_id)
-            .one(&state.db_conn)
-            .await?
-        {
-            Some(model) => model,
-            None => {
-                return Ok((StatusCode::NOT_FOUND, "form not found.".into_response()));
-            }
-        };
-        if !form
-            .access_control_roles
-            .contains(&exhibitor.r#type.into_value().to_string())
-        {
-            return Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response()));
-        }
+    let form = find_answerable_form(&state, &member, form_id).await?;
+    validate_response(&state, &member, &form, &new_response).await?;
 
-        let response = form_responses::ActiveModel {
-            response_id: Set(Uuid::new_v4()),
-            created_at: ActiveValue::NotSet,
-            updated_at: ActiveValue::NotSet,
-            form_id: Set(form_id),
-            respondent_id: Set(claims.sub),
-            answers: Set(json!(new_response.answers)),
-        };
-        let model = response.insert(&state.db_conn).await?;
-        let response = FormResponse::from_model(&model)?;
-        info!("new form was added by {}", claims.sub);
-        Ok((StatusCode::ACCEPTED, Json::from(response).into_response()))
-    } else {
-        Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response()))
-    }
+    let response = form_responses::ActiveModel {
+        response_id: Set(Uuid::new_v4()),
+        created_at: ActiveValue::NotSet,
+        updated_at: ActiveValue::NotSet,
+        form_id: Set(form_id),
+        respondent_id: Set(member.user.id),
+        answers: Set(json!(new_response.answers)),
+    };
+    let model = response.insert(&state.db_conn).await?;
+    let response = FormResponse::from_model(&model)?;
+    info!("new form was added by {}", member.user.id);
+    Ok((StatusCode::ACCEPTED, Json::from(response).into_response()))
 }
 
 #[instrument(name = "GET /api/v1/forms/{form_id}/responses", skip(state))]
 async fn get_responses(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-    Extension(current_user): Extension<CurrentUser>,
+    requester: Requester,
     Path(form_id): Path<Uuid>,
 ) -> AppResponse {
-    let responses = match current_user {
-        CurrentUser::User(claims) => form_responses::Entity::find()
-            .filter(form_responses::Column::RespondentId.eq(claims.sub)),
-        CurrentUser::Admin(_) => form_responses::Entity::find(),
-        CurrentUser::None => {
-            return Ok((StatusCode::FORBIDDEN, "Access forbidden.".into_response()));
+    requester.require_admin_policy::<policy::ResponsesRead>()?;
+    let query = form_responses::Entity::find().filter(form_responses::Column::FormId.eq(form_id));
+    let responses = match requester {
+        Requester::ExhibitorMember(member) => {
+            find_answerable_form(&state, &member, form_id).await?;
+            query
+                .inner_join(users::Entity)
+                .filter(users::Column::ExhibitionId.eq(member.exhibitor.id.clone()))
+        }
+        Requester::Admin(_) => {
+            Forms::find_by_id(form_id)
+                .one(&state.db_conn)
+                .await?
+                .ok_or(AppError::NotFound("form"))?;
+            query
         }
+        Requester::Anonymous => return Err(AppError::Forbidden),
     }
     .all(&state.db_conn)
     .await?;
     let responses = responses
         .iter()
-        .map(|response| FormResponse::from_model(response))
+        .map(FormResponse::from_model)
         .collect::<Result<Vec<FormResponse>, _>>()?;
     Ok((StatusCode::OK, Json::from(responses).into_response()))
 }
@@ -390,21 +321,36 @@ struct ResponseParams {
     form_id: Uuid,
     response_id: Uuid,
 }
-// #[instrument(name = "POST /api/v1/forms/{form_id}/responses/{response_id}", skip(state))]
-// async fn put_response(
-//     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-//     State(state): State<Arc<AppState>>,
-//     Extension(user_id): Extension<CurrentUser>,
-//     Path(params): Path<ResponseParams>,
-//     Json(new_response): Json<ResponseInput>,
-// ) {
-//     form_responses::ActiveModel {
-//         response_id: Set(params.response_id),
-//         created_at: NotSet,
-//         updated_at: NotSet,
-//         form_id: Set(params.form_id),
-//         respondent_id:,
-//         answers: Default::default(),
-//     }
-//     form_responses::Entity::update()
-// }
+
+/// 回答を更新する。同じ参加団体の責任者が作成した回答のみ更新できる
+#[instrument(
+    name = "PUT /api/v1/forms/{form_id}/responses/{response_id}",
+    skip(state)
+)]
+async fn put_response(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    member: RequireExhibitorMember,
+    Path(params): Path<ResponseParams>,
+    Json(new_response): Json<ResponseInput>,
+) -> AppResponse {
+    let form = find_answerable_form(&state, &member, params.form_id).await?;
+    let response = form_responses::Entity::find_by_id(params.response_id)
+        .filter(form_responses::Column::FormId.eq(params.form_id))
+        .inner_join(users::Entity)
+        .filter(users::Column::ExhibitionId.eq(member.exhibitor.id.clone()))
+        .one(&state.db_conn)
+        .await?
+        .ok_or(AppError::NotFound("response"))?;
+    validate_response(&state, &member, &form, &new_response).await?;
+
+    let mut response: form_responses::ActiveModel = response.into();
+    response.answers = Set(json!(new_response.answers));
+    let model = response.update(&state.db_conn).await?;
+    let response = FormResponse::from_model(&model)?;
+    info!(
+        "form response {} was updated by {}",
+        params.response_id, member.user.id
+    );
+    Ok((StatusCode::ACCEPTED, Json::from(response).into_response()))
+}
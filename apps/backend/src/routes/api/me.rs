@@ -0,0 +1,163 @@
+use crate::entities::sea_orm_active_enums::LoginOutcome;
+use crate::entities::users;
+use crate::extractors::RequireExhibitorMember;
+use crate::routes::AppState;
+use crate::util::client_info::ClientInfo;
+use crate::util::json::Json;
+use crate::util::{lockout, totp};
+use crate::util::{AppError, AppResponse};
+use axum::extract::{ConnectInfo, State};
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::Router;
+use http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{debug, info, instrument, warn};
+
+#[instrument(name = "init /api/v1/me")]
+pub fn init_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/totp", post(post_totp).delete(delete_totp))
+        .route("/totp/confirm", post(post_totp_confirm))
+        .route("/totp/recovery_codes", post(post_totp_recovery_codes))
+}
+
+#[derive(Serialize, Debug)]
+struct PostTotpResponse {
+    secret: String,
+    otpauth_url: String,
+}
+
+/// 二要素認証の登録を開始し、認証アプリに登録するシークレットを返す
+#[instrument(name = "POST /api/v1/me/totp", skip(state))]
+async fn post_totp(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    RequireExhibitorMember { user, .. }: RequireExhibitorMember,
+) -> AppResponse {
+    match totp::begin_enrollment(&state.db_conn, &state.totp_cipher, &user).await? {
+        Some(enrollment) => Ok((
+            StatusCode::CREATED,
+            Json(PostTotpResponse {
+                secret: enrollment.secret,
+                otpauth_url: enrollment.otpauth_url,
+            })
+            .into_response(),
+        )),
+        None => Err(AppError::Conflict("totp already enabled.")),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TotpCodePayload {
+    code: String,
+}
+
+#[derive(Serialize, Debug)]
+struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+/// 認証アプリのコードを確認して二要素認証を有効化し、リカバリーコードを返す
+#[instrument(name = "POST /api/v1/me/totp/confirm", skip(state, payload))]
+async fn post_totp_confirm(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    RequireExhibitorMember { user, .. }: RequireExhibitorMember,
+    Json(payload): Json<TotpCodePayload>,
+) -> AppResponse {
+    match totp::confirm_enrollment(
+        &state.db_conn,
+        &state.totp_cipher,
+        &user,
+        payload.code.trim(),
+    )
+    .await?
+    {
+        totp::Confirmation::Confirmed(recovery_codes) => Ok((
+            StatusCode::OK,
+            Json(RecoveryCodesResponse { recovery_codes }).into_response(),
+        )),
+        totp::Confirmation::NotEnrolled => Err(AppError::NotFound("totp enrollment")),
+        totp::Confirmation::AlreadyEnabled => Err(AppError::Conflict("totp already enabled.")),
+        totp::Confirmation::InvalidCode => Err(AppError::BadRequest("invalid code.")),
+    }
+}
+
+/// リカバリーコードを再発行する。以前のリカバリーコードは無効になる
+#[instrument(
+    name = "POST /api/v1/me/totp/recovery_codes",
+    skip(state, headers, payload)
+)]
+async fn post_totp_recovery_codes(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    RequireExhibitorMember { user, .. }: RequireExhibitorMember,
+    Json(payload): Json<TotpCodePayload>,
+) -> AppResponse {
+    let client = ClientInfo::new(&addr, &headers);
+    verify_code(&state, &user, &client, &payload.code).await?;
+    let recovery_codes = totp::regenerate_recovery_codes(&state.db_conn, user.id).await?;
+    info!("totp recovery codes regenerated for {}", user.id);
+    Ok((
+        StatusCode::OK,
+        Json(RecoveryCodesResponse { recovery_codes }).into_response(),
+    ))
+}
+
+/// 二要素認証を無効化する
+#[instrument(name = "DELETE /api/v1/me/totp", skip(state, headers, payload))]
+async fn delete_totp(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    RequireExhibitorMember { user, .. }: RequireExhibitorMember,
+    Json(payload): Json<TotpCodePayload>,
+) -> AppResponse {
+    let client = ClientInfo::new(&addr, &headers);
+    verify_code(&state, &user, &client, &payload.code).await?;
+    totp::disable(&state.db_conn, user.id).await?;
+    info!("totp disabled by {}", user.id);
+    Ok((StatusCode::NO_CONTENT, ().into_response()))
+}
+
+/// 二要素認証のコードを検証する
+///
+/// アクセストークンが盗まれた場合にコードを総当たりされないよう、ログインと同じく失敗を数え、
+/// 失敗が続いた場合はアカウントをロックする。ロック中は`429`を返す。
+async fn verify_code(
+    state: &AppState,
+    user: &users::Model,
+    client: &ClientInfo,
+    code: &str,
+) -> Result<(), AppError> {
+    if let Some(locked_until) = lockout::locked_until(user) {
+        debug!("429 Too Many Requests(locked until {})", locked_until);
+        return Err(AppError::TooManyRequests(lockout::retry_after(
+            locked_until,
+        )));
+    }
+    if !totp::verify(&state.db_conn, &state.totp_cipher, user, code).await? {
+        debug!("400 Bad Request(totp code)");
+        if let Err(err) = lockout::record(
+            &state.db_conn,
+            Some(user.id),
+            &user.m_address,
+            client,
+            LoginOutcome::InvalidTotp,
+        )
+        .await
+        {
+            warn!("failed to record login attempt: {}", err);
+        }
+        lockout::register_failure(&state.db_conn, &state.web.auth.lockout, user.id).await?;
+        return Err(AppError::BadRequest("invalid code."));
+    }
+    if user.failed_login_count > 0 {
+        lockout::reset(&state.db_conn, user.id).await?;
+    }
+    Ok(())
+}
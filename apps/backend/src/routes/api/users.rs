@@ -0,0 +1,283 @@
+use crate::entities::{exhibitors_root, login_attempts, users};
+use crate::extractors::{policy, RequireAdmin};
+use crate::routes::AppState;
+use crate::util::activation;
+use crate::util::json::Json;
+use crate::util::lockout;
+use crate::util::totp;
+use crate::util::{AppError, AppResponse};
+use axum::extract::{ConnectInfo, Path, State};
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, post};
+use axum::Router;
+use chrono::{DateTime, FixedOffset, Utc};
+use http::StatusCode;
+use sea_orm::{
+    ActiveEnum, ColumnTrait, Condition, EntityTrait, QueryFilter, QueryOrder, QuerySelect,
+    TransactionTrait,
+};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{info, instrument};
+use uuid::Uuid;
+
+#[instrument(name = "init /api/v1/users")]
+pub fn init_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/{user_id}/activation_code",
+            post(post_users_id_activation_code),
+        )
+        .route("/{user_id}/totp", delete(delete_users_id_totp))
+        .route("/lockouts", get(get_users_lockouts))
+        .route(
+            "/{user_id}/lockout",
+            get(get_users_id_lockout).delete(delete_users_id_lockout),
+        )
+        .route(
+            "/{user_id}/sessions",
+            get(get_users_id_sessions).delete(delete_users_id_sessions),
+        )
+}
+
+/// `GET /api/v1/users/{user_id}/lockout`で返すログイン試行の件数
+const LOGIN_ATTEMPTS_LIMIT: u64 = 50;
+
+#[derive(Serialize, Debug)]
+struct PostUsersIdActivationCodeResponse {
+    expires_at: String,
+}
+
+/// アクティベーションコードを再発行し、ユーザーにメールで送る。以前に発行されたコードは無効になる。
+/// コードはレスポンスには含めない
+#[instrument(name = "POST /api/v1/users/{user_id}/activation_code", skip(state))]
+async fn post_users_id_activation_code(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    _admin: RequireAdmin<policy::UsersWrite>,
+    Path(user_id): Path<Uuid>,
+) -> AppResponse {
+    let user = match users::Entity::find_by_id(user_id)
+        .one(&state.db_conn)
+        .await?
+    {
+        Some(user) => user,
+        None => return Err(AppError::NotFound("user")),
+    };
+    if user.password_hash.is_some() {
+        return Err(AppError::Conflict("user already activated."));
+    }
+
+    let exhibitor_name = exhibitors_root::Entity::find_by_id(user.exhibition_id.clone())
+        .one(&state.db_conn)
+        .await?
+        .map(|exhibitor| exhibitor.exhibitor_name)
+        .unwrap_or_default();
+
+    let txn = state.db_conn.begin().await?;
+    let expires_at = activation::issue_and_mail_code(
+        &txn,
+        &state.mailer,
+        &state.web.server.base_url,
+        &user,
+        &exhibitor_name,
+        state.web.auth.activation.code_expire_time,
+    )
+    .await?;
+    txn.commit().await?;
+    info!("activation code reissued for {}", user.id);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(PostUsersIdActivationCodeResponse {
+            expires_at: expires_at.to_string(),
+        })
+        .into_response(),
+    ))
+}
+
+/// ユーザーの二要素認証を無効化する。認証アプリを紛失し、リカバリーコードも使えない場合に使う
+#[instrument(name = "DELETE /api/v1/users/{user_id}/totp", skip(state))]
+async fn delete_users_id_totp(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    _admin: RequireAdmin<policy::UsersWrite>,
+    Path(user_id): Path<Uuid>,
+) -> AppResponse {
+    if !totp::disable(&state.db_conn, user_id).await? {
+        return Err(AppError::NotFound("totp"));
+    }
+    info!("totp reset for {}", user_id);
+    Ok((StatusCode::NO_CONTENT, ().into_response()))
+}
+
+#[derive(Serialize, Debug)]
+struct Lockout {
+    user_id: Uuid,
+    m_address: String,
+    failed_login_count: i32,
+    locked_until: Option<DateTime<FixedOffset>>,
+}
+
+impl From<users::Model> for Lockout {
+    fn from(user: users::Model) -> Self {
+        Self {
+            user_id: user.id,
+            m_address: user.m_address,
+            failed_login_count: user.failed_login_count,
+            locked_until: user.locked_until,
+        }
+    }
+}
+
+/// ロック中、またはログインの失敗が続いているユーザーの一覧を返す
+#[instrument(name = "GET /api/v1/users/lockouts", skip(state))]
+async fn get_users_lockouts(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    _admin: RequireAdmin<policy::UsersRead>,
+) -> AppResponse {
+    let lockouts = users::Entity::find()
+        .filter(
+            Condition::any()
+                .add(users::Column::FailedLoginCount.gt(0))
+                .add(users::Column::LockedUntil.gt(Utc::now())),
+        )
+        .order_by_desc(users::Column::FailedLoginCount)
+        .all(&state.db_conn)
+        .await?
+        .into_iter()
+        .map(Lockout::from)
+        .collect::<Vec<_>>();
+    Ok((StatusCode::OK, Json(lockouts).into_response()))
+}
+
+#[derive(Serialize, Debug)]
+struct LoginAttempt {
+    created_at: DateTime<FixedOffset>,
+    ip: String,
+    user_agent: Option<String>,
+    /// `SUCCESS`, `INVALID_PASSWORD`等
+    outcome: String,
+}
+
+#[derive(Serialize, Debug)]
+struct GetUsersIdLockoutResponse {
+    #[serde(flatten)]
+    lockout: Lockout,
+    login_attempts: Vec<LoginAttempt>,
+}
+
+/// ユーザーのロックの状態と、最近のログイン試行を返す
+#[instrument(name = "GET /api/v1/users/{user_id}/lockout", skip(state))]
+async fn get_users_id_lockout(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    _admin: RequireAdmin<policy::UsersRead>,
+    Path(user_id): Path<Uuid>,
+) -> AppResponse {
+    let user = match users::Entity::find_by_id(user_id)
+        .one(&state.db_conn)
+        .await?
+    {
+        Some(user) => user,
+        None => return Err(AppError::NotFound("user")),
+    };
+    let login_attempts = login_attempts::Entity::find()
+        .filter(login_attempts::Column::UserId.eq(user_id))
+        .order_by_desc(login_attempts::Column::CreatedAt)
+        .limit(LOGIN_ATTEMPTS_LIMIT)
+        .all(&state.db_conn)
+        .await?
+        .into_iter()
+        .map(|attempt| LoginAttempt {
+            created_at: attempt.created_at,
+            ip: attempt.ip,
+            user_agent: attempt.user_agent,
+            outcome: attempt.outcome.to_value(),
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(GetUsersIdLockoutResponse {
+            lockout: user.into(),
+            login_attempts,
+        })
+        .into_response(),
+    ))
+}
+
+/// ユーザーのロックを解除し、失敗した回数をリセットする
+#[instrument(name = "DELETE /api/v1/users/{user_id}/lockout", skip(state))]
+async fn delete_users_id_lockout(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    _admin: RequireAdmin<policy::UsersWrite>,
+    Path(user_id): Path<Uuid>,
+) -> AppResponse {
+    if users::Entity::find_by_id(user_id)
+        .one(&state.db_conn)
+        .await?
+        .is_none()
+    {
+        return Err(AppError::NotFound("user"));
+    }
+    lockout::reset(&state.db_conn, user_id).await?;
+    info!("lockout cleared for {}", user_id);
+    Ok((StatusCode::NO_CONTENT, ().into_response()))
+}
+
+#[derive(Serialize, Debug)]
+struct Session {
+    id: Uuid,
+    created_at: Option<DateTime<FixedOffset>>,
+    last_used_at: DateTime<FixedOffset>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+/// ユーザーの有効なセッションの一覧を返す
+#[instrument(name = "GET /api/v1/users/{user_id}/sessions", skip(state))]
+async fn get_users_id_sessions(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    _admin: RequireAdmin<policy::UsersWrite>,
+    Path(user_id): Path<Uuid>,
+) -> AppResponse {
+    let sessions = state
+        .jwt_manager
+        .sessions(user_id)
+        .await?
+        .into_iter()
+        .map(|family| Session {
+            id: family.family_id,
+            created_at: family.created_at,
+            last_used_at: family.last_used_at,
+            ip: family.ip,
+            user_agent: family.user_agent,
+        })
+        .collect::<Vec<_>>();
+    Ok((StatusCode::OK, Json(sessions).into_response()))
+}
+
+/// ユーザーのすべてのセッションを失効させる
+#[instrument(name = "DELETE /api/v1/users/{user_id}/sessions", skip(state))]
+async fn delete_users_id_sessions(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    _admin: RequireAdmin<policy::UsersWrite>,
+    Path(user_id): Path<Uuid>,
+) -> AppResponse {
+    if users::Entity::find_by_id(user_id)
+        .one(&state.db_conn)
+        .await?
+        .is_none()
+    {
+        return Err(AppError::NotFound("user"));
+    }
+    state.jwt_manager.revoke_all(user_id).await?;
+    info!("all sessions of user {} revoked", user_id);
+    Ok((StatusCode::NO_CONTENT, ().into_response()))
+}
@@ -1,63 +1,87 @@
 use crate::entities::prelude::Users;
-use crate::entities::{revoked_refresh_tokens, users};
-use crate::routes::{AppState, AuthSession};
+use crate::entities::sea_orm_active_enums::LoginOutcome;
+use crate::entities::{password_reset_tokens, users};
+use crate::mailer::template::Template;
+use crate::middlewares::CurrentUser;
+use crate::routes::AppState;
+use crate::util::activation;
+use crate::util::auth_session::AuthSession;
+use crate::util::client_info::ClientInfo;
+use crate::util::json::Json;
 use crate::util::jwt;
+use crate::util::lockout;
 use crate::util::oidc::OIDCClient;
-use crate::util::sha::{digest, stretch_with_salt};
-use anyhow::Result;
-use axum::extract::{ConnectInfo, State};
+use crate::util::password::Verification;
+use crate::util::session_cookie;
+use crate::util::token;
+use crate::util::totp;
+use crate::util::{AppError, AppResponse};
+use anyhow::{anyhow, Result};
+use axum::extract::{ConnectInfo, Path, State};
 use axum::http::{HeaderMap, StatusCode};
-use axum::routing::{get, post};
-use axum::{Json, Router};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, MethodRouter};
+use axum::{Extension, Router};
+use axum_extra::extract::CookieJar;
 use axum_gcra::gcra::Quota;
 use axum_gcra::real_ip::RealIp;
 use axum_gcra::RateLimitLayer;
+use chrono::{DateTime, FixedOffset, Utc};
 use http::HeaderValue;
 use oauth2::{
-    AccessToken, AuthorizationCode, CsrfToken,
-    PkceCodeChallenge, RefreshToken, Scope, TokenResponse,
+    AccessToken, AuthorizationCode, CsrfToken, PkceCodeChallenge, RefreshToken, Scope,
+    StandardRevocableToken, TokenResponse,
 };
-use openidconnect::core::CoreAuthenticationFlow;
-use openidconnect::Nonce;
+use openidconnect::core::{CoreAuthenticationFlow, CoreIdToken};
+use openidconnect::{LogoutRequest, Nonce, PostLogoutRedirectUrl};
 use reqwest::Client;
+use sea_orm::sea_query::Expr;
 use sea_orm::ActiveValue::Set;
 use sea_orm::QueryFilter;
-use sea_orm::{ActiveModelTrait, EntityTrait};
+use sea_orm::{ActiveModelTrait, EntityTrait, NotSet, TransactionTrait};
 use sea_orm::{ColumnTrait, IntoActiveModel};
 use serde::{Deserialize, Serialize};
 use std::cmp::PartialEq;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, instrument, warn};
+use uuid::Uuid;
 
 #[instrument(name = "init /auth")]
 pub fn init_router() -> Router<Arc<AppState>> {
     Router::new()
+        .route("/v1/activate", rate_limited(post(activate)))
+        .route("/v1/login", rate_limited(post(login)))
+        .route("/v1/login/totp", rate_limited(post(login_totp)))
+        .route("/v1/refresh", rate_limited(post(refresh)))
+        .route("/v1/reset", rate_limited(post(reset)))
+        .route("/v1/revoke", rate_limited(post(revoke)))
+        .route("/v1/password/forgot", rate_limited(post(password_forgot)))
         .route(
-            "/v1/activate",
-            post(activate).route_layer(
-                RateLimitLayer::<RealIp>::builder()
-                    .with_default_quota(Quota::simple(Duration::from_secs(10)))
-                    .with_global_fallback(true)
-                    .with_gc_interval(Duration::from_secs(5))
-                    .default_handle_error(),
-            ),
-        )
-        .route(
-            "/v1/login",
-            post(login).route_layer(
-                RateLimitLayer::<RealIp>::builder()
-                    .with_default_quota(Quota::simple(Duration::from_secs(10)))
-                    .with_global_fallback(true)
-                    .with_gc_interval(Duration::from_secs(5))
-                    .default_handle_error(),
-            ),
+            "/v1/password/complete",
+            rate_limited(post(password_complete)),
         )
+        .route("/v1/sessions", get(get_sessions).delete(delete_sessions))
+        .route("/v1/sessions/{session_id}", delete(delete_session))
         .route("/v1/admin/login", get(admin_login))
         .route("/v1/admin/redirect", post(admin_redirect))
+        .route("/v1/admin/refresh", post(admin_refresh))
+        .route("/v1/admin/logout", post(admin_logout))
+}
+
+/// IPアドレス毎に10秒に1回までに制限する
+fn rate_limited(route: MethodRouter<Arc<AppState>>) -> MethodRouter<Arc<AppState>> {
+    route.route_layer(
+        RateLimitLayer::<RealIp>::builder()
+            .with_default_quota(Quota::simple(Duration::from_secs(10)))
+            .with_global_fallback(true)
+            .with_gc_interval(Duration::from_secs(5))
+            .default_handle_error(),
+    )
 }
 
 #[derive(Serialize, Deserialize)]
@@ -74,64 +98,46 @@ async fn activate(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
     Json(payload): Json<ActivatePayload>,
-) -> StatusCode {
-    let right_token = stretch_with_salt(
-        payload.m_address.to_string().as_str(),
-        state.web.auth.activation_salt.as_str(),
-        2_i32.pow(state.web.auth.stretch_cost as u32),
-    )
-    .await;
-
-    if digest(&*payload.token) == digest(&*right_token) {
-        //文字列比較の計算時間からトークンを推測されないようにdigestしてから比較
-        let user = match Users::find()
-            .filter(users::Column::MAddress.eq(payload.m_address.to_string()))
-            .one(&state.db_conn)
-            .await
-        {
-            Ok(Some(user)) => user,
-            Ok(None) => {
-                debug!("404 Not Found");
-                return StatusCode::NOT_FOUND;
-            }
-            Err(err) => {
-                warn!("internal server error occurred while finding user: {}", err);
-                return StatusCode::INTERNAL_SERVER_ERROR;
-            }
-        };
+) -> AppResponse {
+    let Some(user) = Users::find()
+        .filter(users::Column::MAddress.eq(payload.m_address.to_string()))
+        .one(&state.db_conn)
+        .await?
+    else {
+        debug!("401 Unauthorized(user)");
+        return Err(AppError::Unauthorized("invalid activation code."));
+    };
 
-        //すでに有効化されているかどうかを確認
-        if user.password_hash.is_some() {
-            debug!("409 Conflict");
-            return StatusCode::CONFLICT;
-        }
+    activate_user(&state, user, payload.token, payload.password).await?;
+    Ok((StatusCode::OK, ().into_response()))
+}
 
-        let password_salt = (&user.password_salt).to_string();
+/// アクティベーションコードを消費し、パスワードを設定する
+async fn activate_user(
+    state: &AppState,
+    user: users::Model,
+    code: String,
+    password: String,
+) -> Result<(), AppError> {
+    let password_hash = state.password_manager.hash(password).await?;
 
-        let mut user: users::ActiveModel = user.into();
+    let txn = state.db_conn.begin().await?;
+    if !activation::consume_code(&txn, user.id, &code).await? {
+        debug!("401 Unauthorized(code)");
+        return Err(AppError::Unauthorized("invalid activation code."));
+    }
 
-        user.password_hash = Set(Some(
-            stretch_with_salt(
-                payload.password.to_string().as_str(),
-                &*password_salt,
-                2_i32.pow(state.web.auth.stretch_cost as u32),
-            )
-            .await,
-        ));
-        match user.update(&state.db_conn).await {
-            Ok(_) => StatusCode::OK,
-            Err(err) => {
-                warn!(
-                    "internal server error occurred while updating user: {}",
-                    err
-                );
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
-        }
-    } else {
-        debug!("401 Unauthorized");
-        StatusCode::UNAUTHORIZED
+    //すでに有効化されているかどうかを確認
+    if user.password_hash.is_some() {
+        debug!("409 Conflict");
+        return Err(AppError::Conflict("account already activated."));
     }
+
+    let mut user: users::ActiveModel = user.into();
+    user.password_hash = Set(Some(password_hash));
+    user.update(&txn).await?;
+    txn.commit().await?;
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -140,102 +146,305 @@ struct LoginPayload {
     password: String,
 }
 
-#[instrument(name = "/auth/v1/login", fields(payload.m_address = %payload.m_address), skip(payload, state))]
+#[instrument(name = "/auth/v1/login", fields(payload.m_address = %payload.m_address), skip(payload, state, headers))]
 async fn login(
-    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
     Json(payload): Json<LoginPayload>,
-) -> Result<Json<jwt::Tokens>, StatusCode> {
-    let user = match Users::find()
-        .filter(users::Column::MAddress.eq(payload.m_address))
+) -> AppResponse {
+    let client = ClientInfo::new(&addr, &headers);
+    let Some(user) = Users::find()
+        .filter(users::Column::MAddress.eq(payload.m_address.clone()))
         .one(&state.db_conn)
-        .await
-    {
-        Ok(Some(user)) => user,
-        Ok(None) => {
-            debug!("401 Unauthorized(user)");
-            return Err(StatusCode::UNAUTHORIZED);
-        }
-        Err(err) => {
-            warn!("internal server error occurred while finding user: {}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+        .await?
+    else {
+        debug!("401 Unauthorized(user)");
+        record_attempt(
+            &state,
+            None,
+            &payload.m_address,
+            &client,
+            LoginOutcome::UnknownUser,
+        )
+        .await;
+        return Err(AppError::Unauthorized(INVALID_CREDENTIALS));
     };
 
-    let prompted_hash = state
-        .sha_manager
-        .stretch_with_salt(
-            payload.password.to_string().as_str(),
-            user.password_salt.as_str(),
+    // ロック中であることを返すとアカウントの存在が分かるため、資格情報の誤りと区別しない
+    if let Some(locked_until) = lockout::locked_until(&user) {
+        debug!("401 Unauthorized(locked until {})", locked_until);
+        record_attempt(
+            &state,
+            Some(user.id),
+            &user.m_address,
+            &client,
+            LoginOutcome::Locked,
         )
         .await;
+        return Err(AppError::Unauthorized(INVALID_CREDENTIALS));
+    }
 
-    let password_hash = match user.password_hash {
-        Some(hash) => hash,
-        None => {
-            debug!("401 Unauthorized(not activated)");
-            return Err(StatusCode::UNAUTHORIZED);
-        }
+    let Some(password_hash) = user.password_hash.clone() else {
+        debug!("401 Unauthorized(not activated)");
+        record_attempt(
+            &state,
+            Some(user.id),
+            &user.m_address,
+            &client,
+            LoginOutcome::NotActivated,
+        )
+        .await;
+        return Err(AppError::Unauthorized(INVALID_CREDENTIALS));
     };
 
-    if digest(&*prompted_hash) == digest(&*password_hash) {
-        match state.jwt_manager.issue_tokens(user.id) {
-            Ok(tokens) => Ok(Json(tokens)),
-            Err(err) => {
-                warn!("internal server error while generating tokens: {}", err);
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
+    match state
+        .password_manager
+        .verify(payload.password.clone(), &user.password_salt, password_hash)
+        .await?
+    {
+        Verification::Valid => {}
+        Verification::ValidNeedsRehash => {
+            // 旧形式のハッシュを現在の設定で再計算する。失敗してもログインは継続する
+            if let Err(err) = rehash_password(&state, user.clone(), payload.password).await {
+                warn!("failed to rehash password: {}", err);
             }
         }
-    } else {
-        debug!("401 Unauthorized(password)");
-        Err(StatusCode::UNAUTHORIZED)
+        Verification::Invalid => {
+            debug!("401 Unauthorized(password)");
+            register_failure(&state, &user, &client, LoginOutcome::InvalidPassword).await?;
+            return Err(AppError::Unauthorized(INVALID_CREDENTIALS));
+        }
+    }
+
+    // 二要素認証が有効な場合はコードの入力を待つ
+    if totp::is_enabled(&state.db_conn, user.id).await? {
+        record_attempt(
+            &state,
+            Some(user.id),
+            &user.m_address,
+            &client,
+            LoginOutcome::MfaRequired,
+        )
+        .await;
+        let mfa_token = state.jwt_manager.issue_mfa_token(user.id)?;
+        return Ok((
+            StatusCode::OK,
+            Json(MfaRequiredResponse { mfa_token }).into_response(),
+        ));
     }
+
+    register_success(&state, &user, &client).await?;
+    let tokens = state.jwt_manager.issue_tokens(user.id, &client).await?;
+    Ok((StatusCode::OK, tokens_response(&state, jar, tokens)))
 }
 
+/// ユーザーの存在が露呈しないよう、ログインの失敗理由は区別しない
+const INVALID_CREDENTIALS: &str = "invalid credentials.";
+
 #[derive(Serialize, Deserialize)]
-struct RefreshPayload {
-    refresh_token: String,
+struct MfaRequiredResponse {
+    mfa_token: String,
 }
+
 #[derive(Serialize, Deserialize)]
-struct RefreshResponse {
-    access_token: String,
+struct LoginTotpPayload {
+    mfa_token: String,
+    code: String,
 }
 
-#[instrument(name = "/auth/v1/refresh", skip(state, payload))]
+/// `login`で発行された`mfa_token`と、認証アプリのコードまたはリカバリーコードを検証し、トークンを発行する
+#[instrument(name = "/auth/v1/login/totp", skip(state, headers, payload))]
+async fn login_totp(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Json(payload): Json<LoginTotpPayload>,
+) -> AppResponse {
+    let client = ClientInfo::new(&addr, &headers);
+    let claims = match state.jwt_manager.decode(payload.mfa_token.as_str()) {
+        Ok(token) => token.claims,
+        Err(err) => {
+            debug!("token decoding failed: {:?}", err);
+            return Err(AppError::Unauthorized(INVALID_TOKEN));
+        }
+    };
+    if !state.jwt_manager.is_mfa_token_valid(&claims) {
+        debug!("401 Unauthorized(mfa token invalid)");
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
+    }
+
+    let Some(user) = Users::find_by_id(claims.sub).one(&state.db_conn).await? else {
+        debug!("401 Unauthorized(user)");
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
+    };
+
+    // `login`と同じく、ロック中であることはコードの誤りと区別しない
+    if let Some(locked_until) = lockout::locked_until(&user) {
+        debug!("401 Unauthorized(locked until {})", locked_until);
+        record_attempt(
+            &state,
+            Some(user.id),
+            &user.m_address,
+            &client,
+            LoginOutcome::Locked,
+        )
+        .await;
+        return Err(AppError::Unauthorized(INVALID_CODE));
+    }
+
+    if !totp::verify(&state.db_conn, &state.totp_cipher, &user, &payload.code).await? {
+        debug!("401 Unauthorized(totp code)");
+        register_failure(&state, &user, &client, LoginOutcome::InvalidTotp).await?;
+        return Err(AppError::Unauthorized(INVALID_CODE));
+    }
+
+    register_success(&state, &user, &client).await?;
+    let tokens = state.jwt_manager.issue_tokens(user.id, &client).await?;
+    Ok((StatusCode::OK, tokens_response(&state, jar, tokens)))
+}
+
+const INVALID_TOKEN: &str = "invalid or expired token.";
+
+const INVALID_CODE: &str = "invalid code.";
+
+/// ログイン試行を記録する。記録に失敗してもログインの処理は継続する
+async fn record_attempt(
+    state: &AppState,
+    user_id: Option<Uuid>,
+    m_address: &str,
+    client: &ClientInfo,
+    outcome: LoginOutcome,
+) {
+    if let Err(err) = lockout::record(&state.db_conn, user_id, m_address, client, outcome).await {
+        warn!("failed to record login attempt: {}", err);
+    }
+}
+
+/// 失敗を記録し、失敗が続いている場合はアカウントをロックする
+async fn register_failure(
+    state: &AppState,
+    user: &users::Model,
+    client: &ClientInfo,
+    outcome: LoginOutcome,
+) -> Result<(), AppError> {
+    record_attempt(state, Some(user.id), &user.m_address, client, outcome).await;
+    lockout::register_failure(&state.db_conn, &state.web.auth.lockout, user.id).await?;
+    Ok(())
+}
+
+/// 成功を記録し、失敗した回数をリセットする
+async fn register_success(
+    state: &AppState,
+    user: &users::Model,
+    client: &ClientInfo,
+) -> Result<(), AppError> {
+    record_attempt(
+        state,
+        Some(user.id),
+        &user.m_address,
+        client,
+        LoginOutcome::Success,
+    )
+    .await;
+    if user.failed_login_count > 0 || user.locked_until.is_some() {
+        lockout::reset(&state.db_conn, user.id).await?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct CsrfTokenResponse {
+    csrf_token: String,
+}
+
+/// 発行したトークンのレスポンス
+///
+/// Cookieモードの場合はトークンをCookieに保存し、ボディにはCSRFトークンのみを含める。
+fn tokens_response(state: &AppState, jar: CookieJar, tokens: jwt::Tokens) -> Response {
+    let cookie = &state.web.auth.cookie;
+    if !cookie.enabled {
+        return Json(tokens).into_response();
+    }
+    let (jar, csrf_token) = session_cookie::set_tokens(
+        jar,
+        cookie,
+        &tokens,
+        state.jwt_manager.access_token_expire_time,
+        state.jwt_manager.refresh_token_expire_time,
+    );
+    (jar, Json(CsrfTokenResponse { csrf_token })).into_response()
+}
+
+/// リフレッシュトークンをボディ、またはCookieモードの場合はCookieから取り出す
+///
+/// Cookieから取り出す場合はCSRFトークンを検証する。
+fn extract_refresh_token(
+    state: &AppState,
+    jar: &CookieJar,
+    headers: &HeaderMap,
+    refresh_token: Option<String>,
+) -> Result<String, AppError> {
+    if let Some(refresh_token) = refresh_token {
+        return Ok(refresh_token);
+    }
+    if !state.web.auth.cookie.enabled {
+        debug!("401 Unauthorized(no refresh token)");
+        return Err(AppError::Unauthorized("refresh token is required."));
+    }
+    let Some(cookie) = jar.get(session_cookie::REFRESH_TOKEN_COOKIE) else {
+        debug!("401 Unauthorized(no refresh token cookie)");
+        return Err(AppError::Unauthorized("refresh token is required."));
+    };
+    if !session_cookie::verify_csrf(jar, headers) {
+        debug!("403 Forbidden(csrf token mismatch)");
+        return Err(AppError::Forbidden);
+    }
+    Ok(cookie.value().to_string())
+}
+
+async fn rehash_password(state: &AppState, user: users::Model, password: String) -> Result<()> {
+    let password_hash = state.password_manager.hash(password).await?;
+    let mut user = user.into_active_model();
+    user.password_hash = Set(Some(password_hash));
+    user.update(&state.db_conn).await?;
+    debug!("password hash upgraded");
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct RefreshPayload {
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+#[instrument(name = "/auth/v1/refresh", skip(state, headers, payload))]
 async fn refresh(
-    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
     Json(payload): Json<RefreshPayload>,
-) -> Result<Json<RefreshResponse>, StatusCode> {
-    let refresh_token = match state.jwt_manager.decode(payload.refresh_token.as_str()) {
+) -> AppResponse {
+    let raw_refresh_token = extract_refresh_token(&state, &jar, &headers, payload.refresh_token)?;
+    let refresh_token = match state.jwt_manager.decode(raw_refresh_token.as_str()) {
         Ok(token) => token,
         Err(err) => {
             debug!("token decoding failed: {:?}", err);
-            return Err(StatusCode::UNAUTHORIZED);
+            return Err(AppError::Unauthorized(INVALID_TOKEN));
         }
     };
     let claims = refresh_token.claims;
+    let client = ClientInfo::new(&addr, &headers);
 
-    if match state
+    match state
         .jwt_manager
-        .is_refresh_token_valid(payload.refresh_token, &claims)
-        .await
+        .rotate_tokens(raw_refresh_token, &claims, &client)
+        .await?
     {
-        Ok(is_valid) => is_valid,
-        Err(err) => {
-            warn!("Token verification failed: {:?}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
-    } {
-        match state.jwt_manager.issue_access_token(claims.sub) {
-            Ok(access_token) => Ok(Json(RefreshResponse { access_token })),
-            Err(err) => {
-                warn!("internal server error while generating tokens: {:?}", err);
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
-            }
-        }
-    } else {
-        Err(StatusCode::UNAUTHORIZED)
+        Some(tokens) => Ok((StatusCode::OK, tokens_response(&state, jar, tokens))),
+        None => Err(AppError::Unauthorized(INVALID_TOKEN)),
     }
 }
 
@@ -250,127 +459,304 @@ async fn reset(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
     Json(payload): Json<ResetPayload>,
-) -> StatusCode {
-    let access_token = match state.jwt_manager.decode(payload.access_token.as_str()) {
-        Ok(access_token) => access_token,
-        Err(_) => {
-            return StatusCode::UNAUTHORIZED;
-        }
+) -> AppResponse {
+    let Ok(access_token) = state.jwt_manager.decode(payload.access_token.as_str()) else {
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
     };
-
-    if state
+    if !state
         .jwt_manager
         .is_access_token_valid(&access_token.claims)
     {
-        let sub = access_token.claims.sub;
-        let user = match users::Entity::find_by_id(sub).one(&state.db_conn).await {
-            Ok(Some(user)) => user,
-            Ok(None) => {
-                warn!("jwt token with invalid user was provided.");
-                return StatusCode::INTERNAL_SERVER_ERROR;
-            }
-            Err(err) => {
-                warn!("internal error occurred while executing sql: {:?}", err);
-                return StatusCode::INTERNAL_SERVER_ERROR;
-            }
-        };
-        //有効化されていない
-        if user.password_hash == None {
-            debug!("The account wasn't activated");
-            return StatusCode::UNAUTHORIZED;
-        }
-        let current_pwd_hash = user.password_hash.clone().unwrap();
-        let old_pwd_hash = state
-            .sha_manager
-            .stretch_with_salt(
-                payload.old_password.to_string().as_str(),
-                user.password_salt.as_str(),
-            )
-            .await;
-        let new_pwd_hash = state
-            .sha_manager
-            .stretch_with_salt(
-                payload.new_password.to_string().as_str(),
-                user.password_salt.as_str(),
-            )
-            .await;
-
-        if digest(old_pwd_hash.as_str()) == digest(current_pwd_hash.as_str()) {
-            let mut user = user.into_active_model();
-            user.password_hash = Set(Some(new_pwd_hash));
-            match user.update(&state.db_conn).await {
-                Ok(_) => StatusCode::OK,
-                Err(err) => {
-                    warn!(
-                        "Internal server error occurred while updating user password: {:?}",
-                        err
-                    );
-                    StatusCode::INTERNAL_SERVER_ERROR
-                }
-            }
-        } else {
-            debug!("password incorrect");
-            StatusCode::UNAUTHORIZED
-        }
-    } else {
-        StatusCode::UNAUTHORIZED
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
     }
+
+    let sub = access_token.claims.sub;
+    let Some(user) = users::Entity::find_by_id(sub).one(&state.db_conn).await? else {
+        warn!("jwt token with invalid user was provided.");
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
+    };
+    //有効化されていない
+    let Some(current_pwd_hash) = user.password_hash.clone() else {
+        debug!("The account wasn't activated");
+        return Err(AppError::Unauthorized(INVALID_CREDENTIALS));
+    };
+    let verification = state
+        .password_manager
+        .verify(payload.old_password, &user.password_salt, current_pwd_hash)
+        .await?;
+    if verification == Verification::Invalid {
+        debug!("password incorrect");
+        return Err(AppError::Unauthorized(INVALID_CREDENTIALS));
+    }
+
+    let new_pwd_hash = state.password_manager.hash(payload.new_password).await?;
+    let mut user = user.into_active_model();
+    user.password_hash = Set(Some(new_pwd_hash));
+    user.update(&state.db_conn).await?;
+    Ok((StatusCode::OK, ().into_response()))
 }
 
 #[derive(Serialize, Deserialize)]
 struct RevokePayload {
-    refresh_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
 }
 #[instrument(name = "/auth/v1/revoke", skip(state, payload))]
 async fn revoke(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
     Json(payload): Json<RevokePayload>,
-) -> StatusCode {
+) -> AppResponse {
+    let raw_refresh_token = extract_refresh_token(&state, &jar, &headers, payload.refresh_token)?;
     // refresh_tokenの有効性確認
-    let refresh_token = match state.jwt_manager.decode(payload.refresh_token.as_str()) {
-        Ok(token) => token,
-        Err(_) => {
-            return StatusCode::UNAUTHORIZED;
-        }
+    let Ok(refresh_token) = state.jwt_manager.decode(raw_refresh_token.as_str()) else {
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
     };
-    let is_valid = match state
+    if !state
         .jwt_manager
-        .is_refresh_token_valid(payload.refresh_token.clone(), &refresh_token.claims)
-        .await
+        .is_refresh_token_valid(raw_refresh_token.clone(), &refresh_token.claims)
+        .await?
     {
-        Ok(is_valid) => is_valid,
-        Err(err) => {
-            warn!("Internal server error occurred: {:?}", err);
-            return StatusCode::INTERNAL_SERVER_ERROR;
-        }
-    };
-    if !is_valid {
-        return StatusCode::UNAUTHORIZED;
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
     }
 
     // refresh_tokenの失効
-    let model = revoked_refresh_tokens::ActiveModel {
-        refresh_token: Set(payload.refresh_token),
-        exp: Set(refresh_token.claims.exp as i32),
+    state
+        .jwt_manager
+        .revoke_refresh_token(raw_refresh_token, &refresh_token.claims)
+        .await?;
+    if state.web.auth.cookie.enabled {
+        return Ok((
+            StatusCode::CREATED,
+            session_cookie::clear(jar, &state.web.auth.cookie).into_response(),
+        ));
+    }
+    Ok((StatusCode::CREATED, ().into_response()))
+}
+
+#[derive(Serialize, Deserialize)]
+struct PasswordForgotPayload {
+    m_address: String,
+}
+/// ユーザーの存在が露呈しないよう、ユーザーが存在しない場合も`202 Accepted`を返す
+#[instrument(name = "/auth/v1/password/forgot", fields(payload.m_address = %payload.m_address), skip(
+    payload,
+    state
+))]
+async fn password_forgot(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<PasswordForgotPayload>,
+) -> AppResponse {
+    let Some(user) = Users::find()
+        .filter(users::Column::MAddress.eq(payload.m_address))
+        .one(&state.db_conn)
+        .await?
+    else {
+        debug!("user not found");
+        return Ok((StatusCode::ACCEPTED, ().into_response()));
+    };
+    //有効化されていない
+    if user.password_hash.is_none() {
+        debug!("The account wasn't activated");
+        return Ok((StatusCode::ACCEPTED, ().into_response()));
+    }
+
+    // 送信キューへの追加までに留め、送信に掛かる時間からユーザーの存在が露呈しないようにする
+    issue_password_reset_token(&state, &user).await?;
+
+    Ok((StatusCode::ACCEPTED, ().into_response()))
+}
+
+/// 未使用のトークンを破棄し、新しいトークンを保存して再設定用のリンクを送信キューに追加する
+async fn issue_password_reset_token(state: &AppState, user: &users::Model) -> Result<()> {
+    let user_id = user.id;
+    let (token, token_hash) = token::generate();
+    let txn = state.db_conn.begin().await?;
+    password_reset_tokens::Entity::delete_many()
+        .filter(password_reset_tokens::Column::UserId.eq(user_id))
+        .filter(password_reset_tokens::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await?;
+    password_reset_tokens::ActiveModel {
+        token_hash: Set(token_hash),
+        created_at: NotSet,
+        user_id: Set(user_id),
+        expires_at: Set((Utc::now()
+            + chrono::Duration::seconds(state.web.auth.password_reset.token_expire_time))
+        .into()),
+        used_at: NotSet,
+    }
+    .insert(&txn)
+    .await?;
+    state
+        .mailer
+        .enqueue(
+            &txn,
+            &user.m_address,
+            Template::PasswordReset {
+                url: format!(
+                    "{}/password/reset?token={}",
+                    state.web.server.base_url, token
+                ),
+                expire_minutes: state.web.auth.password_reset.token_expire_time / 60,
+            },
+        )
+        .await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct PasswordCompletePayload {
+    token: String,
+    new_password: String,
+}
+#[instrument(name = "/auth/v1/password/complete", skip(state, payload))]
+async fn password_complete(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<PasswordCompletePayload>,
+) -> AppResponse {
+    let Some(reset_token) = password_reset_tokens::Entity::find_by_id(token::hash(&payload.token))
+        .one(&state.db_conn)
+        .await?
+    else {
+        debug!("401 Unauthorized(token)");
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
+    };
+    if reset_token.used_at.is_some() || reset_token.expires_at < Utc::now() {
+        debug!("401 Unauthorized(used or expired)");
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
+    }
+
+    let password_hash = state.password_manager.hash(payload.new_password).await?;
+
+    if !complete_password_reset(&state, reset_token, password_hash).await? {
+        debug!("401 Unauthorized(used concurrently)");
+        return Err(AppError::Unauthorized(INVALID_TOKEN));
+    }
+    Ok((StatusCode::OK, ().into_response()))
+}
+
+/// トークンを使用済みにしてパスワードを更新し、すべてのリフレッシュトークンを失効させる。
+/// トークンが既に使用されていた場合は`false`を返す。
+async fn complete_password_reset(
+    state: &AppState,
+    reset_token: password_reset_tokens::Model,
+    password_hash: String,
+) -> Result<bool> {
+    let txn = state.db_conn.begin().await?;
+    let result = password_reset_tokens::Entity::update_many()
+        .col_expr(
+            password_reset_tokens::Column::UsedAt,
+            Expr::value(Utc::now()),
+        )
+        .filter(password_reset_tokens::Column::TokenHash.eq(reset_token.token_hash))
+        .filter(password_reset_tokens::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await?;
+    if result.rows_affected == 0 {
+        txn.rollback().await?;
+        return Ok(false);
+    }
+    // パスワードを再設定した場合はロックも解除する
+    users::ActiveModel {
+        id: Set(reset_token.user_id),
+        password_hash: Set(Some(password_hash)),
+        failed_login_count: Set(0),
+        locked_until: Set(None),
+        ..Default::default()
+    }
+    .update(&txn)
+    .await?;
+    txn.commit().await?;
+
+    state.jwt_manager.revoke_all(reset_token.user_id).await?;
+    Ok(true)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Session {
+    id: Uuid,
+    created_at: Option<DateTime<FixedOffset>>,
+    last_used_at: DateTime<FixedOffset>,
+    /// 使われないままの場合にリフレッシュトークンの期限が切れる日時
+    expires_at: DateTime<FixedOffset>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    /// リクエストに使ったアクセストークンのセッションの場合true
+    current: bool,
+}
+
+/// ログイン中の参加団体責任者の有効なセッションの一覧を返す
+#[instrument(name = "GET /auth/v1/sessions", skip(state))]
+async fn get_sessions(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> AppResponse {
+    let CurrentUser::User(claims) = current_user else {
+        return Err(AppError::Forbidden);
+    };
+    let families = state.jwt_manager.sessions(claims.sub).await?;
+    let sessions: Vec<Session> = families
+        .into_iter()
+        .map(|family| Session {
+            id: family.family_id,
+            created_at: family.created_at,
+            last_used_at: family.last_used_at,
+            expires_at: state.jwt_manager.session_expires_at(&family),
+            ip: family.ip,
+            user_agent: family.user_agent,
+            current: family.family_id == claims.fam,
+        })
+        .collect();
+    Ok((StatusCode::OK, Json(sessions).into_response()))
+}
+
+/// セッションを失効させる。そのセッションのリフレッシュトークンは使えなくなる
+#[instrument(name = "DELETE /auth/v1/sessions/{session_id}", skip(state))]
+async fn delete_session(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Extension(current_user): Extension<CurrentUser>,
+    Path(session_id): Path<Uuid>,
+) -> AppResponse {
+    let CurrentUser::User(claims) = current_user else {
+        return Err(AppError::Forbidden);
     };
-    match revoked_refresh_tokens::Entity::insert(model)
-        .exec(&state.db_conn)
-        .await
+    if !state
+        .jwt_manager
+        .revoke_session(claims.sub, session_id)
+        .await?
     {
-        Ok(_) => StatusCode::CREATED,
-        Err(err) => {
-            warn!("Internal server error occurred: {:?}", err);
-            StatusCode::INTERNAL_SERVER_ERROR
-        }
+        return Err(AppError::NotFound("session"));
     }
+    Ok((StatusCode::NO_CONTENT, ().into_response()))
+}
+
+/// すべてのセッションを失効させる(すべての端末からログアウトする)
+#[instrument(name = "DELETE /auth/v1/sessions", skip(state))]
+async fn delete_sessions(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Extension(current_user): Extension<CurrentUser>,
+) -> AppResponse {
+    let CurrentUser::User(claims) = current_user else {
+        return Err(AppError::Forbidden);
+    };
+    state.jwt_manager.revoke_all(claims.sub).await?;
+    Ok((StatusCode::NO_CONTENT, ().into_response()))
 }
 
 #[instrument(name = "/auth/v1/admin/login", skip(state))]
 async fn admin_login(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
-) -> (StatusCode, HeaderMap) {
+) -> AppResponse {
     //url発行
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
     let (url, csrf_token, nonce) = state
@@ -386,25 +772,22 @@ async fn admin_login(
         .add_scope(Scope::new("offline_access".to_string()))
         .set_pkce_challenge(pkce_challenge)
         .url();
-    state.auth_sessions.lock().await.insert(
-        csrf_token.secret().clone(),
-        AuthSession {
-            pkce_verifier,
-            nonce,
-        },
-    );
+    state
+        .auth_sessions
+        .insert(
+            csrf_token.secret(),
+            AuthSession {
+                pkce_verifier,
+                nonce,
+            },
+        )
+        .await?;
 
     //header
     let mut headers = HeaderMap::new();
-    let val = if let Ok(val) = HeaderValue::from_str(url.as_str()) {
-        val
-    } else {
-        warn!("internal server error while setting header");
-        return (StatusCode::INTERNAL_SERVER_ERROR, headers);
-    };
-    headers.insert(http::header::LOCATION, val);
+    headers.insert(http::header::LOCATION, HeaderValue::from_str(url.as_str())?);
 
-    (StatusCode::FOUND, headers)
+    Ok((StatusCode::FOUND, headers.into_response()))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -417,6 +800,7 @@ struct RedirectQuery {
 struct RedirectResponse {
     pub refresh_token: String,
     pub access_token: String,
+    pub id_token: String,
 }
 
 #[instrument(name = "/auth/v1/admin/redirect", skip(state, payload))]
@@ -424,30 +808,130 @@ async fn admin_redirect(
     ConnectInfo(_addr): ConnectInfo<SocketAddr>,
     State(state): State<Arc<AppState>>,
     payload: Json<RedirectQuery>,
-) -> Result<Json<RedirectResponse>, StatusCode> {
-    let auth_session = match state.auth_sessions.lock().await.remove(&payload.state) {
-        Some(auth_session) => auth_session,
-        None => Err(StatusCode::BAD_REQUEST)?,
+) -> AppResponse {
+    let Some(auth_session) = state.auth_sessions.take(&payload.state).await? else {
+        return Err(AppError::BadRequest("unknown or expired state."));
     };
-    let (refresh_token, access_token) = match request_token(
+    let (refresh_token, access_token, id_token) = request_token(
         auth_session,
         &state.http_client,
         &state.oidc_client,
         &payload.code,
     )
-    .await
-    {
-        Ok(tuple) => tuple,
-        Err(err) => {
-            warn!("login error in call: {:?}", err);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(RedirectResponse {
+            refresh_token: refresh_token.into_secret(),
+            access_token: access_token.into_secret(),
+            id_token,
+        })
+        .into_response(),
+    ))
+}
+
+#[derive(Serialize, Deserialize)]
+struct AdminRefreshPayload {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AdminRefreshResponse {
+    pub refresh_token: String,
+    pub access_token: String,
+    pub id_token: Option<String>,
+}
+
+/// Keycloakのリフレッシュトークンで新しいトークンを取得する
+#[instrument(name = "/auth/v1/admin/refresh", skip(state, payload))]
+async fn admin_refresh(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<AdminRefreshPayload>,
+) -> AppResponse {
+    let refresh_token = RefreshToken::new(payload.refresh_token);
+    let request = state.oidc_client.exchange_refresh_token(&refresh_token)?;
+    let token_response = match request.request_async(&state.http_client).await {
+        Ok(token_response) => token_response,
+        Err(oauth2::RequestTokenError::ServerResponse(err)) => {
+            debug!("401 Unauthorized: {}", err);
+            return Err(AppError::Unauthorized(INVALID_TOKEN));
+        }
+        Err(err) => return Err(anyhow!("failed to refresh: {:?}", err).into()),
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(AdminRefreshResponse {
+            // ローテーションされなかった場合は同じリフレッシュトークンを使い続ける
+            refresh_token: token_response
+                .refresh_token()
+                .map(|token| token.secret().clone())
+                .unwrap_or(refresh_token.into_secret()),
+            access_token: token_response.access_token().secret().clone(),
+            id_token: token_response
+                .extra_fields()
+                .id_token()
+                .map(|id_token| id_token.to_string()),
+        })
+        .into_response(),
+    ))
+}
+
+#[derive(Serialize, Deserialize)]
+struct AdminLogoutPayload {
+    pub refresh_token: String,
+    pub id_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AdminLogoutResponse {
+    pub end_session_url: Option<String>,
+}
+
+/// Keycloakでリフレッシュトークンを失効させ、ブラウザのセッションを終了するためのURLを返す
+///
+/// ブラウザを`end_session_url`に遷移させるとKeycloakからログアウトし、`/admin`に戻る。
+#[instrument(name = "/auth/v1/admin/logout", skip(state, payload))]
+async fn admin_logout(
+    ConnectInfo(_addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<AdminLogoutPayload>,
+) -> AppResponse {
+    let token = StandardRevocableToken::RefreshToken(RefreshToken::new(payload.refresh_token));
+    let request = state.oidc_client.revoke_token(token)?;
+    match request.request_async(&state.http_client).await {
+        Ok(()) => {}
+        // 期限切れ等で失効できなくても、ブラウザのセッションは終了させる
+        Err(oauth2::RequestTokenError::ServerResponse(err)) => {
+            debug!("refresh token was not revoked: {}", err);
+        }
+        Err(err) => return Err(anyhow!("failed to revoke: {:?}", err).into()),
+    }
+
+    let end_session_url = match &state.end_session_url {
+        Some(end_session_url) => {
+            let post_logout_redirect_url =
+                PostLogoutRedirectUrl::new(format!("{}/admin", state.web.server.base_url))?;
+            let mut request = LogoutRequest::from(end_session_url.clone())
+                .set_client_id(state.oidc_client.client_id().clone())
+                .set_post_logout_redirect_uri(post_logout_redirect_url);
+            if let Some(id_token) = payload
+                .id_token
+                .and_then(|id_token| CoreIdToken::from_str(&id_token).ok())
+            {
+                request = request.set_id_token_hint(&id_token);
+            }
+            Some(request.http_get_url().to_string())
         }
+        None => None,
     };
 
-    Ok(Json::from(RedirectResponse {
-        refresh_token: refresh_token.into_secret(),
-        access_token: access_token.into_secret(),
-    }))
+    Ok((
+        StatusCode::OK,
+        Json(AdminLogoutResponse { end_session_url }).into_response(),
+    ))
 }
 
 enum RequestTokenError {
@@ -477,7 +961,7 @@ async fn request_token(
     http_client: &Client,
     oidc_client: &OIDCClient,
     code: &String,
-) -> Result<(RefreshToken, AccessToken)> {
+) -> Result<(RefreshToken, AccessToken, String)> {
     let token_response = oidc_client
         .exchange_code(AuthorizationCode::new(code.to_string()))?
         .set_pkce_verifier(auth_session.pkce_verifier)
@@ -497,5 +981,5 @@ async fn request_token(
         .ok_or(RequestTokenError::NoIdTokenError)?;
     id_token.claims(&oidc_client.id_token_verifier(), &auth_session.nonce)?;
 
-    Ok((refresh_token, access_token))
+    Ok((refresh_token, access_token, id_token.to_string()))
 }
@@ -0,0 +1,18 @@
+use crate::routes::AppState;
+use axum::extract::State;
+use axum::routing::get;
+use axum::{Json, Router};
+use jsonwebtoken::jwk::JwkSet;
+use std::sync::Arc;
+use tracing::instrument;
+
+#[instrument(name = "init /.well-known")]
+pub fn init_router() -> Router<Arc<AppState>> {
+    Router::new().route("/jwks.json", get(get_jwks))
+}
+
+/// 参加団体責任者のトークンを他のサービスで検証するための公開鍵を返す
+#[instrument(name = "GET /.well-known/jwks.json", skip(state))]
+async fn get_jwks(State(state): State<Arc<AppState>>) -> Json<JwkSet> {
+    Json(state.jwt_manager.jwks().clone())
+}
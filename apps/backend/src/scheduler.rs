@@ -0,0 +1,113 @@
+pub mod purge;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, instrument, warn, Instrument};
+
+/// 実行間隔の下限。`tokio::time::interval`は間隔が0の場合にpanicする
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 定期的に実行する処理
+#[async_trait]
+pub trait Job: Send + Sync {
+    /// ログに表示する名前
+    fn name(&self) -> &'static str;
+    async fn run(&self) -> Result<()>;
+}
+
+/// 登録された`Job`をそれぞれの間隔で実行する
+///
+/// 前回の実行が間隔より長くかかった場合、次の実行は前回の終了から間隔を空けて行う。
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<(Duration, Arc<dyn Job>)>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `interval`が`MIN_INTERVAL`より短い場合は`MIN_INTERVAL`にする
+    pub fn add(mut self, interval: Duration, job: impl Job + 'static) -> Self {
+        if interval < MIN_INTERVAL {
+            warn!(
+                "interval of job {} is too short ({:?}); using {:?}",
+                job.name(),
+                interval,
+                MIN_INTERVAL
+            );
+        }
+        self.jobs.push((interval.max(MIN_INTERVAL), Arc::new(job)));
+        self
+    }
+
+    /// それぞれの`Job`を別のタスクで実行し始める
+    #[instrument(skip_all)]
+    pub fn start(self) -> Vec<JoinHandle<()>> {
+        self.jobs
+            .into_iter()
+            .map(|(period, job)| {
+                info!("job {} scheduled every {:?}", job.name(), period);
+                tokio::spawn(run(period, job).instrument(tracing::Span::current()))
+            })
+            .collect()
+    }
+}
+
+async fn run(period: Duration, job: Arc<dyn Job>) {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        debug!("running job {}", job.name());
+        if let Err(err) = job.run().await {
+            warn!("job {} failed: {}", job.name(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Noop;
+
+    #[async_trait]
+    impl Job for Noop {
+        fn name(&self) -> &'static str {
+            "noop"
+        }
+
+        async fn run(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn clamps_zero_interval() {
+        let scheduler = Scheduler::new()
+            .add(Duration::ZERO, Noop)
+            .add(Duration::from_secs(60), Noop);
+        let intervals = scheduler
+            .jobs
+            .iter()
+            .map(|(interval, _)| *interval)
+            .collect::<Vec<_>>();
+        assert_eq!(intervals, [MIN_INTERVAL, Duration::from_secs(60)]);
+    }
+
+    #[tokio::test]
+    async fn runs_job_with_zero_interval() {
+        let handles = Scheduler::new().add(Duration::ZERO, Noop).start();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        for handle in handles {
+            assert!(!handle.is_finished());
+            handle.abort();
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use crate::config::RefreshTokenExpiry;
+use crate::entities::sea_orm_active_enums::MailStatus;
+use crate::entities::{
+    activation_codes, login_attempts, mail_outbox, password_reset_tokens, refresh_token_families,
+    revoked_refresh_tokens, stored_files,
+};
+use crate::scheduler::Job;
+use crate::storage::ObjectStorage;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter};
+use tracing::{info, warn};
+
+/// 期限切れのトークンを削除する
+/// * `revoked_refresh_tokens`: 期限が切れたトークンはdenylistになくても使えない
+/// * `activation_codes`: 期限切れのコード。再発行は管理者が行う
+/// * `password_reset_tokens`: 期限切れ、または使用済みのトークン
+pub struct PurgeExpiredTokens {
+    db_conn: DatabaseConnection,
+}
+
+impl PurgeExpiredTokens {
+    pub fn new(db_conn: DatabaseConnection) -> Self {
+        Self { db_conn }
+    }
+}
+
+#[async_trait]
+impl Job for PurgeExpiredTokens {
+    fn name(&self) -> &'static str {
+        "purge_expired_tokens"
+    }
+
+    async fn run(&self) -> Result<()> {
+        let now = Utc::now();
+        let revoked_refresh_tokens = revoked_refresh_tokens::Entity::delete_many()
+            .filter(revoked_refresh_tokens::Column::Exp.lt(now.timestamp()))
+            .exec(&self.db_conn)
+            .await?
+            .rows_affected;
+        let activation_codes = activation_codes::Entity::delete_many()
+            .filter(activation_codes::Column::ExpiresAt.lt(now))
+            .exec(&self.db_conn)
+            .await?
+            .rows_affected;
+        let password_reset_tokens = password_reset_tokens::Entity::delete_many()
+            .filter(
+                Condition::any()
+                    .add(password_reset_tokens::Column::ExpiresAt.lt(now))
+                    .add(password_reset_tokens::Column::UsedAt.is_not_null()),
+            )
+            .exec(&self.db_conn)
+            .await?
+            .rows_affected;
+        if revoked_refresh_tokens + activation_codes + password_reset_tokens > 0 {
+            info!(
+                "purged {} revoked refresh tokens, {} activation codes, {} password reset tokens",
+                revoked_refresh_tokens, activation_codes, password_reset_tokens
+            );
+        }
+        Ok(())
+    }
+}
+
+/// 保持期間を過ぎたログイン試行の記録を削除する
+pub struct PurgeLoginAttempts {
+    db_conn: DatabaseConnection,
+    retention: i64,
+}
+
+impl PurgeLoginAttempts {
+    pub fn new(db_conn: DatabaseConnection, retention: i64) -> Self {
+        Self { db_conn, retention }
+    }
+}
+
+#[async_trait]
+impl Job for PurgeLoginAttempts {
+    fn name(&self) -> &'static str {
+        "purge_login_attempts"
+    }
+
+    async fn run(&self) -> Result<()> {
+        let oldest = Utc::now() - chrono::Duration::seconds(self.retention);
+        let result = login_attempts::Entity::delete_many()
+            .filter(login_attempts::Column::CreatedAt.lt(oldest))
+            .exec(&self.db_conn)
+            .await?;
+        if result.rows_affected > 0 {
+            info!("purged {} login attempts", result.rows_affected);
+        }
+        Ok(())
+    }
+}
+
+/// 保持期間を過ぎた送信済み・送信に失敗したメールを削除する
+pub struct PurgeMails {
+    db_conn: DatabaseConnection,
+    retention: i64,
+}
+
+impl PurgeMails {
+    pub fn new(db_conn: DatabaseConnection, retention: i64) -> Self {
+        Self { db_conn, retention }
+    }
+}
+
+#[async_trait]
+impl Job for PurgeMails {
+    fn name(&self) -> &'static str {
+        "purge_mails"
+    }
+
+    async fn run(&self) -> Result<()> {
+        let oldest = Utc::now() - chrono::Duration::seconds(self.retention);
+        let result = mail_outbox::Entity::delete_many()
+            .filter(mail_outbox::Column::Status.is_in([MailStatus::Sent, MailStatus::Failed]))
+            .filter(mail_outbox::Column::UpdatedAt.lt(oldest))
+            .exec(&self.db_conn)
+            .await?;
+        if result.rows_affected > 0 {
+            info!("purged {} mails", result.rows_affected);
+        }
+        Ok(())
+    }
+}
+
+/// 失効したファミリーと、リフレッシュトークンの期限が切れたファミリーを削除する
+///
+/// 削除されたファミリーのトークンは`rotate_tokens`でファミリーが見つからず無効になる。
+pub struct PurgeRefreshTokenFamilies {
+    db_conn: DatabaseConnection,
+    expire_time: i64,
+    expiry: RefreshTokenExpiry,
+}
+
+impl PurgeRefreshTokenFamilies {
+    pub fn new(db_conn: DatabaseConnection, expire_time: i64, expiry: RefreshTokenExpiry) -> Self {
+        Self {
+            db_conn,
+            expire_time,
+            expiry,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for PurgeRefreshTokenFamilies {
+    fn name(&self) -> &'static str {
+        "purge_refresh_token_families"
+    }
+
+    async fn run(&self) -> Result<()> {
+        let oldest = Utc::now() - chrono::Duration::seconds(self.expire_time);
+        let expiry_base = match self.expiry {
+            RefreshTokenExpiry::Sliding => refresh_token_families::Column::LastUsedAt,
+            RefreshTokenExpiry::Absolute => refresh_token_families::Column::CreatedAt,
+        };
+        let result = refresh_token_families::Entity::delete_many()
+            .filter(
+                Condition::any()
+                    .add(refresh_token_families::Column::Revoked.eq(true))
+                    .add(expiry_base.lt(oldest)),
+            )
+            .exec(&self.db_conn)
+            .await?;
+        if result.rows_affected > 0 {
+            info!("purged {} refresh token families", result.rows_affected);
+        }
+        Ok(())
+    }
+}
+
+/// どの回答の`answer_file`からも参照されていないファイルの`stored_files`のSQLの条件
+const UNREFERENCED_FILE: &str = "NOT EXISTS (\
+    SELECT 1 FROM form_responses \
+    CROSS JOIN LATERAL json_each(form_responses.answers) AS answer \
+    CROSS JOIN LATERAL json_array_elements_text(answer.value -> 'answer_file' -> 'file_ids') AS file_ids(file_id) \
+    WHERE file_ids.file_id = stored_files.file_id::text)";
+
+/// 保持期間を過ぎても回答から参照されていないアップロードされたファイルを削除する
+///
+/// 回答から参照されたファイルを消さないよう、先に条件付きで`stored_files`の行を削除し、
+/// 削除できた行のファイルを`ObjectStorage`から削除する。ストレージからの削除に失敗したファイルはログに残す。
+pub struct PurgeUnreferencedFiles {
+    db_conn: DatabaseConnection,
+    storage: Box<dyn ObjectStorage>,
+    retention: i64,
+}
+
+impl PurgeUnreferencedFiles {
+    pub fn new(
+        db_conn: DatabaseConnection,
+        storage: Box<dyn ObjectStorage>,
+        retention: i64,
+    ) -> Self {
+        Self {
+            db_conn,
+            storage,
+            retention,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for PurgeUnreferencedFiles {
+    fn name(&self) -> &'static str {
+        "purge_unreferenced_files"
+    }
+
+    async fn run(&self) -> Result<()> {
+        let oldest = Utc::now() - chrono::Duration::seconds(self.retention);
+        let files = stored_files::Entity::delete_many()
+            .filter(stored_files::Column::CreatedAt.lt(oldest))
+            .filter(Expr::cust(UNREFERENCED_FILE))
+            .exec_with_returning(&self.db_conn)
+            .await?;
+        for file in &files {
+            if let Err(err) = self.storage.delete(&file.file_id.to_string()).await {
+                warn!(
+                    "failed to delete file {} from storage: {:?}",
+                    file.file_id, err
+                );
+            }
+        }
+        if !files.is_empty() {
+            info!("purged {} unreferenced files", files.len());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+pub mod local;
+pub mod s3;
+
+use crate::config;
+use anyhow::Result;
+use async_trait::async_trait;
+use local::LocalStorage;
+use s3::S3Storage;
+
+/// アップロードされたファイルの保存先
+///
+/// `key`はファイルのID(`stored_files.file_id`)。
+#[async_trait]
+pub trait ObjectStorage: Send + Sync {
+    async fn put(&self, key: &str, content_type: &str, data: Vec<u8>) -> Result<()>;
+    /// 存在しない場合は`None`
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+pub fn from_config(storage: &config::Storage) -> Result<Box<dyn ObjectStorage>> {
+    Ok(match &storage.backend {
+        config::StorageBackend::Local { path } => Box::new(LocalStorage::new(path)),
+        config::StorageBackend::S3(s3) => Box::new(S3Storage::new(s3)?),
+    })
+}
@@ -0,0 +1,47 @@
+use crate::storage::ObjectStorage;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// ローカルのディレクトリに保存する
+///
+/// 書き込み途中のファイルを読まないよう、一時ファイルに書き込んでから移動する。
+pub struct LocalStorage {
+    path: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for LocalStorage {
+    async fn put(&self, key: &str, _content_type: &str, data: Vec<u8>) -> Result<()> {
+        tokio::fs::create_dir_all(&self.path).await?;
+        let tmp = self
+            .path
+            .join(format!(".{}.{}", key, Uuid::new_v4().simple()));
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, self.path.join(key)).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path.join(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path.join(key)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -1,21 +1,170 @@
 use axum::response::{IntoResponse, Response};
-use http::StatusCode;
+use http::header::{CONTENT_TYPE, RETRY_AFTER};
+use http::{HeaderValue, StatusCode};
+use serde::Serialize;
 use tracing::warn;
+use uuid::Uuid;
 
+pub mod activation;
+pub mod auth_session;
+pub mod client_info;
+pub mod json;
 pub(crate) mod jwt;
+pub mod jwt_keys;
+pub mod lockout;
 pub mod oidc;
+pub mod password;
+pub mod session_cookie;
 pub mod sha;
+pub mod token;
+pub mod totp;
 
-pub struct AppError(anyhow::Error);
+/// APIのエラー
+///
+/// RFC 7807の`application/problem+json`として返す。`code`は機械的に判別するための文字列。
+/// `Internal`の詳細はcorrelation idと共にログに出力し、クライアントにはcorrelation idのみを返す。
+#[derive(Debug)]
+pub enum AppError {
+    /// リクエストの内容が不正
+    BadRequest(&'static str),
+    /// リクエストのフィールドが不正
+    Validation(Vec<FieldError>),
+    /// 認証情報が無い、または無効
+    Unauthorized(&'static str),
+    Forbidden,
+    /// リソースが見つからない。値はリソースの名前
+    NotFound(&'static str),
+    Conflict(&'static str),
+    /// リクエストのボディが大きすぎる。値は上限(バイト)
+    PayloadTooLarge(usize),
+    /// 保存できる容量を超える。値は上限(バイト)
+    QuotaExceeded(i64),
+    /// アカウントがロックされている等で受け付けられない。値は再試行できるまでの秒数(`Retry-After`)
+    TooManyRequests(i64),
+    Internal(anyhow::Error),
+}
+
+/// * `field`: 不正なフィールドのパス(例: `answers.name`)。ボディ全体の場合は空文字列
+#[derive(Serialize, Debug)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Problem {
+    #[serde(rename = "type")]
+    r#type: &'static str,
+    title: &'static str,
+    status: u16,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<Uuid>,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PayloadTooLarge(_) | AppError::QuotaExceeded(_) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Validation(_) => "validation_failed",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::QuotaExceeded(_) => "quota_exceeded",
+            AppError::TooManyRequests(_) => "too_many_requests",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+}
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        warn!("Internal server error: {}", self.0);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Internal Server Error: {}", self.0),
+        let status = self.status();
+        let mut problem = Problem {
+            r#type: "about:blank",
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            code: self.code(),
+            detail: None,
+            errors: vec![],
+            correlation_id: None,
+        };
+        let mut retry_after = None;
+        match self {
+            AppError::BadRequest(detail)
+            | AppError::Unauthorized(detail)
+            | AppError::Conflict(detail) => {
+                problem.detail = Some(detail.to_string());
+            }
+            AppError::Validation(errors) => {
+                problem.errors = errors;
+            }
+            AppError::Forbidden => {
+                problem.detail = Some("Access forbidden.".to_string());
+            }
+            AppError::NotFound(resource) => {
+                problem.detail = Some(format!("{} not found.", resource));
+            }
+            AppError::PayloadTooLarge(limit) => {
+                problem.detail = Some(format!("payload must be at most {} bytes.", limit));
+            }
+            AppError::QuotaExceeded(quota) => {
+                problem.detail = Some(format!("storage quota of {} bytes exceeded.", quota));
+            }
+            AppError::TooManyRequests(seconds) => {
+                problem.detail = Some(format!("retry after {} seconds.", seconds));
+                retry_after = Some(seconds);
+            }
+            AppError::Internal(err) => {
+                // 内部の情報はクライアントに返さず、問い合わせ用のIDのみを返す
+                let correlation_id = Uuid::new_v4();
+                warn!(%correlation_id, "Internal server error: {:?}", err);
+                problem.correlation_id = Some(correlation_id);
+            }
+        }
+        let mut response = (
+            status,
+            [(CONTENT_TYPE, "application/problem+json")],
+            serde_json::to_string(&problem).unwrap_or_default(),
         )
-            .into_response()
+            .into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
     }
 }
 
@@ -24,7 +173,7 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self::Internal(err.into())
     }
 }
 
@@ -0,0 +1,87 @@
+use crate::entities::{activation_codes, users};
+use crate::mailer::template::Template;
+use crate::mailer::Mailer;
+use crate::util::token;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, NotSet, QueryFilter};
+use uuid::Uuid;
+
+/// アクティベーションコードを発行し、ハッシュをDBに保存する。
+/// 既に発行されたコードがある場合は置き換える。
+/// 戻り値は`(コード, 有効期限)`
+pub async fn issue_code<C: ConnectionTrait>(
+    db: &C,
+    user_id: Uuid,
+    expire_time: i64,
+) -> Result<(String, DateTime<FixedOffset>)> {
+    let (code, code_hash) = token::generate();
+    let expires_at: DateTime<FixedOffset> =
+        (Utc::now() + chrono::Duration::seconds(expire_time)).into();
+    activation_codes::Entity::insert(activation_codes::ActiveModel {
+        user_id: Set(user_id),
+        created_at: NotSet,
+        code_hash: Set(code_hash),
+        expires_at: Set(expires_at),
+    })
+    .on_conflict(
+        OnConflict::column(activation_codes::Column::UserId)
+            .update_columns([
+                activation_codes::Column::CodeHash,
+                activation_codes::Column::ExpiresAt,
+            ])
+            .value(activation_codes::Column::CreatedAt, Utc::now())
+            .to_owned(),
+    )
+    .exec(db)
+    .await?;
+    Ok((code, expires_at))
+}
+
+/// アクティベーションコードを発行し、アクティベーション用のリンクを代表者宛ての送信キューに追加する。
+/// コードはメールでのみ渡し、戻り値は有効期限のみ
+pub async fn issue_and_mail_code<C: ConnectionTrait>(
+    db: &C,
+    mailer: &Mailer,
+    base_url: &str,
+    user: &users::Model,
+    exhibitor_name: &str,
+    expire_time: i64,
+) -> Result<DateTime<FixedOffset>> {
+    let (code, expires_at) = issue_code(db, user.id, expire_time).await?;
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/activate", base_url),
+        &[
+            ("m_address", user.m_address.as_str()),
+            ("token", code.as_str()),
+        ],
+    )?;
+    mailer
+        .enqueue(
+            db,
+            &user.m_address,
+            Template::Activation {
+                first_name: user.first_name.clone(),
+                last_name: user.last_name.clone(),
+                exhibitor_name: exhibitor_name.to_string(),
+                url: url.to_string(),
+                expires_at,
+            },
+        )
+        .await?;
+    Ok(expires_at)
+}
+
+/// アクティベーションコードを検証し、有効な場合は消費する。
+/// コードが存在しない、一致しない、期限切れの場合は`false`を返す。
+pub async fn consume_code<C: ConnectionTrait>(db: &C, user_id: Uuid, code: &str) -> Result<bool> {
+    let result = activation_codes::Entity::delete_many()
+        .filter(activation_codes::Column::UserId.eq(user_id))
+        .filter(activation_codes::Column::CodeHash.eq(token::hash(code)))
+        .filter(activation_codes::Column::ExpiresAt.gt(Utc::now()))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected == 1)
+}
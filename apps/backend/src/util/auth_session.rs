@@ -0,0 +1,151 @@
+use crate::config;
+use crate::entities::auth_sessions;
+use crate::util::token;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use oauth2::PkceCodeVerifier;
+use openidconnect::Nonce;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, NotSet, QueryFilter};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+/// 管理者のログイン(OIDC)を開始してから、リダイレクトされて戻ってくるまでに保持する値
+pub struct AuthSession {
+    pub pkce_verifier: PkceCodeVerifier,
+    pub nonce: Nonce,
+}
+
+/// `state`をキーに`AuthSession`を保存する
+///
+/// セッションは一度しか取り出せず、`expire_time`を過ぎたものは取り出せない。
+#[async_trait]
+pub trait AuthSessionStore: Send + Sync {
+    async fn insert(&self, state: &str, session: AuthSession) -> Result<()>;
+    /// セッションを取り出して削除する。存在しないか期限切れの場合は`None`
+    async fn take(&self, state: &str) -> Result<Option<AuthSession>>;
+    /// 期限切れのセッションを削除し、削除した件数を返す
+    async fn purge_expired(&self) -> Result<u64>;
+}
+
+pub fn from_config(
+    admin_session: &config::AdminSession,
+    db_conn: DatabaseConnection,
+) -> Box<dyn AuthSessionStore> {
+    match admin_session.store {
+        config::AuthSessionStore::Postgres => Box::new(PostgresAuthSessionStore::new(
+            db_conn,
+            admin_session.expire_time,
+        )),
+        config::AuthSessionStore::Memory => {
+            Box::new(MemoryAuthSessionStore::new(admin_session.expire_time))
+        }
+    }
+}
+
+/// `auth_sessions`テーブルに保存する
+///
+/// `state`はハッシュ化して保存する。
+pub struct PostgresAuthSessionStore {
+    db_conn: DatabaseConnection,
+    expire_time: i64,
+}
+
+impl PostgresAuthSessionStore {
+    pub fn new(db_conn: DatabaseConnection, expire_time: i64) -> Self {
+        Self {
+            db_conn,
+            expire_time,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthSessionStore for PostgresAuthSessionStore {
+    async fn insert(&self, state: &str, session: AuthSession) -> Result<()> {
+        // 途中で放棄されたセッションが溜まらないようにする
+        self.purge_expired().await?;
+        auth_sessions::Entity::insert(auth_sessions::ActiveModel {
+            state_hash: Set(token::hash(state)),
+            created_at: NotSet,
+            pkce_verifier: Set(session.pkce_verifier.secret().clone()),
+            nonce: Set(session.nonce.secret().clone()),
+            expires_at: Set((Utc::now() + chrono::Duration::seconds(self.expire_time)).into()),
+        })
+        .exec(&self.db_conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn take(&self, state: &str) -> Result<Option<AuthSession>> {
+        let deleted = auth_sessions::Entity::delete_many()
+            .filter(auth_sessions::Column::StateHash.eq(token::hash(state)))
+            .filter(auth_sessions::Column::ExpiresAt.gt(Utc::now()))
+            .exec_with_returning(&self.db_conn)
+            .await?;
+        Ok(deleted.into_iter().next().map(|model| AuthSession {
+            pkce_verifier: PkceCodeVerifier::new(model.pkce_verifier),
+            nonce: Nonce::new(model.nonce),
+        }))
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        let result = auth_sessions::Entity::delete_many()
+            .filter(auth_sessions::Column::ExpiresAt.lte(Utc::now()))
+            .exec(&self.db_conn)
+            .await?;
+        if result.rows_affected > 0 {
+            debug!("{} expired auth sessions purged", result.rows_affected);
+        }
+        Ok(result.rows_affected)
+    }
+}
+
+/// メモリに保存する(開発用)
+///
+/// 再起動すると失われ、複数のインスタンスでは共有されない。
+pub struct MemoryAuthSessionStore {
+    expire_time: Duration,
+    sessions: Mutex<HashMap<String, (AuthSession, Instant)>>,
+}
+
+impl MemoryAuthSessionStore {
+    pub fn new(expire_time: i64) -> Self {
+        Self {
+            expire_time: Duration::from_secs(expire_time.max(0) as u64),
+            sessions: Default::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthSessionStore for MemoryAuthSessionStore {
+    async fn insert(&self, state: &str, session: AuthSession) -> Result<()> {
+        self.purge_expired().await?;
+        self.sessions
+            .lock()
+            .await
+            .insert(state.to_string(), (session, Instant::now()));
+        Ok(())
+    }
+
+    async fn take(&self, state: &str) -> Result<Option<AuthSession>> {
+        Ok(self
+            .sessions
+            .lock()
+            .await
+            .remove(state)
+            .filter(|(_, created_at)| created_at.elapsed() < self.expire_time)
+            .map(|(session, _)| session))
+    }
+
+    async fn purge_expired(&self) -> Result<u64> {
+        let mut sessions = self.sessions.lock().await;
+        let before = sessions.len();
+        sessions.retain(|_, (_, created_at)| created_at.elapsed() < self.expire_time);
+        Ok((before - sessions.len()) as u64)
+    }
+}
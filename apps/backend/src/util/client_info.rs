@@ -0,0 +1,20 @@
+use http::{header, HeaderMap};
+use std::net::SocketAddr;
+
+/// リクエストを送ったクライアントの情報。ログイン試行やセッションの記録に使う
+pub struct ClientInfo {
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn new(addr: &SocketAddr, headers: &HeaderMap) -> Self {
+        Self {
+            ip: addr.ip().to_string(),
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::util::{AppError, FieldError};
+use axum::body::Bytes;
+use axum::extract::{FromRequest, Request};
+use axum::response::{IntoResponse, Response};
+use http::header::CONTENT_TYPE;
+use http::HeaderMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::error::Category;
+use std::ops::{Deref, DerefMut};
+
+/// `axum::Json`の代わりに使うJSONの抽出器・レスポンス
+///
+/// ボディが不正な場合、どのフィールドが不正かを`AppError::Validation`としてproblem+jsonで返す。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json_content_type(req.headers()) {
+            return Err(AppError::BadRequest(
+                "Content-Type must be application/json.",
+            ));
+        }
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|_| AppError::BadRequest("failed to read body."))?;
+
+        let deserializer = &mut serde_json::Deserializer::from_slice(&bytes);
+        match serde_path_to_error::deserialize(deserializer) {
+            Ok(value) => Ok(Json(value)),
+            Err(err) => match err.inner().classify() {
+                Category::Data => {
+                    // ルートの場合は"."になる
+                    let field = match err.path().to_string() {
+                        path if path == "." => String::new(),
+                        path => path,
+                    };
+                    Err(AppError::Validation(vec![FieldError::new(
+                        field,
+                        err.into_inner().to_string(),
+                    )]))
+                }
+                Category::Syntax | Category::Eof | Category::Io => {
+                    Err(AppError::BadRequest("invalid json."))
+                }
+            },
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T> From<T> for Json<T> {
+    fn from(value: T) -> Self {
+        Json(value)
+    }
+}
+
+/// `application/json`または`application/*+json`の場合true
+fn is_json_content_type(headers: &HeaderMap) -> bool {
+    let Some(mime) = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+    else {
+        return false;
+    };
+    let mime = mime.trim().to_ascii_lowercase();
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
@@ -1,25 +1,48 @@
-use crate::entities::revoked_refresh_tokens;
-use anyhow::Result;
-use chrono::Utc;
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
-use sea_orm::{DatabaseConnection, EntityTrait};
+use crate::config::{self, RefreshTokenExpiry};
+use crate::entities::{refresh_token_families, revoked_refresh_tokens, users};
+use crate::util::client_info::ClientInfo;
+use crate::util::jwt_keys::KeySet;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{TokenData, Validation};
+use sea_orm::sea_query::Expr;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, NotSet, QueryFilter,
+    QueryOrder, QuerySelect, QueryTrait, TransactionTrait,
+};
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use tracing::warn;
 use uuid::Uuid;
 
+/// 二要素認証の入力を待つ間のトークンの有効期限(秒)
+const MFA_TOKEN_EXPIRE_TIME: i64 = 60 * 5;
+
+/// * `aud`: トークンを受け入れるサービス。検証時に一致しない場合は無効
+/// * `jti`: トークンのID
+/// * `fam`: トークンが属するファミリーのID \
+///   ログイン毎に発行され、リフレッシュトークンのローテーションを追跡するのに使われる
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub iss: String,
+    pub aud: String,
     pub sub: Uuid,
     pub exp: i64,
     pub iat: i64,
     pub typ: Type,
+    pub jti: Uuid,
+    pub fam: Uuid,
 }
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
 pub enum Type {
     AccessToken,
     RefreshToken,
+    /// パスワードの検証に成功し、二要素認証の入力を待っている
+    MfaToken,
 }
 
 impl Display for Type {
@@ -27,6 +50,7 @@ impl Display for Type {
         let str = match self {
             Type::AccessToken => String::from("access_token"),
             Type::RefreshToken => String::from("refresh_token"),
+            Type::MfaToken => String::from("mfa_token"),
         };
         write!(f, "{}", str)
     }
@@ -34,91 +58,344 @@ impl Display for Type {
 
 #[derive(Serialize, Deserialize)]
 pub struct Tokens {
-    refresh_token: String,
-    access_token: String,
+    pub refresh_token: String,
+    pub access_token: String,
 }
 
 pub struct JWTManager {
-    pub algorithm: Algorithm,
     pub access_token_expire_time: i64,
     pub refresh_token_expire_time: i64,
+    pub refresh_token_expiry: RefreshTokenExpiry,
     pub iss: String,
-    encoding_key: EncodingKey,
-    decoding_key: DecodingKey,
+    pub aud: String,
+    keys: KeySet,
     db_conn: DatabaseConnection,
 }
 
 impl JWTManager {
-    pub fn new(
-        algorithm: Algorithm,
-        access_token_expire_time: i64,
-        refresh_token_expire_time: i64,
-        iss: impl ToString,
-        encoding_key: EncodingKey,
-        decoding_key: DecodingKey,
+    /// `issuer`が設定されていない場合は`base_url`を`iss`にする
+    pub fn from_config(
+        config: &config::Token,
+        base_url: &str,
+        keys: KeySet,
         db_conn: DatabaseConnection,
     ) -> Self {
+        let iss = config
+            .issuer
+            .clone()
+            .unwrap_or_else(|| base_url.to_string());
         Self {
-            algorithm,
-            access_token_expire_time,
-            refresh_token_expire_time,
-            iss: iss.to_string(),
-            encoding_key,
-            decoding_key,
+            access_token_expire_time: config.access_token_expire_time,
+            refresh_token_expire_time: config.refresh_token_expire_time,
+            refresh_token_expiry: config.refresh_token_expiry,
+            aud: config.audience.clone().unwrap_or_else(|| iss.clone()),
+            iss,
+            keys,
             db_conn,
         }
     }
 
     pub fn encode(&self, claims: &Claims) -> Result<String> {
-        Ok(jsonwebtoken::encode(
-            &Header::new(self.algorithm),
-            claims,
-            &self.encoding_key,
-        )?)
+        let (header, encoding_key) = self.keys.signing_key();
+        Ok(jsonwebtoken::encode(&header, claims, encoding_key)?)
     }
 
+    /// ヘッダーの`kid`に対応する鍵でトークンを検証する。`iss`と`aud`も検証する
     pub fn decode(&self, token: &str) -> Result<TokenData<Claims>> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let (algorithm, decoding_key) = self
+            .keys
+            .decoding_key(header.kid.as_deref())
+            .ok_or_else(|| anyhow!("unknown kid: {:?}", header.kid))?;
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[&self.iss]);
+        validation.set_audience(&[&self.aud]);
         Ok(jsonwebtoken::decode::<Claims>(
             token,
-            &self.decoding_key,
-            &Validation::new(self.algorithm),
+            decoding_key,
+            &validation,
         )?)
     }
 
-    pub fn issue_tokens(&self, sub: Uuid) -> Result<Tokens> {
-        return Ok(Tokens {
-            refresh_token: self.issue_refresh_token(sub.clone())?,
-            access_token: self.issue_access_token(sub.clone())?,
-        });
+    /// トークンの検証に使う公開鍵
+    pub fn jwks(&self) -> &JwkSet {
+        self.keys.jwks()
+    }
+
+    /// 新しいファミリーを作成し、トークンを発行する。
+    /// ファミリーはログイン毎のセッションとして`client`と共に記録される。
+    pub async fn issue_tokens(&self, sub: Uuid, client: &ClientInfo) -> Result<Tokens> {
+        let family_id = Uuid::new_v4();
+        let jti = Uuid::new_v4();
+        refresh_token_families::ActiveModel {
+            family_id: Set(family_id),
+            created_at: NotSet,
+            updated_at: NotSet,
+            user_id: Set(sub),
+            current_jti: Set(jti),
+            revoked: NotSet,
+            last_used_at: NotSet,
+            ip: Set(Some(client.ip.clone())),
+            user_agent: Set(client.user_agent.clone()),
+        }
+        .insert(&self.db_conn)
+        .await?;
+
+        let exp = Utc::now().timestamp() + self.refresh_token_expire_time;
+        Ok(Tokens {
+            refresh_token: self.issue_refresh_token(sub, family_id, jti, exp)?,
+            access_token: self.issue_access_token(sub, family_id)?,
+        })
     }
-    pub fn issue_refresh_token(&self, sub: Uuid) -> Result<String> {
+    pub fn issue_refresh_token(&self, sub: Uuid, fam: Uuid, jti: Uuid, exp: i64) -> Result<String> {
         let refresh_token_claims = Claims {
             iss: self.iss.clone(),
+            aud: self.aud.clone(),
             sub,
-            exp: Utc::now().timestamp() + self.refresh_token_expire_time,
+            exp,
             iat: Utc::now().timestamp(),
             typ: Type::RefreshToken,
+            jti,
+            fam,
         };
         let refresh_token = self.encode(&refresh_token_claims)?;
         Ok(refresh_token)
     }
-    pub fn issue_access_token(&self, sub: Uuid) -> Result<String> {
+    pub fn issue_access_token(&self, sub: Uuid, fam: Uuid) -> Result<String> {
         let access_token_claims = Claims {
             iss: self.iss.clone(),
+            aud: self.aud.clone(),
             sub,
             exp: Utc::now().timestamp() + self.access_token_expire_time,
             iat: Utc::now().timestamp(),
             typ: Type::AccessToken,
+            jti: Uuid::new_v4(),
+            fam,
         };
         let access_token = self.encode(&access_token_claims)?;
 
         Ok(access_token)
     }
 
+    /// 二要素認証の入力を待つ間のトークンを発行する。ファミリーには属さない
+    pub fn issue_mfa_token(&self, sub: Uuid) -> Result<String> {
+        let mfa_token_claims = Claims {
+            iss: self.iss.clone(),
+            aud: self.aud.clone(),
+            sub,
+            exp: Utc::now().timestamp() + MFA_TOKEN_EXPIRE_TIME,
+            iat: Utc::now().timestamp(),
+            typ: Type::MfaToken,
+            jti: Uuid::new_v4(),
+            fam: Uuid::nil(),
+        };
+        self.encode(&mfa_token_claims)
+    }
+
+    /// リフレッシュトークンをローテーションし、新しいトークンを発行する。
+    /// 使用されたリフレッシュトークンは失効する。
+    ///
+    /// トークンが無効な場合は`None`を返す。
+    /// ローテーション済みのトークンが再利用された場合(並行してローテーションされた場合を含む)は
+    /// ファミリー全体を失効させ、再ログインを要求する。
+    /// 管理者等によってファミリーが失効させられている場合もトークンは無効になる。
+    pub async fn rotate_tokens(
+        &self,
+        token: String,
+        claims: &Claims,
+        client: &ClientInfo,
+    ) -> Result<Option<Tokens>> {
+        // typ検証
+        if claims.typ != Type::RefreshToken {
+            return Ok(None);
+        }
+
+        // exp検証
+        if claims.exp < Utc::now().timestamp() {
+            return Ok(None);
+        }
+
+        // ファミリー検証
+        let family = match refresh_token_families::Entity::find_by_id(claims.fam)
+            .one(&self.db_conn)
+            .await?
+        {
+            Some(family) => family,
+            None => return Ok(None),
+        };
+        if family.revoked {
+            return Ok(None);
+        }
+
+        // 再利用検知
+        if family.current_jti != claims.jti || self.is_revoked(&token).await? {
+            warn!(
+                "reuse of rotated refresh token detected: family {} of user {}",
+                family.family_id, family.user_id
+            );
+            self.revoke_family(family.family_id).await?;
+            return Ok(None);
+        }
+
+        // ローテーション
+        let jti = Uuid::new_v4();
+        let txn = self.db_conn.begin().await?;
+        let result = refresh_token_families::Entity::update_many()
+            .col_expr(refresh_token_families::Column::CurrentJti, Expr::value(jti))
+            .col_expr(
+                refresh_token_families::Column::LastUsedAt,
+                Expr::value(Utc::now()),
+            )
+            .col_expr(
+                refresh_token_families::Column::Ip,
+                Expr::value(client.ip.clone()),
+            )
+            .col_expr(
+                refresh_token_families::Column::UserAgent,
+                Expr::value(client.user_agent.clone()),
+            )
+            .filter(refresh_token_families::Column::FamilyId.eq(claims.fam))
+            .filter(refresh_token_families::Column::CurrentJti.eq(claims.jti))
+            .filter(refresh_token_families::Column::Revoked.eq(false))
+            .exec(&txn)
+            .await?;
+        if result.rows_affected == 0 {
+            // 同じトークンによるローテーションが並行して行われた。再利用として扱う
+            txn.rollback().await?;
+            warn!(
+                "concurrent reuse of refresh token detected: family {} of user {}",
+                family.family_id, family.user_id
+            );
+            self.revoke_family(family.family_id).await?;
+            return Ok(None);
+        }
+        revoked_refresh_tokens::ActiveModel {
+            refresh_token: Set(token),
+            exp: Set(claims.exp as i32),
+        }
+        .insert(&txn)
+        .await?;
+        txn.commit().await?;
+
+        // Absoluteの場合はログイン時の期限を引き継ぐ
+        let exp = match self.refresh_token_expiry {
+            RefreshTokenExpiry::Sliding => Utc::now().timestamp() + self.refresh_token_expire_time,
+            RefreshTokenExpiry::Absolute => claims.exp,
+        };
+        Ok(Some(Tokens {
+            refresh_token: self.issue_refresh_token(claims.sub, claims.fam, jti, exp)?,
+            access_token: self.issue_access_token(claims.sub, claims.fam)?,
+        }))
+    }
+
+    /// リフレッシュトークンとそのファミリーを失効させる。
+    pub async fn revoke_refresh_token(&self, token: String, claims: &Claims) -> Result<()> {
+        let txn = self.db_conn.begin().await?;
+        revoked_refresh_tokens::ActiveModel {
+            refresh_token: Set(token),
+            exp: Set(claims.exp as i32),
+        }
+        .insert(&txn)
+        .await?;
+        refresh_token_families::Entity::update_many()
+            .col_expr(refresh_token_families::Column::Revoked, Expr::value(true))
+            .filter(refresh_token_families::Column::FamilyId.eq(claims.fam))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// ユーザーのすべてのファミリーを失効させる。
+    pub async fn revoke_all(&self, user_id: Uuid) -> Result<()> {
+        refresh_token_families::Entity::update_many()
+            .col_expr(refresh_token_families::Column::Revoked, Expr::value(true))
+            .filter(refresh_token_families::Column::UserId.eq(user_id))
+            .exec(&self.db_conn)
+            .await?;
+        Ok(())
+    }
+
+    /// ユーザーの有効なセッション(失効しておらず、リフレッシュトークンの期限が切れていないファミリー)を
+    /// 最後に使われた順に返す。
+    pub async fn sessions(&self, user_id: Uuid) -> Result<Vec<refresh_token_families::Model>> {
+        let oldest = Utc::now() - chrono::Duration::seconds(self.refresh_token_expire_time);
+        let expiry_base = match self.refresh_token_expiry {
+            RefreshTokenExpiry::Sliding => refresh_token_families::Column::LastUsedAt,
+            RefreshTokenExpiry::Absolute => refresh_token_families::Column::CreatedAt,
+        };
+        Ok(refresh_token_families::Entity::find()
+            .filter(refresh_token_families::Column::UserId.eq(user_id))
+            .filter(refresh_token_families::Column::Revoked.eq(false))
+            .filter(expiry_base.gt(oldest))
+            .order_by_desc(refresh_token_families::Column::LastUsedAt)
+            .all(&self.db_conn)
+            .await?)
+    }
+
+    /// セッションのリフレッシュトークンの期限が切れる日時
+    pub fn session_expires_at(
+        &self,
+        family: &refresh_token_families::Model,
+    ) -> DateTime<FixedOffset> {
+        let base = match self.refresh_token_expiry {
+            RefreshTokenExpiry::Sliding => family.last_used_at,
+            RefreshTokenExpiry::Absolute => family.created_at.unwrap_or(family.last_used_at),
+        };
+        base + chrono::Duration::seconds(self.refresh_token_expire_time)
+    }
+
+    /// ユーザーのセッションを1つ失効させる。有効なセッションが存在しなかった場合false
+    pub async fn revoke_session(&self, user_id: Uuid, family_id: Uuid) -> Result<bool> {
+        let result = refresh_token_families::Entity::update_many()
+            .col_expr(refresh_token_families::Column::Revoked, Expr::value(true))
+            .filter(refresh_token_families::Column::FamilyId.eq(family_id))
+            .filter(refresh_token_families::Column::UserId.eq(user_id))
+            .filter(refresh_token_families::Column::Revoked.eq(false))
+            .exec(&self.db_conn)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// 参加団体に属するすべてのユーザーのファミリーを失効させる。
+    pub async fn revoke_exhibitor(&self, exhibition_id: &str) -> Result<()> {
+        refresh_token_families::Entity::update_many()
+            .col_expr(refresh_token_families::Column::Revoked, Expr::value(true))
+            .filter(
+                refresh_token_families::Column::UserId.in_subquery(
+                    users::Entity::find()
+                        .select_only()
+                        .column(users::Column::Id)
+                        .filter(users::Column::ExhibitionId.eq(exhibition_id))
+                        .into_query(),
+                ),
+            )
+            .exec(&self.db_conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke_family(&self, family_id: Uuid) -> Result<()> {
+        refresh_token_families::Entity::update_many()
+            .col_expr(refresh_token_families::Column::Revoked, Expr::value(true))
+            .filter(refresh_token_families::Column::FamilyId.eq(family_id))
+            .exec(&self.db_conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn is_revoked(&self, token: &str) -> Result<bool> {
+        Ok(revoked_refresh_tokens::Entity::find_by_id(token)
+            .one(&self.db_conn)
+            .await?
+            .is_some())
+    }
+
     /// 以下の条件がすべて満たされる場合true、それ以外はfalse
     /// - `claims.typ`が`refresh_token`である。
     /// - 有効期限が切れていない
     /// - revokeされていない
+    /// - ファミリーが失効しておらず、ファミリーの最新のトークンである
     pub async fn is_refresh_token_valid(&self, token: String, claims: &Claims) -> Result<bool> {
         // typ検証
         if claims.typ != Type::RefreshToken {
@@ -126,20 +403,23 @@ impl JWTManager {
         }
 
         // exp検証
-        if claims.exp > Utc::now().timestamp() {
+        if claims.exp < Utc::now().timestamp() {
             return Ok(false);
         }
 
         // revoke検証
-        if revoked_refresh_tokens::Entity::find_by_id(token)
+        if self.is_revoked(&token).await? {
+            return Ok(false);
+        }
+
+        // ファミリー検証
+        match refresh_token_families::Entity::find_by_id(claims.fam)
             .one(&self.db_conn)
             .await?
-            != None
         {
-            return Ok(false);
+            Some(family) => Ok(!family.revoked && family.current_jti == claims.jti),
+            None => Ok(false),
         }
-
-        Ok(true)
     }
 
     /// 以下の条件がすべて満たされる場合true、それ以外はfalse
@@ -158,4 +438,21 @@ impl JWTManager {
 
         true
     }
+
+    /// 以下の条件がすべて満たされる場合true、それ以外はfalse
+    /// - `claims.typ`が`mfa_token`である。
+    /// - 有効期限が切れていない
+    pub fn is_mfa_token_valid(&self, claims: &Claims) -> bool {
+        // typ検証
+        if claims.typ != Type::MfaToken {
+            return false;
+        }
+
+        // exp検証
+        if claims.exp < Utc::now().timestamp() {
+            return false;
+        }
+
+        true
+    }
 }
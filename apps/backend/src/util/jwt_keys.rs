@@ -0,0 +1,332 @@
+use crate::config::{self, JwtAlgorithm};
+use anyhow::{anyhow, Context, Result};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+    EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+    PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::{DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::rand_core::OsRng;
+use rsa::traits::PublicKeyParts;
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use tracing::warn;
+
+/// 生成するRSA鍵のビット数
+const RSA_KEY_BITS: usize = 2048;
+
+/// JWTの署名と検証に使う鍵
+///
+/// 署名には`signing_kid`の鍵のみを使い、検証はJWTのヘッダーの`kid`で鍵を選ぶ。
+/// 検証用の公開鍵は`jwks`として`/.well-known/jwks.json`で公開される。
+pub struct KeySet {
+    signing_kid: String,
+    signing_algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_keys: HashMap<String, (Algorithm, DecodingKey)>,
+    jwks: JwkSet,
+}
+
+impl KeySet {
+    /// 設定された鍵を読み込む
+    ///
+    /// `generate_missing_keys`が有効で、署名に使う鍵のファイルが両方とも存在しない場合は鍵ペアを生成する。
+    pub fn from_config(config: &config::Jwt) -> Result<Self> {
+        let mut signing_key = None;
+        let mut decoding_keys = HashMap::new();
+        let mut jwks = Vec::new();
+        for key in &config.keys {
+            let algorithm = to_algorithm(key.algorithm);
+            if key.kid == config.signing_kid {
+                let path = key
+                    .secret_key_path
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("secret key of signing kid {} is not set", key.kid))?;
+                let missing =
+                    !Path::new(path).exists() && !Path::new(&key.public_key_path).exists();
+                if missing && config.generate_missing_keys {
+                    generate(key, path)?;
+                }
+                let secret_key_pem = fs::read(path).with_context(|| {
+                    if missing {
+                        format!(
+                            "failed to read {}; run `keygen` or set \
+                             web.auth.jwt.generate_missing_keys to generate a new key pair",
+                            path
+                        )
+                    } else {
+                        format!("failed to read {}", path)
+                    }
+                })?;
+                let encoding_key = match key.algorithm {
+                    JwtAlgorithm::RS256 => EncodingKey::from_rsa_pem(&secret_key_pem)?,
+                    JwtAlgorithm::ES256 => EncodingKey::from_ec_pem(&secret_key_pem)?,
+                    JwtAlgorithm::EdDSA => EncodingKey::from_ed_pem(&secret_key_pem)?,
+                };
+                signing_key = Some((algorithm, encoding_key));
+            }
+
+            let public_key_pem = fs::read_to_string(&key.public_key_path)
+                .with_context(|| format!("failed to read {}", key.public_key_path))?;
+            let decoding_key = match key.algorithm {
+                JwtAlgorithm::RS256 => DecodingKey::from_rsa_pem(public_key_pem.as_bytes())?,
+                JwtAlgorithm::ES256 => DecodingKey::from_ec_pem(public_key_pem.as_bytes())?,
+                JwtAlgorithm::EdDSA => DecodingKey::from_ed_pem(public_key_pem.as_bytes())?,
+            };
+            if decoding_keys
+                .insert(key.kid.clone(), (algorithm, decoding_key))
+                .is_some()
+            {
+                return Err(anyhow!("duplicate jwt kid: {}", key.kid));
+            }
+            jwks.push(to_jwk(key, &public_key_pem)?);
+        }
+        let (signing_algorithm, encoding_key) = signing_key
+            .ok_or_else(|| anyhow!("signing kid {} is not in jwt keys", config.signing_kid))?;
+
+        Ok(Self {
+            signing_kid: config.signing_kid.clone(),
+            signing_algorithm,
+            encoding_key,
+            decoding_keys,
+            jwks: JwkSet { keys: jwks },
+        })
+    }
+
+    /// 署名に使うヘッダー(`alg`と`kid`)と鍵
+    pub fn signing_key(&self) -> (Header, &EncodingKey) {
+        let mut header = Header::new(self.signing_algorithm);
+        header.kid = Some(self.signing_kid.clone());
+        (header, &self.encoding_key)
+    }
+
+    /// `kid`に対応する検証用の鍵とそのアルゴリズムを返す
+    ///
+    /// `kid`の無いトークンは複数の鍵に対応する前に発行されたものなので、署名に使っている鍵で検証する。
+    pub fn decoding_key(&self, kid: Option<&str>) -> Option<(Algorithm, &DecodingKey)> {
+        self.decoding_keys
+            .get(kid.unwrap_or(&self.signing_kid))
+            .map(|(algorithm, decoding_key)| (*algorithm, decoding_key))
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+/// 署名に使う鍵ペアのファイルが両方とも存在しない場合に生成する(`keygen`)。生成した場合は`true`を返す
+///
+/// 片方のみ存在する場合は、パスの誤りで鍵が入れ替わらないようエラーにする。
+pub fn generate_signing_key(config: &config::Jwt) -> Result<bool> {
+    let key = config
+        .keys
+        .iter()
+        .find(|key| key.kid == config.signing_kid)
+        .ok_or_else(|| anyhow!("signing kid {} is not in jwt keys", config.signing_kid))?;
+    let path = key
+        .secret_key_path
+        .as_ref()
+        .ok_or_else(|| anyhow!("secret key of signing kid {} is not set", key.kid))?;
+    match (
+        Path::new(path).exists(),
+        Path::new(&key.public_key_path).exists(),
+    ) {
+        (false, false) => {
+            generate(key, path)?;
+            Ok(true)
+        }
+        (true, true) => Ok(false),
+        _ => Err(anyhow!(
+            "only one of {} and {} exists",
+            path,
+            key.public_key_path
+        )),
+    }
+}
+
+fn to_algorithm(algorithm: JwtAlgorithm) -> Algorithm {
+    match algorithm {
+        JwtAlgorithm::RS256 => Algorithm::RS256,
+        JwtAlgorithm::ES256 => Algorithm::ES256,
+        JwtAlgorithm::EdDSA => Algorithm::EdDSA,
+    }
+}
+
+/// 鍵ペアを生成し、秘密鍵は所有者のみが読み書きできる権限で保存する
+fn generate(key: &config::JwtKey, secret_key_path: &str) -> Result<()> {
+    let (secret_key_pem, public_key_pem) = match key.algorithm {
+        JwtAlgorithm::RS256 => {
+            let secret_key = RsaPrivateKey::new(&mut OsRng, RSA_KEY_BITS)?;
+            (
+                secret_key.to_pkcs8_pem(LineEnding::LF)?,
+                RsaPublicKey::from(&secret_key).to_public_key_pem(LineEnding::LF)?,
+            )
+        }
+        JwtAlgorithm::ES256 => {
+            let secret_key = p256::SecretKey::random(&mut OsRng);
+            (
+                secret_key.to_pkcs8_pem(LineEnding::LF)?,
+                secret_key.public_key().to_public_key_pem(LineEnding::LF)?,
+            )
+        }
+        JwtAlgorithm::EdDSA => {
+            let secret_key = ed25519_dalek::SigningKey::generate(&mut OsRng);
+            (
+                secret_key.to_pkcs8_pem(LineEnding::LF)?,
+                secret_key
+                    .verifying_key()
+                    .to_public_key_pem(LineEnding::LF)?,
+            )
+        }
+    };
+    write_new_file(secret_key_path, secret_key_pem.as_bytes(), 0o600)?;
+    write_new_file(&key.public_key_path, public_key_pem.as_bytes(), 0o644)?;
+    warn!(
+        "generated new {:?} key pair for kid {}: {}, {}",
+        key.algorithm, key.kid, secret_key_path, key.public_key_path
+    );
+    Ok(())
+}
+
+/// 既存のファイルは上書きしない
+fn write_new_file(path: &str, contents: &[u8], mode: u32) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)
+        .with_context(|| format!("failed to create {}", path))?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+/// 公開鍵(PEM)をJWKに変換する
+fn to_jwk(key: &config::JwtKey, pem: &str) -> Result<Jwk> {
+    let (key_algorithm, algorithm) = match key.algorithm {
+        JwtAlgorithm::RS256 => {
+            // RSAの公開鍵はPKCS#1のPEMも受け付ける
+            let public_key = RsaPublicKey::from_public_key_pem(pem)
+                .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+                .map_err(|err| anyhow!("invalid rsa public key {}: {}", key.kid, err))?;
+            (
+                KeyAlgorithm::RS256,
+                AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: base64url::encode(&public_key.n().to_bytes_be()),
+                    e: base64url::encode(&public_key.e().to_bytes_be()),
+                }),
+            )
+        }
+        JwtAlgorithm::ES256 => {
+            let public_key = p256::PublicKey::from_public_key_pem(pem)
+                .map_err(|err| anyhow!("invalid p-256 public key {}: {}", key.kid, err))?;
+            let point = public_key.to_encoded_point(false);
+            let (Some(x), Some(y)) = (point.x(), point.y()) else {
+                return Err(anyhow!("invalid p-256 public key {}", key.kid));
+            };
+            (
+                KeyAlgorithm::ES256,
+                AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve: EllipticCurve::P256,
+                    x: base64url::encode(x),
+                    y: base64url::encode(y),
+                }),
+            )
+        }
+        JwtAlgorithm::EdDSA => {
+            let public_key = ed25519_dalek::VerifyingKey::from_public_key_pem(pem)
+                .map_err(|err| anyhow!("invalid ed25519 public key {}: {}", key.kid, err))?;
+            (
+                KeyAlgorithm::EdDSA,
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: base64url::encode(public_key.as_bytes()),
+                }),
+            )
+        }
+    };
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(key.kid.clone()),
+            ..Default::default()
+        },
+        algorithm,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn config(dir: &Path, generate_missing_keys: bool) -> config::Jwt {
+        config::Jwt {
+            signing_kid: "test".to_string(),
+            keys: vec![config::JwtKey {
+                kid: "test".to_string(),
+                algorithm: JwtAlgorithm::EdDSA,
+                secret_key_path: Some(dir.join("secret_key").to_string_lossy().into_owned()),
+                public_key_path: dir.join("public_key").to_string_lossy().into_owned(),
+            }],
+            generate_missing_keys,
+        }
+    }
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("jwt_keys_{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn does_not_generate_missing_keys_by_default() {
+        let dir = temp_dir();
+        assert!(KeySet::from_config(&config(&dir, false)).is_err());
+        assert!(!dir.join("secret_key").exists());
+        assert!(!dir.join("public_key").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn generates_missing_keys_when_enabled() {
+        let dir = temp_dir();
+        let keys = KeySet::from_config(&config(&dir, true)).unwrap();
+        assert_eq!(keys.jwks().keys.len(), 1);
+        assert!(keys.decoding_key(Some("test")).is_some());
+
+        // 生成した鍵は次の起動で読み込まれ、置き換えられない
+        let secret_key = fs::read(dir.join("secret_key")).unwrap();
+        KeySet::from_config(&config(&dir, true)).unwrap();
+        assert_eq!(fs::read(dir.join("secret_key")).unwrap(), secret_key);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keygen_generates_only_missing_key_pairs() {
+        let dir = temp_dir();
+        let config = config(&dir, false);
+        assert!(generate_signing_key(&config).unwrap());
+        KeySet::from_config(&config).unwrap();
+
+        let secret_key = fs::read(dir.join("secret_key")).unwrap();
+        assert!(!generate_signing_key(&config).unwrap());
+        assert_eq!(fs::read(dir.join("secret_key")).unwrap(), secret_key);
+
+        // 片方のみ存在する場合は生成しない
+        fs::remove_file(dir.join("public_key")).unwrap();
+        assert!(generate_signing_key(&config).is_err());
+        assert!(!dir.join("public_key").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
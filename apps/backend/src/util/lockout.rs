@@ -0,0 +1,163 @@
+use crate::config;
+use crate::entities::sea_orm_active_enums::LoginOutcome;
+use crate::entities::{login_attempts, users};
+use crate::util::client_info::ClientInfo;
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use sea_orm::sea_query::Expr;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, EntityTrait, NotSet, QueryFilter};
+use tracing::info;
+use uuid::Uuid;
+
+/// ロックされている場合、ロックが解除される日時を返す
+pub fn locked_until(user: &users::Model) -> Option<DateTime<FixedOffset>> {
+    user.locked_until.filter(|until| *until > Utc::now())
+}
+
+/// ロックが解除されるまでの秒数(`Retry-After`)
+pub fn retry_after(locked_until: DateTime<FixedOffset>) -> i64 {
+    (locked_until.timestamp() - Utc::now().timestamp()).max(1)
+}
+
+/// ログイン試行を`login_attempts`に記録する
+pub async fn record<C: ConnectionTrait>(
+    db: &C,
+    user_id: Option<Uuid>,
+    m_address: &str,
+    client: &ClientInfo,
+    outcome: LoginOutcome,
+) -> Result<()> {
+    login_attempts::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        created_at: NotSet,
+        user_id: Set(user_id),
+        m_address: Set(m_address.to_string()),
+        ip: Set(client.ip.clone()),
+        user_agent: Set(client.user_agent.clone()),
+        outcome: Set(outcome),
+    }
+    .insert(db)
+    .await?;
+    Ok(())
+}
+
+/// 連続して失敗した回数を増やし、`threshold`回に達していればロックする
+///
+/// ロック時間は`base_lock_time`から失敗する度に倍になり、`max_lock_time`で頭打ちになる。
+/// ロックした場合はロックが解除される日時を返す。
+pub async fn register_failure<C: ConnectionTrait>(
+    db: &C,
+    config: &config::Lockout,
+    user_id: Uuid,
+) -> Result<Option<DateTime<Utc>>> {
+    // 並行して失敗した場合も数え漏らさないよう、DB上で加算する
+    let updated = users::Entity::update_many()
+        .col_expr(
+            users::Column::FailedLoginCount,
+            Expr::col(users::Column::FailedLoginCount).add(1),
+        )
+        .filter(users::Column::Id.eq(user_id))
+        .exec_with_returning(db)
+        .await?;
+    let failed_login_count = match updated.first() {
+        Some(user) => user.failed_login_count,
+        None => return Ok(None),
+    };
+    let Some(lock_time) = lock_time(config, failed_login_count) else {
+        return Ok(None);
+    };
+    let locked_until = Utc::now() + chrono::Duration::seconds(lock_time);
+    users::Entity::update_many()
+        .col_expr(users::Column::LockedUntil, Expr::value(locked_until))
+        .filter(users::Column::Id.eq(user_id))
+        .exec(db)
+        .await?;
+    info!(
+        "user {} locked until {} after {} failed logins",
+        user_id, locked_until, failed_login_count
+    );
+    Ok(Some(locked_until))
+}
+
+/// 連続して`failed_login_count`回失敗した場合のロック時間(秒)。ロックしない場合は`None`
+fn lock_time(config: &config::Lockout, failed_login_count: i32) -> Option<i64> {
+    let excess = failed_login_count.checked_sub(config.threshold)?;
+    if excess < 0 {
+        return None;
+    }
+    Some(
+        config
+            .base_lock_time
+            .saturating_mul(1 << excess.min(30))
+            .min(config.max_lock_time),
+    )
+}
+
+/// 失敗した回数とロックを解除する
+pub async fn reset<C: ConnectionTrait>(db: &C, user_id: Uuid) -> Result<()> {
+    users::Entity::update_many()
+        .col_expr(users::Column::FailedLoginCount, Expr::value(0))
+        .col_expr(
+            users::Column::LockedUntil,
+            Expr::value(Option::<DateTime<Utc>>::None),
+        )
+        .filter(users::Column::Id.eq(user_id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> config::Lockout {
+        config::Lockout {
+            threshold: 5,
+            base_lock_time: 60,
+            max_lock_time: 60 * 60,
+        }
+    }
+
+    #[test]
+    fn does_not_lock_below_threshold() {
+        assert_eq!(lock_time(&config(), 0), None);
+        assert_eq!(lock_time(&config(), 4), None);
+    }
+
+    #[test]
+    fn locks_for_base_time_at_threshold() {
+        assert_eq!(lock_time(&config(), 5), Some(60));
+    }
+
+    #[test]
+    fn doubles_lock_time_after_threshold() {
+        assert_eq!(lock_time(&config(), 6), Some(120));
+        assert_eq!(lock_time(&config(), 7), Some(240));
+        assert_eq!(lock_time(&config(), 10), Some(60 * 32));
+    }
+
+    #[test]
+    fn caps_lock_time_at_max_lock_time() {
+        assert_eq!(lock_time(&config(), 11), Some(60 * 60));
+        assert_eq!(lock_time(&config(), 40), Some(60 * 60));
+        assert_eq!(lock_time(&config(), i32::MAX), Some(60 * 60));
+    }
+
+    #[test]
+    fn does_not_overflow_with_large_base_lock_time() {
+        let config = config::Lockout {
+            threshold: 1,
+            base_lock_time: i64::MAX / 2,
+            max_lock_time: i64::MAX,
+        };
+        assert_eq!(lock_time(&config, 1), Some(i64::MAX / 2));
+        assert_eq!(lock_time(&config, 100), Some(i64::MAX));
+    }
+
+    #[test]
+    fn handles_negative_count_without_overflow() {
+        assert_eq!(lock_time(&config(), i32::MIN), None);
+    }
+}
@@ -1,13 +1,27 @@
+use crate::permissions::RoleClaims;
+use anyhow::{anyhow, Result};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet, PublicKeyUse};
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
 use oauth2::basic::{BasicErrorResponseType, BasicRevocationErrorResponse};
 use oauth2::StandardRevocableToken;
 use openidconnect::core::{
-    CoreAuthDisplay, CoreAuthPrompt, CoreGenderClaim, CoreJsonWebKey,
-    CoreJweContentEncryptionAlgorithm, CoreTokenIntrospectionResponse, CoreTokenResponse,
+    CoreAuthDisplay, CoreAuthPrompt, CoreClaimName, CoreClaimType, CoreClientAuthMethod,
+    CoreGenderClaim, CoreGrantType, CoreJsonWebKey, CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm, CoreResponseMode, CoreResponseType, CoreSubjectIdentifierType,
+    CoreTokenIntrospectionResponse, CoreTokenResponse,
 };
 use openidconnect::{
-    Client, EmptyAdditionalClaims, EndpointMaybeSet, EndpointNotSet, EndpointSet,
-    StandardErrorResponse,
+    AdditionalProviderMetadata, Client, EmptyAdditionalClaims, EndpointMaybeSet, EndpointNotSet,
+    EndpointSet, LogoutProviderMetadata, ProviderMetadata, RevocationUrl, StandardErrorResponse,
+    UserInfoClaims,
 };
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
 
 pub type OIDCClient = Client<
     EmptyAdditionalClaims,
@@ -24,7 +38,328 @@ pub type OIDCClient = Client<
     EndpointSet,
     EndpointNotSet,
     EndpointNotSet,
-    EndpointNotSet,
+    EndpointSet,
     EndpointMaybeSet,
     EndpointMaybeSet,
 >;
+
+/// RP-Initiated Logoutの`end_session_endpoint`とRFC 7009の`revocation_endpoint`を含むプロバイダのメタデータ
+pub type OIDCProviderMetadata = ProviderMetadata<
+    LogoutProviderMetadata<RevocationProviderMetadata>,
+    CoreAuthDisplay,
+    CoreClientAuthMethod,
+    CoreClaimName,
+    CoreClaimType,
+    CoreGrantType,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm,
+    CoreJsonWebKey,
+    CoreResponseMode,
+    CoreResponseType,
+    CoreSubjectIdentifierType,
+>;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RevocationProviderMetadata {
+    pub revocation_endpoint: RevocationUrl,
+}
+
+impl AdditionalProviderMetadata for RevocationProviderMetadata {}
+
+/// 管理者のアクセストークンのクレーム
+/// * `roles`: 権限の判定に使うロールとグループ
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminClaims {
+    pub iss: String,
+    pub sub: String,
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub roles: RoleClaims,
+}
+
+impl From<UserInfoClaims<RoleClaims, CoreGenderClaim>> for AdminClaims {
+    fn from(user_info: UserInfoClaims<RoleClaims, CoreGenderClaim>) -> Self {
+        Self {
+            iss: user_info
+                .issuer()
+                .map(|issuer| issuer.to_string())
+                .unwrap_or_default(),
+            sub: user_info.subject().to_string(),
+            preferred_username: user_info
+                .preferred_username()
+                .map(|username| username.to_string()),
+            email: user_info.email().map(|email| email.to_string()),
+            name: user_info
+                .name()
+                .and_then(|name| name.get(None))
+                .map(|name| name.to_string()),
+            roles: user_info.additional_claims().clone(),
+        }
+    }
+}
+
+/// JWKSでの検証のエラー
+pub enum VerifyError {
+    /// JWKSでは検証できない(JWTでない、署名鍵が見つからない、JWKSを取得できない)
+    Unverifiable(anyhow::Error),
+    /// 署名や`iss`、`aud`、`exp`の検証に失敗した
+    Invalid(jsonwebtoken::errors::Error),
+}
+
+impl Debug for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unverifiable(err) => write!(f, "Unverifiable({})", err),
+            Self::Invalid(err) => write!(f, "Invalid({})", err),
+        }
+    }
+}
+
+/// アクセストークンが自分に宛てたものであることの確認方法
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Audience {
+    /// `aud`に含まれていることを要求する
+    Aud(String),
+    /// `azp`(トークンを要求したクライアント)が一致することを要求する
+    ///
+    /// Keycloakのアクセストークンの`aud`はAudienceマッパーを設定しない限り`account`で、クライアントIDは`azp`にのみ入る。
+    Azp(String),
+}
+
+impl Audience {
+    /// `audience`が設定されていれば`aud`を、されていなければクライアントIDを`azp`で確認する
+    pub fn new(audience: Option<String>, client_id: impl ToString) -> Self {
+        match audience {
+            Some(audience) => Self::Aud(audience),
+            None => Self::Azp(client_id.to_string()),
+        }
+    }
+}
+
+/// 検証時のみ使うクレーム
+#[derive(Deserialize)]
+struct VerifiedClaims {
+    azp: Option<String>,
+    #[serde(flatten)]
+    claims: AdminClaims,
+}
+
+/// 未知の`kid`によってJWKSを再取得する最短の間隔
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// プロバイダのJWKSを`kid`毎にキャッシュし、アクセストークンを検証する
+///
+/// キャッシュは`refresh_interval`毎に再取得する。鍵のローテーションに対応するため、未知の`kid`の場合も再取得する。
+pub struct JwksCache {
+    jwks_uri: String,
+    issuer: String,
+    audience: Audience,
+    refresh_interval: Duration,
+    http_client: reqwest::Client,
+    keys: RwLock<CachedKeys>,
+}
+
+#[derive(Default)]
+struct CachedKeys {
+    keys: HashMap<String, DecodingKey>,
+    /// 最後に取得に成功した時刻
+    fetched_at: Option<Instant>,
+    /// 最後に取得を試みた時刻
+    attempted_at: Option<Instant>,
+}
+
+impl JwksCache {
+    pub fn new(
+        jwks_uri: impl ToString,
+        issuer: impl ToString,
+        audience: Audience,
+        refresh_interval: Duration,
+        http_client: reqwest::Client,
+    ) -> Self {
+        Self {
+            jwks_uri: jwks_uri.to_string(),
+            issuer: issuer.to_string(),
+            audience,
+            refresh_interval,
+            http_client,
+            keys: Default::default(),
+        }
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<AdminClaims, VerifyError> {
+        let header = decode_header(token).map_err(|err| VerifyError::Unverifiable(err.into()))?;
+        let kid = header
+            .kid
+            .ok_or_else(|| VerifyError::Unverifiable(anyhow!("kid not found in the header")))?;
+        let key = self
+            .key(&kid)
+            .await
+            .map_err(VerifyError::Unverifiable)?
+            .ok_or_else(|| VerifyError::Unverifiable(anyhow!("unknown kid: {}", kid)))?;
+
+        // 鍵の種類と異なるアルゴリズム(HS256等)はdecodeで拒否される
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        match &self.audience {
+            Audience::Aud(audience) => {
+                validation.set_audience(&[audience]);
+                validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+            }
+            Audience::Azp(_) => {
+                validation.validate_aud = false;
+                validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+            }
+        }
+        let claims = decode::<VerifiedClaims>(token, &key, &validation)
+            .map_err(VerifyError::Invalid)?
+            .claims;
+        if let Audience::Azp(client_id) = &self.audience {
+            if claims.azp.as_deref() != Some(client_id.as_str()) {
+                return Err(VerifyError::Invalid(ErrorKind::InvalidAudience.into()));
+            }
+        }
+        Ok(claims.claims)
+    }
+
+    /// JWKSを取得し直す
+    pub async fn refresh(&self) -> Result<()> {
+        let mut keys = self.keys.write().await;
+        self.fetch(&mut keys).await
+    }
+
+    async fn key(&self, kid: &str) -> Result<Option<DecodingKey>> {
+        {
+            let keys = self.keys.read().await;
+            if keys.is_fresh(self.refresh_interval) {
+                if let Some(key) = keys.keys.get(kid) {
+                    return Ok(Some(key.clone()));
+                }
+            }
+            if keys.attempted_within(MIN_REFETCH_INTERVAL) {
+                return Ok(keys.keys.get(kid).cloned());
+            }
+        }
+
+        let mut keys = self.keys.write().await;
+        // ロックを待っている間に他のリクエストが取得した場合は取得しない
+        if !keys.attempted_within(MIN_REFETCH_INTERVAL) {
+            if let Err(err) = self.fetch(&mut keys).await {
+                if keys.fetched_at.is_none() {
+                    return Err(err);
+                }
+                warn!("failed to refresh jwks, using cached keys: {}", err);
+            }
+        }
+        Ok(keys.keys.get(kid).cloned())
+    }
+
+    async fn fetch(&self, keys: &mut CachedKeys) -> Result<()> {
+        keys.attempted_at = Some(Instant::now());
+        let body = self
+            .http_client
+            .get(&self.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let jwks: JwkSet = serde_json::from_slice(&body)?;
+
+        // 暗号化用の鍵や共通鍵は署名の検証に使わない
+        keys.keys = jwks
+            .keys
+            .iter()
+            .filter(|jwk| jwk.common.public_key_use != Some(PublicKeyUse::Encryption))
+            .filter(|jwk| !matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)))
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                match DecodingKey::from_jwk(jwk) {
+                    Ok(key) => Some((kid, key)),
+                    Err(err) => {
+                        warn!("ignored jwk {}: {}", kid, err);
+                        None
+                    }
+                }
+            })
+            .collect();
+        keys.fetched_at = Some(Instant::now());
+        debug!("jwks fetched: {} keys", keys.keys.len());
+        Ok(())
+    }
+}
+
+impl CachedKeys {
+    fn is_fresh(&self, refresh_interval: Duration) -> bool {
+        self.fetched_at
+            .is_some_and(|fetched_at| fetched_at.elapsed() < refresh_interval)
+    }
+
+    fn attempted_within(&self, interval: Duration) -> bool {
+        self.attempted_at
+            .is_some_and(|attempted_at| attempted_at.elapsed() < interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"secret";
+
+    async fn cache(audience: Audience) -> JwksCache {
+        let cache = JwksCache::new(
+            "http://127.0.0.1:0/jwks",
+            "https://sso.example.com/realms/koudaisai",
+            audience,
+            Duration::from_secs(60 * 60),
+            reqwest::Client::new(),
+        );
+        {
+            let mut keys = cache.keys.write().await;
+            keys.keys
+                .insert("kid".to_string(), DecodingKey::from_secret(SECRET));
+            keys.fetched_at = Some(Instant::now());
+        }
+        cache
+    }
+
+    fn token(aud: &str, azp: &str) -> String {
+        let header = Header {
+            kid: Some("kid".to_string()),
+            ..Default::default()
+        };
+        let claims = json!({
+            "iss": "https://sso.example.com/realms/koudaisai",
+            "sub": "admin",
+            "aud": aud,
+            "azp": azp,
+            "exp": chrono::Utc::now().timestamp() + 60,
+        });
+        encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn accepts_keycloak_token_by_azp_without_audience() {
+        let cache = cache(Audience::new(None, "portal")).await;
+        let claims = cache.verify(&token("account", "portal")).await.unwrap();
+        assert_eq!(claims.sub, "admin");
+        assert!(matches!(
+            cache.verify(&token("account", "other")).await,
+            Err(VerifyError::Invalid(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn requires_aud_when_audience_is_set() {
+        let cache = cache(Audience::new(Some("portal-api".to_string()), "portal")).await;
+        assert!(cache.verify(&token("portal-api", "other")).await.is_ok());
+        assert!(matches!(
+            cache.verify(&token("account", "portal")).await,
+            Err(VerifyError::Invalid(_))
+        ));
+    }
+}
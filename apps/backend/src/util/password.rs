@@ -0,0 +1,144 @@
+use crate::config;
+use crate::util::sha::{digest, stretch_with_salt};
+use anyhow::{anyhow, Result};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordVerifier, Version};
+use std::sync::Arc;
+use tokio::task::spawn_blocking;
+
+/// パスワードハッシュ関数
+///
+/// ハッシュはPHC形式の文字列として`users.password_hash`に保存される。
+/// 計算に時間のかかる処理のため、`PasswordManager`を通してblocking poolで実行される。
+pub trait PasswordHasher: Send + Sync {
+    /// パスワードをハッシュ化し、PHC形式の文字列を返す
+    fn hash(&self, password: &str) -> Result<String>;
+    /// パスワードがPHC形式のハッシュと一致するか検証する
+    fn verify(&self, password: &str, hash: &str) -> Result<bool>;
+    /// ハッシュのアルゴリズムやパラメータが現在の設定と異なる場合true
+    fn needs_rehash(&self, hash: &str) -> bool;
+}
+
+/// Argon2id
+pub struct Argon2idHasher {
+    params: Params,
+}
+
+impl Argon2idHasher {
+    pub fn new(memory_cost: u32, time_cost: u32, parallelism: u32) -> Result<Self> {
+        let params = Params::new(memory_cost, time_cost, parallelism, None)
+            .map_err(|err| anyhow!("invalid argon2 params: {}", err))?;
+        Ok(Self { params })
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+impl PasswordHasher for Argon2idHasher {
+    fn hash(&self, password: &str) -> Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash =
+            argon2::PasswordHasher::hash_password(&self.argon2(), password.as_bytes(), &salt)
+                .map_err(|err| anyhow!("failed to hash password: {}", err))?;
+        Ok(hash.to_string())
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool> {
+        let hash =
+            PasswordHash::new(hash).map_err(|err| anyhow!("invalid password hash: {}", err))?;
+        match self.argon2().verify_password(password.as_bytes(), &hash) {
+            Ok(_) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(err) => Err(anyhow!("failed to verify password: {}", err)),
+        }
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        let hash = match PasswordHash::new(hash) {
+            Ok(hash) => hash,
+            Err(_) => return true,
+        };
+        if hash.algorithm != Algorithm::Argon2id.ident() {
+            return true;
+        }
+        match Params::try_from(&hash) {
+            Ok(params) => params != self.params,
+            Err(_) => true,
+        }
+    }
+}
+
+/// パスワードの検証結果
+#[derive(Debug, Eq, PartialEq)]
+pub enum Verification {
+    /// パスワードが一致しない
+    Invalid,
+    /// パスワードが一致する
+    Valid,
+    /// パスワードは一致するが、旧形式のハッシュまたは古いパラメータで保存されている
+    ValidNeedsRehash,
+}
+
+pub struct PasswordManager {
+    hasher: Arc<dyn PasswordHasher>,
+    legacy_stretch_cost: i32,
+}
+
+impl PasswordManager {
+    pub fn new(hasher: Arc<dyn PasswordHasher>, legacy_stretch_cost: i32) -> Self {
+        Self {
+            hasher,
+            legacy_stretch_cost,
+        }
+    }
+
+    pub fn from_config(auth: &config::Auth) -> Result<Self> {
+        let hasher: Arc<dyn PasswordHasher> = match auth.password_hash.algorithm {
+            config::PasswordHashAlgorithm::Argon2id => Arc::new(Argon2idHasher::new(
+                auth.password_hash.memory_cost,
+                auth.password_hash.time_cost,
+                auth.password_hash.parallelism,
+            )?),
+        };
+        Ok(Self::new(hasher, 2_i32.pow(auth.stretch_cost as u32)))
+    }
+
+    pub async fn hash(&self, password: String) -> Result<String> {
+        let hasher = self.hasher.clone();
+        spawn_blocking(move || hasher.hash(&password)).await?
+    }
+
+    /// パスワードを検証する
+    /// * `salt`: 旧形式(SHA-256のストレッチング)のハッシュの検証に使うユーザー毎のsalt
+    pub async fn verify(&self, password: String, salt: &str, hash: String) -> Result<Verification> {
+        if is_legacy_hash(&hash) {
+            let prompted_hash = stretch_with_salt(&password, salt, self.legacy_stretch_cost).await;
+            //文字列比較の計算時間からハッシュを推測されないようにdigestしてから比較
+            return if digest(&prompted_hash) == digest(&hash) {
+                Ok(Verification::ValidNeedsRehash)
+            } else {
+                Ok(Verification::Invalid)
+            };
+        }
+
+        let hasher = self.hasher.clone();
+        spawn_blocking(move || {
+            if !hasher.verify(&password, &hash)? {
+                Ok(Verification::Invalid)
+            } else if hasher.needs_rehash(&hash) {
+                Ok(Verification::ValidNeedsRehash)
+            } else {
+                Ok(Verification::Valid)
+            }
+        })
+        .await?
+    }
+}
+
+/// PHC形式でない(SHA-256のストレッチングによる16進数の)ハッシュの場合true
+fn is_legacy_hash(hash: &str) -> bool {
+    !hash.starts_with('$')
+}
@@ -0,0 +1,126 @@
+use crate::config;
+use crate::util::jwt::Tokens;
+use crate::util::sha::digest;
+use crate::util::token;
+use axum::http::{HeaderMap, Method};
+use axum_extra::extract::cookie::{Cookie, CookieJar};
+
+pub const ACCESS_TOKEN_COOKIE: &str = "access_token";
+pub const REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+pub const CSRF_TOKEN_COOKIE: &str = "csrf_token";
+pub const CSRF_TOKEN_HEADER: &str = "x-csrf-token";
+
+/// アクセストークンはAPIと認証API(`/auth/v1/sessions`)の両方に送る
+const ACCESS_TOKEN_PATH: &str = "/";
+/// リフレッシュトークンは認証APIにのみ送る
+const REFRESH_TOKEN_PATH: &str = "/auth/v1";
+/// CSRFトークンはどのページのJavaScriptからも読めるようにする
+const CSRF_TOKEN_PATH: &str = "/";
+
+/// トークンとCSRFトークンのCookieを`jar`に追加する
+///
+/// 戻り値は`(Cookieを追加したjar, CSRFトークン)`。
+/// CSRFトークンは`X-CSRF-Token`ヘッダーに付けて送り返してもらう(double-submit)。
+pub fn set_tokens(
+    jar: CookieJar,
+    config: &config::Cookie,
+    tokens: &Tokens,
+    access_token_expire_time: i64,
+    refresh_token_expire_time: i64,
+) -> (CookieJar, String) {
+    let (csrf_token, _) = token::generate();
+    let jar = jar
+        .add(build(
+            config,
+            ACCESS_TOKEN_COOKIE,
+            tokens.access_token.clone(),
+            ACCESS_TOKEN_PATH,
+            true,
+            access_token_expire_time,
+        ))
+        .add(build(
+            config,
+            REFRESH_TOKEN_COOKIE,
+            tokens.refresh_token.clone(),
+            REFRESH_TOKEN_PATH,
+            true,
+            refresh_token_expire_time,
+        ))
+        .add(build(
+            config,
+            CSRF_TOKEN_COOKIE,
+            csrf_token.clone(),
+            CSRF_TOKEN_PATH,
+            false,
+            refresh_token_expire_time,
+        ));
+    (jar, csrf_token)
+}
+
+/// `set_tokens`で追加したCookieを削除する
+pub fn clear(jar: CookieJar, config: &config::Cookie) -> CookieJar {
+    [
+        (ACCESS_TOKEN_COOKIE, ACCESS_TOKEN_PATH),
+        (REFRESH_TOKEN_COOKIE, REFRESH_TOKEN_PATH),
+        (CSRF_TOKEN_COOKIE, CSRF_TOKEN_PATH),
+    ]
+    .into_iter()
+    .fold(jar, |jar, (name, path)| {
+        // 削除するCookieはpathとdomainが一致している必要がある
+        let mut cookie = Cookie::build((name, "")).path(path);
+        if let Some(domain) = &config.domain {
+            cookie = cookie.domain(domain.clone());
+        }
+        jar.remove(cookie)
+    })
+}
+
+/// `X-CSRF-Token`ヘッダーの値が`csrf_token`のCookieと一致する場合true
+pub fn verify_csrf(jar: &CookieJar, headers: &HeaderMap) -> bool {
+    let Some(cookie) = jar.get(CSRF_TOKEN_COOKIE) else {
+        return false;
+    };
+    let Some(header) = headers
+        .get(CSRF_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    // 比較にかかる時間からトークンを推測されないよう、ハッシュ同士を比較する
+    !cookie.value().is_empty() && digest(cookie.value()) == digest(header)
+}
+
+/// 状態を変更しないメソッドの場合true。これらのリクエストにはCSRFトークンを要求しない
+pub fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn build(
+    config: &config::Cookie,
+    name: &'static str,
+    value: String,
+    path: &'static str,
+    http_only: bool,
+    max_age: i64,
+) -> Cookie<'static> {
+    let mut cookie = Cookie::build((name, value))
+        .path(path)
+        .http_only(http_only)
+        .secure(config.secure)
+        .same_site(config.same_site.into())
+        .max_age(time::Duration::seconds(max_age));
+    if let Some(domain) = &config.domain {
+        cookie = cookie.domain(domain.clone());
+    }
+    cookie.build()
+}
+
+impl From<config::SameSite> for axum_extra::extract::cookie::SameSite {
+    fn from(same_site: config::SameSite) -> Self {
+        match same_site {
+            config::SameSite::Strict => Self::Strict,
+            config::SameSite::Lax => Self::Lax,
+            config::SameSite::None => Self::None,
+        }
+    }
+}
@@ -1,22 +1,20 @@
 use sha2::digest::Update;
 use sha2::{Digest, Sha256};
+use tokio::task::spawn_blocking;
 
-pub struct SHAManager {
-    pub(crate) stretch_cost: i32,
-}
-
-impl SHAManager {
-    pub async fn stretch_with_salt(&self, data: &str, salt: &str) -> String {
-        stretch_with_salt(data, salt, self.stretch_cost).await
-    }
-}
-
+/// ストレッチングはworkerを占有しないようblocking poolで実行する
 pub async fn stretch_with_salt(data: &str, salt: &str, n: i32) -> String {
-    (0..n).fold(data.to_string(), |data, _| digest_with_salt(&*data, salt))
+    let (data, salt) = (data.to_string(), salt.to_string());
+    spawn_blocking(move || (0..n).fold(data, |data, _| digest_with_salt(&data, &salt)))
+        .await
+        .expect("stretching task panicked")
 }
 
 pub async fn stretch(data: &str, n: i32) -> String {
-    (0..n).fold(data.to_string(), |data, _| digest(&*data))
+    let data = data.to_string();
+    spawn_blocking(move || (0..n).fold(data, |data, _| digest(&data)))
+        .await
+        .expect("stretching task panicked")
 }
 
 pub fn digest_with_salt(data: &str, salt: &str) -> String {
@@ -0,0 +1,17 @@
+use crate::util::sha::digest;
+use rand::distr::{Alphanumeric, SampleString};
+
+const TOKEN_LENGTH: usize = 43;
+
+/// メールで送るような使い捨てのトークンを生成する
+/// 戻り値は`(トークン, DBに保存するハッシュ)`
+pub fn generate() -> (String, String) {
+    let token = Alphanumeric.sample_string(&mut rand::rng(), TOKEN_LENGTH);
+    let hash = hash(&token);
+    (token, hash)
+}
+
+/// DBに保存されたハッシュと比較するためのハッシュを計算する
+pub fn hash(token: &str) -> String {
+    digest(token)
+}
@@ -0,0 +1,446 @@
+use crate::config;
+use crate::entities::{totp_recovery_codes, user_totp, users};
+use crate::util::token;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use rand::distr::{Alphanumeric, SampleString};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, NotSet, QueryFilter,
+    TransactionTrait,
+};
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// 認証アプリに表示される発行者名
+const ISSUER: &str = "Koudaisai Portal";
+const DIGITS: usize = 6;
+const STEP: i64 = 30;
+/// 時刻のずれを前後何ステップまで許容するか
+const SKEW: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+/// 暗号化されたシークレットの接頭辞。接頭辞の無いものは暗号化される前に保存された平文
+const SEALED_PREFIX: &str = "enc:";
+const NONCE_LENGTH: usize = 12;
+
+/// シークレットをDBに保存する前に暗号化し、読み出すときに復号する
+///
+/// 鍵は`web.auth.totp_encryption_key`のSHA-256。鍵が設定されていない場合は平文のまま保存する。
+pub struct SecretCipher {
+    cipher: Option<Aes256Gcm>,
+}
+
+impl SecretCipher {
+    pub fn new(key: Option<&str>) -> Self {
+        Self {
+            cipher: key.map(|key| {
+                Aes256Gcm::new_from_slice(&Sha256::digest(key.as_bytes())[..])
+                    .expect("SHA-256 digest is a valid AES-256 key")
+            }),
+        }
+    }
+
+    pub fn from_config(auth: &config::Auth) -> Self {
+        if auth.totp_encryption_key.is_none() {
+            warn!("web.auth.totp_encryption_key is not set; totp secrets are stored in plaintext");
+        }
+        Self::new(auth.totp_encryption_key.as_deref())
+    }
+
+    fn seal(&self, secret: &str) -> Result<String> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(secret.to_string());
+        };
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, secret.as_bytes())
+            .map_err(|_| anyhow!("failed to encrypt totp secret"))?;
+        Ok(format!(
+            "{}{}{}",
+            SEALED_PREFIX,
+            hex_encode(&nonce),
+            hex_encode(&ciphertext)
+        ))
+    }
+
+    fn open(&self, stored: &str) -> Result<String> {
+        let Some(sealed) = stored.strip_prefix(SEALED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+        let cipher = self
+            .cipher
+            .as_ref()
+            .ok_or_else(|| anyhow!("totp secret is encrypted but no key is configured"))?;
+        let bytes = hex_decode(sealed).ok_or_else(|| anyhow!("malformed totp secret"))?;
+        if bytes.len() < NONCE_LENGTH {
+            return Err(anyhow!("malformed totp secret"));
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+        let nonce: [u8; NONCE_LENGTH] = nonce.try_into()?;
+        let secret = cipher
+            .decrypt(&Nonce::from(nonce), ciphertext)
+            .map_err(|_| anyhow!("failed to decrypt totp secret"))?;
+        Ok(String::from_utf8(secret)?)
+    }
+
+    /// 平文で保存されており、暗号化して保存し直すべき場合true
+    fn needs_seal(&self, stored: &str) -> bool {
+        self.cipher.is_some() && !stored.starts_with(SEALED_PREFIX)
+    }
+}
+
+/// 登録を開始したときに認証アプリへ渡す値
+/// * `secret`: Base32でエンコードされたシークレット(手入力用)
+/// * `otpauth_url`: QRコードにする`otpauth://`のURI
+pub struct Enrollment {
+    pub secret: String,
+    pub otpauth_url: String,
+}
+
+pub enum Confirmation {
+    /// 有効化した。発行したリカバリーコードを含む
+    Confirmed(Vec<String>),
+    NotEnrolled,
+    AlreadyEnabled,
+    InvalidCode,
+}
+
+/// 新しいシークレットを生成して登録を開始する
+///
+/// 有効化されていない登録がある場合は置き換える。既に有効化されている場合は`None`
+pub async fn begin_enrollment(
+    db: &DatabaseConnection,
+    cipher: &SecretCipher,
+    user: &users::Model,
+) -> Result<Option<Enrollment>> {
+    if is_enabled(db, user.id).await? {
+        return Ok(None);
+    }
+    let secret = match Secret::generate_secret().to_encoded() {
+        Secret::Encoded(secret) => secret,
+        Secret::Raw(_) => unreachable!(),
+    };
+    let otpauth_url = build(&secret, &user.m_address)?.get_url();
+    user_totp::Entity::insert(user_totp::ActiveModel {
+        user_id: Set(user.id),
+        created_at: NotSet,
+        updated_at: NotSet,
+        secret: Set(cipher.seal(&secret)?),
+        confirmed_at: Set(None),
+        last_used_step: Set(None),
+    })
+    .on_conflict(
+        OnConflict::column(user_totp::Column::UserId)
+            .update_columns([
+                user_totp::Column::Secret,
+                user_totp::Column::ConfirmedAt,
+                user_totp::Column::LastUsedStep,
+            ])
+            .to_owned(),
+    )
+    .exec(db)
+    .await?;
+    Ok(Some(Enrollment {
+        secret,
+        otpauth_url,
+    }))
+}
+
+/// 認証アプリが生成したコードを確認して二要素認証を有効化し、リカバリーコードを発行する
+pub async fn confirm_enrollment(
+    db: &DatabaseConnection,
+    cipher: &SecretCipher,
+    user: &users::Model,
+    code: &str,
+) -> Result<Confirmation> {
+    let model = match user_totp::Entity::find_by_id(user.id).one(db).await? {
+        Some(model) => model,
+        None => return Ok(Confirmation::NotEnrolled),
+    };
+    if model.confirmed_at.is_some() {
+        return Ok(Confirmation::AlreadyEnabled);
+    }
+    let totp = build(&cipher.open(&model.secret)?, &user.m_address)?;
+    let step = match matched_step(&totp, code, None, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => return Ok(Confirmation::InvalidCode),
+    };
+
+    let txn = db.begin().await?;
+    let result = user_totp::Entity::update_many()
+        .col_expr(user_totp::Column::ConfirmedAt, Expr::value(Utc::now()))
+        .col_expr(user_totp::Column::LastUsedStep, Expr::value(step))
+        .filter(user_totp::Column::UserId.eq(user.id))
+        .filter(user_totp::Column::ConfirmedAt.is_null())
+        .exec(&txn)
+        .await?;
+    if result.rows_affected == 0 {
+        // 同じユーザーの確認が並行して行われた
+        txn.rollback().await?;
+        return Ok(Confirmation::AlreadyEnabled);
+    }
+    let recovery_codes = regenerate_recovery_codes(&txn, user.id).await?;
+    txn.commit().await?;
+    info!("totp enabled for {}", user.id);
+    Ok(Confirmation::Confirmed(recovery_codes))
+}
+
+/// 二要素認証が有効化されている場合true
+pub async fn is_enabled<C: ConnectionTrait>(db: &C, user_id: Uuid) -> Result<bool> {
+    Ok(user_totp::Entity::find_by_id(user_id)
+        .filter(user_totp::Column::ConfirmedAt.is_not_null())
+        .one(db)
+        .await?
+        .is_some())
+}
+
+/// 6桁のコード、またはリカバリーコードを検証する
+///
+/// 一度使われたコード(同じ時間ステップ以前のコード、使用済みのリカバリーコード)は受け付けない。
+pub async fn verify(
+    db: &DatabaseConnection,
+    cipher: &SecretCipher,
+    user: &users::Model,
+    code: &str,
+) -> Result<bool> {
+    let model = match user_totp::Entity::find_by_id(user.id)
+        .filter(user_totp::Column::ConfirmedAt.is_not_null())
+        .one(db)
+        .await?
+    {
+        Some(model) => model,
+        None => return Ok(false),
+    };
+
+    let code = code.trim();
+    if code.len() != DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return consume_recovery_code(db, user.id, code).await;
+    }
+
+    let secret = cipher.open(&model.secret)?;
+    let totp = build(&secret, &user.m_address)?;
+    let step = match matched_step(&totp, code, model.last_used_step, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+    let mut update = user_totp::Entity::update_many()
+        .col_expr(user_totp::Column::LastUsedStep, Expr::value(step));
+    if cipher.needs_seal(&model.secret) {
+        // 暗号化される前に登録されたシークレットを暗号化して保存し直す
+        update = update.col_expr(
+            user_totp::Column::Secret,
+            Expr::value(cipher.seal(&secret)?),
+        );
+    }
+    // 同じコードによる検証が並行して行われた場合に一方だけ成功させる
+    let result = update
+        .filter(user_totp::Column::UserId.eq(user.id))
+        .filter(
+            user_totp::Column::LastUsedStep
+                .is_null()
+                .or(user_totp::Column::LastUsedStep.lt(step)),
+        )
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected == 1)
+}
+
+/// リカバリーコードを再発行する。以前のリカバリーコードは無効になる
+pub async fn regenerate_recovery_codes<C: ConnectionTrait>(
+    db: &C,
+    user_id: Uuid,
+) -> Result<Vec<String>> {
+    totp_recovery_codes::Entity::delete_many()
+        .filter(totp_recovery_codes::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+    let codes = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            Alphanumeric
+                .sample_string(&mut rand::rng(), RECOVERY_CODE_LENGTH)
+                .to_lowercase()
+        })
+        .collect::<Vec<_>>();
+    totp_recovery_codes::Entity::insert_many(codes.iter().map(|code| {
+        totp_recovery_codes::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            created_at: NotSet,
+            user_id: Set(user_id),
+            code_hash: Set(token::hash(code)),
+            used_at: NotSet,
+        }
+    }))
+    .exec(db)
+    .await?;
+    Ok(codes)
+}
+
+/// 二要素認証を無効化し、シークレットとリカバリーコードを削除する。登録されていなかった場合false
+pub async fn disable(db: &DatabaseConnection, user_id: Uuid) -> Result<bool> {
+    let txn = db.begin().await?;
+    totp_recovery_codes::Entity::delete_many()
+        .filter(totp_recovery_codes::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await?;
+    let result = user_totp::Entity::delete_by_id(user_id).exec(&txn).await?;
+    txn.commit().await?;
+    Ok(result.rows_affected > 0)
+}
+
+async fn consume_recovery_code(db: &DatabaseConnection, user_id: Uuid, code: &str) -> Result<bool> {
+    let result = totp_recovery_codes::Entity::update_many()
+        .col_expr(totp_recovery_codes::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(totp_recovery_codes::Column::UserId.eq(user_id))
+        .filter(totp_recovery_codes::Column::CodeHash.eq(token::hash(&code.to_lowercase())))
+        .filter(totp_recovery_codes::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+    if result.rows_affected > 0 {
+        debug!("recovery code used by {}", user_id);
+    }
+    Ok(result.rows_affected > 0)
+}
+
+fn build(secret: &str, account_name: &str) -> Result<TOTP> {
+    Ok(TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP as u64,
+        Secret::Encoded(secret.to_string()).to_bytes()?,
+        Some(ISSUER.to_string()),
+        account_name.to_string(),
+    )?)
+}
+
+/// 時刻`now`(UNIX時間)において`code`が一致した時間ステップを返す。`last_used_step`以前のステップとは一致させない
+fn matched_step(totp: &TOTP, code: &str, last_used_step: Option<i64>, now: i64) -> Option<i64> {
+    let current = now / STEP;
+    (current - SKEW..=current + SKEW)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| totp.check(code, (*step * STEP) as u64))
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 6238 付録Bのシークレット(`12345678901234567890`)のBase32
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn rfc_totp() -> TOTP {
+        build(RFC_SECRET, "paul.j.3858@m.isct.ac.jp").unwrap()
+    }
+
+    #[test]
+    fn matches_rfc6238_sha1_vectors() {
+        // 8桁の参照値の下6桁
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        let totp = rfc_totp();
+        for (time, code) in vectors {
+            assert_eq!(
+                matched_step(&totp, code, None, time),
+                Some(time / STEP),
+                "T = {}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_codes_within_skew() {
+        let totp = rfc_totp();
+        let now = 1234567890;
+        let current = now / STEP;
+        for step in [current - 1, current, current + 1] {
+            let code = totp.generate((step * STEP) as u64);
+            assert_eq!(matched_step(&totp, &code, None, now), Some(step));
+        }
+        for step in [current - 2, current + 2] {
+            let code = totp.generate((step * STEP) as u64);
+            assert_eq!(matched_step(&totp, &code, None, now), None);
+        }
+    }
+
+    #[test]
+    fn rejects_replayed_steps() {
+        let totp = rfc_totp();
+        let now = 1234567890;
+        let current = now / STEP;
+        let code = totp.generate(now as u64);
+        assert_eq!(matched_step(&totp, &code, Some(current), now), None);
+        assert_eq!(matched_step(&totp, &code, Some(current + 1), now), None);
+        assert_eq!(
+            matched_step(&totp, &code, Some(current - 1), now),
+            Some(current)
+        );
+
+        // 前のステップのコードは、より新しいステップが使われた後は受け付けない
+        let previous = totp.generate(((current - 1) * STEP) as u64);
+        assert_eq!(matched_step(&totp, &previous, Some(current), now), None);
+    }
+
+    #[test]
+    fn rejects_wrong_code() {
+        assert_eq!(matched_step(&rfc_totp(), "000000", None, 59), None);
+    }
+
+    #[test]
+    fn seals_and_opens_secret() {
+        let cipher = SecretCipher::new(Some("key"));
+        let sealed = cipher.seal(RFC_SECRET).unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert!(!sealed.contains(RFC_SECRET));
+        assert_ne!(sealed, cipher.seal(RFC_SECRET).unwrap());
+        assert_eq!(cipher.open(&sealed).unwrap(), RFC_SECRET);
+        assert!(!cipher.needs_seal(&sealed));
+    }
+
+    #[test]
+    fn opens_plaintext_secret_stored_before_encryption() {
+        let cipher = SecretCipher::new(Some("key"));
+        assert_eq!(cipher.open(RFC_SECRET).unwrap(), RFC_SECRET);
+        assert!(cipher.needs_seal(RFC_SECRET));
+    }
+
+    #[test]
+    fn rejects_secret_sealed_with_another_key() {
+        let sealed = SecretCipher::new(Some("key")).seal(RFC_SECRET).unwrap();
+        assert!(SecretCipher::new(Some("other")).open(&sealed).is_err());
+        assert!(SecretCipher::new(None).open(&sealed).is_err());
+    }
+
+    #[test]
+    fn stores_plaintext_without_key() {
+        let cipher = SecretCipher::new(None);
+        assert_eq!(cipher.seal(RFC_SECRET).unwrap(), RFC_SECRET);
+        assert!(!cipher.needs_seal(RFC_SECRET));
+    }
+}
@@ -0,0 +1,167 @@
+//! `/api/v1`のアクセス制御の結合テスト
+//!
+//! `auth_v1`と同じく起動済みのサーバーと有効化済みの参加団体責任者アカウントが必要なため、
+//! 通常の`cargo test`では実行されない。
+//! ```sh
+//! KOUDAISAI_PORTAL_TEST_BASE_URL=http://localhost:8080 \
+//! KOUDAISAI_PORTAL_TEST_M_ADDRESS=paul.j.3858@m.isct.ac.jp \
+//! KOUDAISAI_PORTAL_TEST_PASSWORD=password \
+//! cargo test -- --ignored
+//! ```
+
+use http::{Method, StatusCode};
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::env;
+
+struct TestEnv {
+    client: Client,
+    base_url: String,
+}
+
+impl TestEnv {
+    fn new() -> Self {
+        Self {
+            client: Client::new(),
+            base_url: env::var("KOUDAISAI_PORTAL_TEST_BASE_URL")
+                .unwrap_or("http://localhost:8080".to_string()),
+        }
+    }
+
+    async fn login(&self) -> String {
+        let response = self
+            .client
+            .post(format!("{}/auth/v1/login", self.base_url))
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(
+                json!({
+                    "m_address": env::var("KOUDAISAI_PORTAL_TEST_M_ADDRESS")
+                        .expect("KOUDAISAI_PORTAL_TEST_M_ADDRESS is not set"),
+                    "password": env::var("KOUDAISAI_PORTAL_TEST_PASSWORD")
+                        .expect("KOUDAISAI_PORTAL_TEST_PASSWORD is not set"),
+                })
+                .to_string(),
+            )
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        body["access_token"].as_str().unwrap().to_string()
+    }
+
+    /// problem+jsonのエラーが返ることを確認し、`code`を返す
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        access_token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Option<String>) {
+        let mut request = self
+            .client
+            .request(method, format!("{}/api/v1{}", self.base_url, path));
+        if let Some(access_token) = access_token {
+            request = request.bearer_auth(access_token);
+        }
+        if let Some(body) = body {
+            request = request
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string());
+        }
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        if status.is_success() {
+            return (status, None);
+        }
+        assert_eq!(
+            response
+                .headers()
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok()),
+            Some("application/problem+json")
+        );
+        let problem: Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(problem["status"], status.as_u16());
+        (status, problem["code"].as_str().map(str::to_string))
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn admin_routes_are_forbidden_for_anonymous_and_exhibitor_members() {
+    let env = TestEnv::new();
+    let access_token = env.login().await;
+
+    for (method, path) in [
+        (Method::GET, "/exhibitors"),
+        (Method::GET, "/users/lockouts"),
+        (Method::DELETE, "/exhibitors/T-001/sessions"),
+    ] {
+        for token in [None, Some(access_token.as_str())] {
+            let (status, code) = env.send(method.clone(), path, token, None).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{} {}", method, path);
+            assert_eq!(code.as_deref(), Some("forbidden"));
+        }
+    }
+
+    let (status, code) = env
+        .send(
+            Method::POST,
+            "/forms",
+            Some(&access_token),
+            Some(json!({"info": {}, "items": [], "access_control": {"roles": []}})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(code.as_deref(), Some("forbidden"));
+}
+
+#[tokio::test]
+#[ignore]
+async fn exhibitor_member_routes_are_forbidden_for_anonymous() {
+    let env = TestEnv::new();
+
+    let (status, code) = env.send(Method::POST, "/me/totp", None, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(code.as_deref(), Some("forbidden"));
+
+    let (status, _) = env.send(Method::GET, "/exhibitors/T-001", None, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+#[ignore]
+async fn other_exhibitor_is_not_found_for_exhibitor_member() {
+    let env = TestEnv::new();
+    let access_token = env.login().await;
+
+    let (status, code) = env
+        .send(
+            Method::GET,
+            "/exhibitors/NOT-A-MEMBER",
+            Some(&access_token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(code.as_deref(), Some("not_found"));
+}
+
+#[tokio::test]
+#[ignore]
+async fn invalid_payload_returns_validation_error() {
+    let env = TestEnv::new();
+    let access_token = env.login().await;
+
+    let (status, code) = env
+        .send(
+            Method::POST,
+            "/me/totp/confirm",
+            Some(&access_token),
+            Some(json!({"code": 123456})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(code.as_deref(), Some("validation_failed"));
+}
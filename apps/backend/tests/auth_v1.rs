@@ -0,0 +1,325 @@
+//! `/auth/v1`の結合テスト
+//!
+//! 起動済みのサーバーに対してリクエストを送る。有効化済みのアカウントが必要なため、通常の`cargo test`では実行されない。
+//! ```sh
+//! KOUDAISAI_PORTAL_TEST_BASE_URL=http://localhost:8080 \
+//! KOUDAISAI_PORTAL_TEST_M_ADDRESS=paul.j.3858@m.isct.ac.jp \
+//! KOUDAISAI_PORTAL_TEST_PASSWORD=password \
+//! KOUDAISAI_PORTAL_TEST_MAIL_DIR=./mails \
+//! cargo test -- --ignored
+//! ```
+//! `KOUDAISAI_PORTAL_TEST_MAIL_DIR`にはサーバーの`mail.transport`に設定した`File`の`path`を指定する。
+
+use http::StatusCode;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::sleep;
+
+struct TestEnv {
+    client: Client,
+    base_url: String,
+    m_address: String,
+    password: String,
+}
+
+impl TestEnv {
+    fn new() -> Self {
+        Self {
+            client: Client::new(),
+            base_url: env::var("KOUDAISAI_PORTAL_TEST_BASE_URL")
+                .unwrap_or("http://localhost:8080".to_string()),
+            m_address: env::var("KOUDAISAI_PORTAL_TEST_M_ADDRESS")
+                .expect("KOUDAISAI_PORTAL_TEST_M_ADDRESS is not set"),
+            password: env::var("KOUDAISAI_PORTAL_TEST_PASSWORD")
+                .expect("KOUDAISAI_PORTAL_TEST_PASSWORD is not set"),
+        }
+    }
+
+    /// レート制限に掛かった場合は`retry-after`秒待ってから再試行する
+    async fn post(&self, path: &str, body: Value) -> (StatusCode, Option<Value>) {
+        loop {
+            let response = self
+                .client
+                .post(format!("{}/auth/v1{}", self.base_url, path))
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(body.to_string())
+                .send()
+                .await
+                .unwrap();
+            let status = response.status();
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let retry_after = response
+                    .headers()
+                    .get(http::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(10);
+                sleep(Duration::from_secs(retry_after)).await;
+                continue;
+            }
+            let body = serde_json::from_str(&response.text().await.unwrap()).ok();
+            return (status, body);
+        }
+    }
+
+    /// アクセストークンを付けてリクエストを送る
+    async fn send_with_token(
+        &self,
+        method: http::Method,
+        path: &str,
+        access_token: &str,
+    ) -> (StatusCode, Option<Value>) {
+        let response = self
+            .client
+            .request(method, format!("{}/auth/v1{}", self.base_url, path))
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = serde_json::from_str(&response.text().await.unwrap()).ok();
+        (status, body)
+    }
+
+    async fn login(&self) -> Value {
+        let (status, body) = self
+            .post(
+                "/login",
+                json!({"m_address": self.m_address, "password": self.password}),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        body.unwrap()
+    }
+
+    /// `FileTransport`が書き出したメールのうち、`known`に含まれない自分宛てのものを待つ
+    ///
+    /// メールは送信キューを通して送られるため、`mail.outbox.poll_interval`より長く待つ
+    async fn wait_for_mail(&self, known: &HashSet<PathBuf>) -> Value {
+        let mail_dir = env::var("KOUDAISAI_PORTAL_TEST_MAIL_DIR")
+            .expect("KOUDAISAI_PORTAL_TEST_MAIL_DIR is not set");
+        for _ in 0..300 {
+            for path in mail_files(&mail_dir) {
+                if known.contains(&path) {
+                    continue;
+                }
+                let mail: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+                if mail["to"] == self.m_address.as_str() {
+                    return mail;
+                }
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        panic!("mail was not delivered");
+    }
+}
+
+fn mail_files(mail_dir: &str) -> HashSet<PathBuf> {
+    match std::fs::read_dir(mail_dir) {
+        Ok(entries) => entries.map(|entry| entry.unwrap().path()).collect(),
+        Err(_) => HashSet::new(),
+    }
+}
+
+/// メール本文のリンクからトークンを取り出す
+fn extract_token(body: &str) -> String {
+    let start = body.find("token=").expect("token not found in mail") + "token=".len();
+    body[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect()
+}
+
+#[tokio::test]
+#[ignore]
+async fn login_refresh_revoke() {
+    let env = TestEnv::new();
+
+    // login
+    let tokens = env.login().await;
+    let refresh_token = tokens["refresh_token"].as_str().unwrap();
+    assert!(tokens["access_token"].is_string());
+
+    // refresh
+    let (status, body) = env
+        .post("/refresh", json!({"refresh_token": refresh_token}))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let tokens = body.unwrap();
+    let refresh_token = tokens["refresh_token"].as_str().unwrap();
+    assert!(tokens["access_token"].is_string());
+
+    // revoke
+    let (status, _) = env
+        .post("/revoke", json!({"refresh_token": refresh_token}))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // revoke済みのトークンではrefreshできない
+    let (status, _) = env
+        .post("/refresh", json!({"refresh_token": refresh_token}))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore]
+async fn reuse_of_rotated_refresh_token_revokes_family() {
+    let env = TestEnv::new();
+
+    let tokens = env.login().await;
+    let first = tokens["refresh_token"].as_str().unwrap();
+
+    // ローテーション
+    let (status, body) = env.post("/refresh", json!({"refresh_token": first})).await;
+    assert_eq!(status, StatusCode::OK);
+    let tokens = body.unwrap();
+    let second = tokens["refresh_token"].as_str().unwrap();
+    assert_ne!(first, second);
+
+    // ローテーション済みのトークンを再利用
+    let (status, _) = env.post("/refresh", json!({"refresh_token": first})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // ファミリー全体が失効している
+    let (status, _) = env.post("/refresh", json!({"refresh_token": second})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore]
+async fn refresh_with_access_token_fails() {
+    let env = TestEnv::new();
+
+    let tokens = env.login().await;
+    let (status, _) = env
+        .post("/refresh", json!({"refresh_token": tokens["access_token"]}))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore]
+async fn refresh_with_malformed_token_fails() {
+    let env = TestEnv::new();
+
+    let (status, _) = env
+        .post("/refresh", json!({"refresh_token": "not.a.jwt"}))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[ignore]
+async fn list_and_revoke_session() {
+    let env = TestEnv::new();
+
+    let tokens = env.login().await;
+    let access_token = tokens["access_token"].as_str().unwrap();
+
+    // ログインしたセッションが含まれている
+    let (status, body) = env
+        .send_with_token(http::Method::GET, "/sessions", access_token)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let sessions = body.unwrap();
+    let current = sessions
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|session| session["current"] == true)
+        .expect("current session not found");
+    let session_id = current["id"].as_str().unwrap();
+
+    // 失効させる
+    let (status, _) = env
+        .send_with_token(
+            http::Method::DELETE,
+            &format!("/sessions/{}", session_id),
+            access_token,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // 失効したセッションのリフレッシュトークンではrefreshできない
+    let (status, _) = env
+        .post(
+            "/refresh",
+            json!({"refresh_token": tokens["refresh_token"]}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // 既に失効している
+    let (status, _) = env
+        .send_with_token(
+            http::Method::DELETE,
+            &format!("/sessions/{}", session_id),
+            access_token,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[ignore]
+async fn forgot_and_complete_password() {
+    let env = TestEnv::new();
+    let tokens = env.login().await;
+    let known = mail_files(&env::var("KOUDAISAI_PORTAL_TEST_MAIL_DIR").unwrap_or_default());
+
+    // 再設定メールの送信
+    let (status, _) = env
+        .post("/password/forgot", json!({"m_address": env.m_address}))
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let mail = env.wait_for_mail(&known).await;
+    let token = extract_token(mail["body"].as_str().unwrap());
+
+    // 同じパスワードに再設定
+    let (status, _) = env
+        .post(
+            "/password/complete",
+            json!({"token": token, "new_password": env.password}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // トークンは一度しか使えない
+    let (status, _) = env
+        .post(
+            "/password/complete",
+            json!({"token": token, "new_password": env.password}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // 再設定前に発行されたリフレッシュトークンは失効している
+    let (status, _) = env
+        .post(
+            "/refresh",
+            json!({"refresh_token": tokens["refresh_token"]}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    env.login().await;
+}
+
+#[tokio::test]
+#[ignore]
+async fn forgot_password_for_unknown_user_is_accepted() {
+    let env = TestEnv::new();
+
+    let (status, _) = env
+        .post(
+            "/password/forgot",
+            json!({"m_address": "unknown.u.0000@m.isct.ac.jp"}),
+        )
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+}